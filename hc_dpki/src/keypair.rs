@@ -34,11 +34,11 @@ impl Keypair {
     /// @param {SecBuf} seed - the seed buffer
     pub fn new_from_seed(seed: &mut SecBuf) -> Result<Self, HolochainError> {
         let mut sign_public_key = SecBuf::with_insecure(sign::PUBLICKEYBYTES);
-        let mut sign_secret_key = SecBuf::with_secure(sign::SECRETKEYBYTES);
+        let mut sign_secret_key = SecBuf::try_with_secure(sign::SECRETKEYBYTES)?;
         let mut enc_public_key = SecBuf::with_insecure(kx::PUBLICKEYBYTES);
-        let mut enc_secret_key = SecBuf::with_secure(kx::SECRETKEYBYTES);
+        let mut enc_secret_key = SecBuf::try_with_secure(kx::SECRETKEYBYTES)?;
 
-        sign::seed_keypair(&mut sign_public_key, &mut sign_secret_key, seed)?;
+        sign::seed_keypair(seed, &mut sign_public_key, &mut sign_secret_key)?;
         kx::seed_keypair(seed, &mut enc_public_key, &mut enc_secret_key)?;
 
         Ok(Keypair {
@@ -69,7 +69,7 @@ impl Keypair {
         let bundle_type: String = "hcKeypair".to_string();
         let corrected_pub_keys = KeyBuffer::with_corrected(&self.pub_keys)?;
 
-        let mut key_buf = SecBuf::with_secure(BUNDLE_DATA_LEN);
+        let mut key_buf = SecBuf::try_with_secure(BUNDLE_DATA_LEN)?;
 
         let mut offset: usize = 0;
 
@@ -115,10 +115,10 @@ impl Keypair {
         let bundle_decoded = base64::decode(&bundle.data)?;
         let bundle_string = str::from_utf8(&bundle_decoded).unwrap();
         let data: bundle::ReturnBundleData = json::decode(&bundle_string).unwrap();
-        let mut decrypted_data = SecBuf::with_secure(BUNDLE_DATA_LEN);
+        let mut decrypted_data = SecBuf::try_with_secure(BUNDLE_DATA_LEN)?;
         util::pw_dec(&data, passphrase, &mut decrypted_data, config)?;
-        let mut sign_priv = SecBuf::with_secure(SIGNATURESIZE);
-        let mut enc_priv = SecBuf::with_secure(32);
+        let mut sign_priv = SecBuf::try_with_secure(SIGNATURESIZE)?;
+        let mut enc_priv = SecBuf::try_with_secure(32)?;
 
         let pub_keys = {
             let decrypted_data = decrypted_data.read_lock();
@@ -173,8 +173,8 @@ impl Keypair {
         let mut enc_pub = SecBuf::with_insecure(kx::PUBLICKEYBYTES);
 
         util::decode_id(pub_keys, &mut sign_pub, &mut enc_pub)?;
-        let verified: i32 = sign::verify(signature, data, &mut sign_pub);
-        Ok(verified)
+        let verified = sign::verify(signature, data, &mut sign_pub)?;
+        Ok(if verified { 0 } else { -1 })
     }
 
     // /// encrypt arbitrary data to be readale by potentially multiple recipients
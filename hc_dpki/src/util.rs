@@ -42,7 +42,7 @@ pub fn pw_enc(
     passphrase: &mut SecBuf,
     config: Option<PwHashConfig>,
 ) -> Result<bundle::ReturnBundleData, HolochainError> {
-    let mut secret = SecBuf::with_secure(kx::SESSIONKEYBYTES);
+    let mut secret = SecBuf::try_with_secure(kx::SESSIONKEYBYTES)?;
     let mut salt = SecBuf::with_insecure(pwhash::SALTBYTES);
     holochain_sodium::random::random_secbuf(&mut salt);
     let mut nonce = SecBuf::with_insecure(aead::NONCEBYTES);
@@ -75,7 +75,7 @@ pub fn pw_dec(
     decrypted_data: &mut SecBuf,
     config: Option<PwHashConfig>,
 ) -> Result<(), HolochainError> {
-    let mut secret = SecBuf::with_secure(kx::SESSIONKEYBYTES);
+    let mut secret = SecBuf::try_with_secure(kx::SESSIONKEYBYTES)?;
     let mut salt = SecBuf::with_insecure(pwhash::SALTBYTES);
     convert_vec_to_secbuf(&bundle.salt, &mut salt);
     let mut nonce = SecBuf::with_insecure(bundle.nonce.len());
@@ -192,7 +192,7 @@ mod tests {
 
         let data = data.read_lock();
         let dec_mess = dec_mess.read_lock();
-        assert_eq!(format!("{:?}", *dec_mess), format!("{:?}", *data));
+        assert_eq!(dec_mess.debug_unredacted(), data.debug_unredacted());
     }
 
     #[test]
@@ -207,7 +207,7 @@ mod tests {
         let mut salt = SecBuf::with_insecure(pwhash::SALTBYTES);
         pw_hash(&mut password, &mut salt, &mut pw2_hash, TEST_CONFIG).unwrap();
         let pw2_hash = pw2_hash.read_lock();
-        assert_eq!("[134, 156, 170, 171, 184, 19, 40, 158, 64, 227, 105, 252, 59, 175, 119, 226, 77, 238, 49, 61, 27, 174, 47, 246, 179, 168, 88, 200, 65, 11, 14, 159]",  format!("{:?}", *pw2_hash));
+        assert_eq!("[134, 156, 170, 171, 184, 19, 40, 158, 64, 227, 105, 252, 59, 175, 119, 226, 77, 238, 49, 61, 27, 174, 47, 246, 179, 168, 88, 200, 65, 11, 14, 159]",  pw2_hash.debug_unredacted());
     }
 
     #[test]
@@ -229,7 +229,7 @@ mod tests {
         let enc_pub = enc_pub.read_lock();
         let sign_pub_dec = sign_pub_dec.read_lock();
         let enc_pub_dec = enc_pub_dec.read_lock();
-        assert_eq!(format!("{:?}", *sign_pub), format!("{:?}", *sign_pub_dec));
-        assert_eq!(format!("{:?}", *enc_pub), format!("{:?}", *enc_pub_dec));
+        assert_eq!(sign_pub.debug_unredacted(), sign_pub_dec.debug_unredacted());
+        assert_eq!(enc_pub.debug_unredacted(), enc_pub_dec.debug_unredacted());
     }
 }
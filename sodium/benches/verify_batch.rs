@@ -0,0 +1,66 @@
+//! compares verifying 1000 signatures one call at a time against one verify_batch call
+
+#[macro_use]
+extern crate criterion;
+extern crate holochain_sodium;
+
+use criterion::Criterion;
+use holochain_sodium::{
+    random::random_secbuf,
+    secbuf::SecBuf,
+    sign::{self, VerifyItem},
+};
+
+const COUNT: usize = 1000;
+
+/// (signatures, messages, public keys)
+fn signed_messages() -> (Vec<SecBuf>, Vec<SecBuf>, Vec<SecBuf>) {
+    let mut signatures = Vec::with_capacity(COUNT);
+    let mut messages = Vec::with_capacity(COUNT);
+    let mut public_keys = Vec::with_capacity(COUNT);
+    for _ in 0..COUNT {
+        let (pk, mut sk) = sign::keypair().unwrap();
+        let mut message = SecBuf::with_insecure(256);
+        random_secbuf(&mut message);
+        let mut signature = SecBuf::with_insecure(sign::BYTES);
+        sign::sign(&mut message, &mut sk, &mut signature).unwrap();
+        signatures.push(signature);
+        messages.push(message);
+        public_keys.push(pk);
+    }
+    (signatures, messages, public_keys)
+}
+
+fn bench_verify(c: &mut Criterion) {
+    c.bench_function("verify 1000 singly", |b| {
+        let (mut signatures, mut messages, mut public_keys) = signed_messages();
+        b.iter(|| {
+            for i in 0..COUNT {
+                assert!(
+                    sign::verify(&mut signatures[i], &mut messages[i], &mut public_keys[i])
+                        .unwrap()
+                );
+            }
+        })
+    });
+
+    c.bench_function("verify 1000 batched", |b| {
+        let (mut signatures, mut messages, mut public_keys) = signed_messages();
+        b.iter(|| {
+            let mut items: Vec<VerifyItem> = signatures
+                .iter_mut()
+                .zip(messages.iter_mut())
+                .zip(public_keys.iter_mut())
+                .map(|((signature, message), public_key)| VerifyItem {
+                    signature,
+                    message,
+                    public_key,
+                })
+                .collect();
+            assert!(sign::verify_batch(&mut items).unwrap().iter().all(|ok| *ok));
+        })
+    });
+}
+
+criterion_group!(benches, bench_verify);
+criterion_main!(benches);
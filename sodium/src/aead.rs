@@ -3,6 +3,11 @@
 use super::{check_init, secbuf::SecBuf};
 use crate::error::SodiumError;
 
+/// for payloads too large to encrypt in one piece
+pub use crate::secretstream::{StreamDecryptor, StreamEncryptor, StreamTag};
+
+/// Used to set the size of the secret var in the enc fns
+pub const KEYBYTES: usize = rust_sodium_sys::crypto_aead_xchacha20poly1305_ietf_KEYBYTES as usize;
 /// Used to set the size of nonce var in the enc fns
 pub const NONCEBYTES: usize =
     rust_sodium_sys::crypto_aead_xchacha20poly1305_ietf_NPUBBYTES as usize;
@@ -11,6 +16,35 @@ pub const NONCEBYTES: usize =
 /// Note: look at the test cases to see how it is used
 pub const ABYTES: usize = rust_sodium_sys::crypto_aead_xchacha20poly1305_ietf_ABYTES as usize;
 
+/// validate the sizes shared by enc and dec, `message_len` is the plaintext length
+fn check_sizes(
+    secret: usize,
+    nonce: usize,
+    message_len: usize,
+    cipher: usize,
+) -> Result<(), SodiumError> {
+    if secret != KEYBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "secret must be a Buffer of length: {}, got {}.",
+            KEYBYTES, secret
+        )));
+    }
+    if nonce != NONCEBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "nonce must be a Buffer of length: {}, got {}.",
+            NONCEBYTES, nonce
+        )));
+    }
+    if cipher != message_len + ABYTES {
+        return Err(SodiumError::LengthMismatch(format!(
+            "cipher must be a Buffer of length: {} (message + ABYTES), got {}.",
+            message_len + ABYTES,
+            cipher
+        )));
+    }
+    Ok(())
+}
+
 /// Generate symmetric cipher text given a message, secret, and optional auth data
 ///
 /// @param {SecBuf} message - data to encrypt
@@ -22,6 +56,7 @@ pub const ABYTES: usize = rust_sodium_sys::crypto_aead_xchacha20poly1305_ietf_AB
 /// @param {SecBuf} nonce - sometimes called initialization vector (iv)
 ///
 /// @param {SecBuf} cipher - Empty Buffer (needed when you want to Decrypt the meassage)
+/// of length message + ABYTES
 pub fn enc(
     message: &mut SecBuf,
     secret: &mut SecBuf,
@@ -30,6 +65,7 @@ pub fn enc(
     cipher: &mut SecBuf,
 ) -> Result<(), SodiumError> {
     check_init();
+    check_sizes(secret.len(), nonce.len(), message.len(), cipher.len())?;
     let my_adata_locker;
     let mut my_adata = std::ptr::null();
     let mut my_ad_len = 0 as libc::c_ulonglong;
@@ -72,6 +108,9 @@ pub fn enc(
 /// @param {Buffer} nonce - sometimes called initialization vector (iv)
 ///
 /// @param {Buffer} cipher - the cipher text
+///
+/// if the cipher text, adata or nonce don't authenticate, returns
+/// CouldNotDecrypt and leaves decrypted_message zeroed
 pub fn dec(
     decrypted_message: &mut SecBuf,
     secret: &mut SecBuf,
@@ -80,6 +119,12 @@ pub fn dec(
     cipher: &mut SecBuf,
 ) -> Result<(), SodiumError> {
     check_init();
+    check_sizes(
+        secret.len(),
+        nonce.len(),
+        decrypted_message.len(),
+        cipher.len(),
+    )?;
     let my_adata_locker;
     let mut my_adata = std::ptr::null();
     let mut my_ad_len = 0 as libc::c_ulonglong;
@@ -95,7 +140,7 @@ pub fn dec(
     let nonce = nonce.read_lock();
     let secret = secret.read_lock();
 
-    unsafe {
+    let ret = unsafe {
         rust_sodium_sys::crypto_aead_xchacha20poly1305_ietf_decrypt(
             raw_ptr_char!(decrypted_message),
             std::ptr::null_mut(),
@@ -106,11 +151,156 @@ pub fn dec(
             my_ad_len,
             raw_ptr_char_immut!(nonce),
             raw_ptr_char_immut!(secret),
-        );
+        )
+    };
+    if ret != 0 {
+        unsafe {
+            rust_sodium_sys::sodium_memzero(
+                raw_ptr_void!(decrypted_message),
+                decrypted_message.len(),
+            );
+        }
+        return Err(SodiumError::CouldNotDecrypt);
     }
     Ok(())
 }
 
+/// the bytes `NonceCounter::snapshot` adds ahead of the nonce
+const SNAPSHOT_HEADER: usize = 2;
+
+/// Hands out NONCEBYTES nonces that never repeat
+///
+/// the nonce is a fixed prefix followed by a `counter_len` byte suffix,
+/// a little-endian counter starting at zero, the prefix is random with
+/// `with_random_prefix` so counters on different devices sharing a key don't collide
+pub struct NonceCounter {
+    nonce: SecBuf,
+    counter_len: usize,
+    exhausted: bool,
+}
+
+impl NonceCounter {
+    /// a counter over the last `counter_len` (1 to 8) bytes, with a zero prefix
+    pub fn new(counter_len: usize) -> Result<NonceCounter, SodiumError> {
+        NonceCounter::check_counter_len(counter_len)?;
+        let mut nonce = SecBuf::try_with_insecure(NONCEBYTES)?;
+        nonce.fill(0);
+        Ok(NonceCounter {
+            nonce,
+            counter_len,
+            exhausted: false,
+        })
+    }
+
+    /// a counter over the last `counter_len` (1 to 8) bytes, with a random prefix
+    pub fn with_random_prefix(counter_len: usize) -> Result<NonceCounter, SodiumError> {
+        let mut counter = NonceCounter::new(counter_len)?;
+        let prefix_len = counter.prefix_len();
+        crate::random::random_secbuf_range(&mut counter.nonce, 0..prefix_len)?;
+        Ok(counter)
+    }
+
+    /// the counter suffix starts right after the prefix
+    fn prefix_len(&self) -> usize {
+        NONCEBYTES - self.counter_len
+    }
+
+    fn check_counter_len(counter_len: usize) -> Result<(), SodiumError> {
+        if counter_len == 0 || counter_len > 8 {
+            return Err(SodiumError::LengthMismatch(format!(
+                "nonce counter must be 1 to 8 bytes, got {}",
+                counter_len
+            )));
+        }
+        Ok(())
+    }
+
+    /// the current counter value
+    fn count(&mut self) -> u64 {
+        let prefix_len = self.prefix_len();
+        self.nonce.peek(|b| {
+            b[prefix_len..]
+                .iter()
+                .rev()
+                .fold(0u64, |c, byte| (c << 8) | u64::from(*byte))
+        })
+    }
+
+    /// the largest counter value
+    fn max_count(&self) -> u64 {
+        std::u64::MAX >> (64 - 8 * self.counter_len)
+    }
+
+    /// return a copy of the current nonce, then advance the counter
+    ///
+    /// once the counter has handed out its largest value this returns
+    /// NonceExhausted rather than wrapping around
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<SecBuf, SodiumError> {
+        if self.exhausted {
+            return Err(SodiumError::NonceExhausted);
+        }
+        let out = self.nonce.insecure_clone()?;
+        if self.count() == self.max_count() {
+            self.exhausted = true;
+        } else {
+            check_init();
+            let prefix_len = self.prefix_len();
+            let mut nonce = self.nonce.write_lock();
+            unsafe {
+                rust_sodium_sys::sodium_increment(
+                    raw_ptr_char!(nonce).add(prefix_len),
+                    self.counter_len,
+                );
+            }
+        }
+        Ok(out)
+    }
+
+    /// how many more nonces `next` will return, saturating at u64::MAX
+    pub fn remaining(&mut self) -> u64 {
+        if self.exhausted {
+            return 0;
+        }
+        (self.max_count() - self.count()).saturating_add(1)
+    }
+
+    /// the counter state, to be persisted and handed back to `restore`
+    /// (it is not secret, but reusing an old snapshot reuses nonces)
+    pub fn snapshot(&mut self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SNAPSHOT_HEADER + NONCEBYTES);
+        out.push(self.counter_len as u8);
+        out.push(self.exhausted as u8);
+        self.nonce.peek(|b| out.extend_from_slice(b));
+        out
+    }
+
+    /// continue a counter from the bytes returned by `snapshot`
+    pub fn restore(bytes: &[u8]) -> Result<NonceCounter, SodiumError> {
+        if bytes.len() != SNAPSHOT_HEADER + NONCEBYTES {
+            return Err(SodiumError::SizeMismatch(format!(
+                "nonce counter snapshot must be {} bytes, got {}",
+                SNAPSHOT_HEADER + NONCEBYTES,
+                bytes.len()
+            )));
+        }
+        let counter_len = bytes[0] as usize;
+        NonceCounter::check_counter_len(counter_len)?;
+        let exhausted = match bytes[1] {
+            0 => false,
+            1 => true,
+            _ => return Err(SodiumError::new("bad nonce counter snapshot")),
+        };
+        let mut nonce = SecBuf::try_with_insecure(NONCEBYTES)?;
+        nonce.write(0, &bytes[SNAPSHOT_HEADER..])?;
+        Ok(NonceCounter {
+            nonce,
+            counter_len,
+            exhausted,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -127,7 +317,7 @@ mod tests {
         let mut adata = SecBuf::with_secure(16);
         random_secbuf(&mut adata);
 
-        let mut nonce = SecBuf::with_insecure(16);
+        let mut nonce = SecBuf::with_insecure(NONCEBYTES);
         random_secbuf(&mut nonce);
 
         let mut cipher = SecBuf::with_insecure(message.len() + ABYTES);
@@ -156,8 +346,8 @@ mod tests {
             let message = message.read_lock();
             let decrypted_message = decrypted_message.read_lock();
             assert_eq!(
-                format!("{:?}", *message),
-                format!("{:?}", *decrypted_message)
+                message.debug_unredacted(),
+                decrypted_message.debug_unredacted()
             );
         }
     }
@@ -186,12 +376,12 @@ mod tests {
             &mut nonce,
             &mut cipher,
         )
-        .unwrap();;
+        .unwrap();
         let message = message.read_lock();
         let decrypted_message = decrypted_message.read_lock();
         assert_eq!(
-            format!("{:?}", *message),
-            format!("{:?}", *decrypted_message)
+            message.debug_unredacted(),
+            decrypted_message.debug_unredacted()
         );
     }
     #[test]
@@ -220,21 +410,202 @@ mod tests {
             &mut nonce,
             &mut cipher,
         )
-        .unwrap();;
+        .unwrap();
         let dec_len = cip_len - ABYTES;
         let mut decrypted_message = SecBuf::with_insecure(dec_len);
-        dec(
+        match dec(
             &mut decrypted_message,
             &mut secret,
             Some(&mut adata1),
             &mut nonce,
             &mut cipher,
-        )
-        .unwrap();;
+        ) {
+            Err(SodiumError::CouldNotDecrypt) => (),
+            _ => panic!("expected CouldNotDecrypt"),
+        }
         let decrypted_message = decrypted_message.read_lock();
         assert_eq!(
             "[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]",
-            format!("{:?}", *decrypted_message)
+            decrypted_message.debug_unredacted()
         );
     }
+
+    /// (message, secret, adata, nonce, cipher) encrypted with adata
+    fn encrypted() -> (SecBuf, SecBuf, SecBuf, SecBuf, SecBuf) {
+        let mut message = SecBuf::with_secure(16);
+        random_secbuf(&mut message);
+        let mut secret = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut secret);
+        let mut adata = SecBuf::with_insecure(16);
+        random_secbuf(&mut adata);
+        let mut nonce = SecBuf::with_insecure(NONCEBYTES);
+        random_secbuf(&mut nonce);
+        let mut cipher = SecBuf::with_insecure(message.len() + ABYTES);
+        enc(
+            &mut message,
+            &mut secret,
+            Some(&mut adata),
+            &mut nonce,
+            &mut cipher,
+        )
+        .unwrap();
+        (message, secret, adata, nonce, cipher)
+    }
+
+    fn flip_first_bit(b: &mut SecBuf) {
+        let mut b = b.write_lock();
+        b[0] ^= 0x01;
+    }
+
+    fn assert_rejected(
+        secret: &mut SecBuf,
+        adata: &mut SecBuf,
+        nonce: &mut SecBuf,
+        cipher: &mut SecBuf,
+    ) {
+        let mut decrypted_message = SecBuf::with_secure(cipher.len() - ABYTES);
+        match dec(&mut decrypted_message, secret, Some(adata), nonce, cipher) {
+            Err(SodiumError::CouldNotDecrypt) => (),
+            _ => panic!("expected CouldNotDecrypt"),
+        }
+        let decrypted_message = decrypted_message.read_lock();
+        assert!(decrypted_message.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn it_should_reject_flipped_cipher_byte() {
+        let (_, mut secret, mut adata, mut nonce, mut cipher) = encrypted();
+        flip_first_bit(&mut cipher);
+        assert_rejected(&mut secret, &mut adata, &mut nonce, &mut cipher);
+    }
+
+    #[test]
+    fn it_should_reject_flipped_adata_byte() {
+        let (_, mut secret, mut adata, mut nonce, mut cipher) = encrypted();
+        flip_first_bit(&mut adata);
+        assert_rejected(&mut secret, &mut adata, &mut nonce, &mut cipher);
+    }
+
+    #[test]
+    fn it_should_reject_wrong_nonce() {
+        let (_, mut secret, mut adata, mut nonce, mut cipher) = encrypted();
+        flip_first_bit(&mut nonce);
+        assert_rejected(&mut secret, &mut adata, &mut nonce, &mut cipher);
+    }
+
+    #[test]
+    fn it_should_reject_bad_sizes() {
+        let (mut message, mut secret, _, mut nonce, _) = encrypted();
+        let mut short_nonce = SecBuf::with_insecure(16);
+        let mut short_secret = SecBuf::with_secure(16);
+        let mut cipher = SecBuf::with_insecure(message.len() + ABYTES);
+        let mut short_cipher = SecBuf::with_insecure(message.len());
+
+        let results = vec![
+            enc(
+                &mut message,
+                &mut short_secret,
+                None,
+                &mut nonce,
+                &mut cipher,
+            ),
+            enc(
+                &mut message,
+                &mut secret,
+                None,
+                &mut short_nonce,
+                &mut cipher,
+            ),
+        ];
+        for result in results {
+            match result {
+                Err(SodiumError::SizeMismatch(_)) => (),
+                _ => panic!("expected SizeMismatch"),
+            }
+        }
+        match enc(
+            &mut message,
+            &mut secret,
+            None,
+            &mut nonce,
+            &mut short_cipher,
+        ) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+
+        let mut decrypted_message = SecBuf::with_insecure(message.len() + 1);
+        match dec(
+            &mut decrypted_message,
+            &mut secret,
+            None,
+            &mut nonce,
+            &mut cipher,
+        ) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+    }
+
+    #[test]
+    fn it_should_never_repeat_nonces() {
+        let mut counter = NonceCounter::with_random_prefix(8).unwrap();
+        let mut a = counter.next().unwrap();
+        let mut b = counter.next().unwrap();
+        assert_eq!(NONCEBYTES, a.len());
+        let a = a.read_lock();
+        let b = b.read_lock();
+        assert_ne!(a.debug_unredacted(), b.debug_unredacted());
+        assert_eq!(0, a[NONCEBYTES - 8]);
+        assert_eq!(1, b[NONCEBYTES - 8]);
+        assert_eq!(a[..NONCEBYTES - 8], b[..NONCEBYTES - 8]);
+    }
+
+    #[test]
+    fn it_should_error_instead_of_wrapping() {
+        let mut counter = NonceCounter::new(1).unwrap();
+        assert_eq!(256, counter.remaining());
+        for i in 0..256 {
+            let mut nonce = counter.next().unwrap();
+            let nonce = nonce.read_lock();
+            assert_eq!(i as u8, nonce[NONCEBYTES - 1]);
+            assert_eq!(0, nonce[NONCEBYTES - 2]);
+        }
+        assert_eq!(0, counter.remaining());
+        match counter.next() {
+            Err(SodiumError::NonceExhausted) => (),
+            _ => panic!("expected NonceExhausted"),
+        }
+
+        let mut counter = NonceCounter::new(8).unwrap();
+        assert_eq!(std::u64::MAX, counter.remaining());
+        counter.next().unwrap();
+        assert_eq!(std::u64::MAX, counter.remaining());
+        counter.next().unwrap();
+        assert_eq!(std::u64::MAX - 1, counter.remaining());
+    }
+
+    #[test]
+    fn it_should_restore_a_nonce_counter_snapshot() {
+        let mut counter = NonceCounter::with_random_prefix(4).unwrap();
+        for _ in 0..10 {
+            counter.next().unwrap();
+        }
+        let snapshot = counter.snapshot();
+        let mut restored = NonceCounter::restore(&snapshot).unwrap();
+        assert_eq!(counter.remaining(), restored.remaining());
+
+        let mut a = counter.next().unwrap();
+        let mut b = restored.next().unwrap();
+        let a = a.read_lock();
+        let b = b.read_lock();
+        assert_eq!(10, a[NONCEBYTES - 4]);
+        assert_eq!(a.debug_unredacted(), b.debug_unredacted());
+
+        assert!(NonceCounter::restore(&snapshot[1..]).is_err());
+        let mut bad = snapshot.clone();
+        bad[0] = 9;
+        assert!(NonceCounter::restore(&bad).is_err());
+        assert!(NonceCounter::new(0).is_err());
+    }
 }
@@ -0,0 +1,422 @@
+//! This module provides passphrase encrypted bundles of named secrets,
+//! a self-describing JSON document for writing an agent's keys to disk
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{aead, pwhash, random::random_secbuf, secbuf::SecBuf};
+use crate::error::SodiumError;
+
+/// the format version written by `encrypt_bundle`
+pub const BUNDLE_VERSION: u8 = 1;
+
+/// the key derivation recorded in the document
+const ALGORITHM: &str = "argon2id13";
+
+/// each entry is a u16 name length, the name, a u32 secret length and the secret
+const ENTRY_HEADER_LEN: usize = 2 + 4;
+
+/// A secret and the name it is bundled under
+pub struct NamedSecBuf {
+    pub name: String,
+    pub secret: SecBuf,
+}
+
+/// The argon2id cost of deriving the bundle key from the passphrase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PwHashLimits {
+    pub ops_limit: u64,
+    pub mem_limit: usize,
+}
+
+impl Default for PwHashLimits {
+    fn default() -> Self {
+        PwHashLimits {
+            ops_limit: pwhash::OPSLIMIT_MODERATE,
+            mem_limit: pwhash::MEMLIMIT_MODERATE,
+        }
+    }
+}
+
+/// read ahead of the full document, so other versions are never misparsed
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundleDocument {
+    version: u8,
+    algorithm: String,
+    limits: PwHashLimits,
+    salt: String,
+    nonce: String,
+    cipher: String,
+}
+
+fn bad_json(e: serde_json::Error) -> SodiumError {
+    SodiumError::new(&format!("bad bundle json: {}", e))
+}
+
+fn bad_contents() -> SodiumError {
+    SodiumError::new("bad bundle contents")
+}
+
+/// the document fields the cipher text is bound to as additional data
+fn additional_data(version: u8, limits: PwHashLimits, salt: &[u8]) -> Result<SecBuf, SodiumError> {
+    let mut adata = format!(
+        "{}:{}:{}:{}:",
+        version, ALGORITHM, limits.ops_limit, limits.mem_limit
+    )
+    .into_bytes();
+    adata.extend_from_slice(salt);
+    let mut b = SecBuf::try_with_insecure(adata.len())?;
+    b.write(0, &adata)?;
+    Ok(b)
+}
+
+/// derive the aead key from the passphrase
+///
+/// the limits are checked against the libsodium bounds first, limits crypto_pwhash
+/// would refuse must never leave the key unwritten
+fn derive_key(
+    passphrase: &mut SecBuf,
+    limits: PwHashLimits,
+    salt: &mut SecBuf,
+) -> Result<SecBuf, SodiumError> {
+    let params = pwhash::PwHashParams::custom(limits.ops_limit, limits.mem_limit)?;
+    let mut secret = SecBuf::try_with_secure(aead::KEYBYTES)?;
+    pwhash::hash_with_params(passphrase, salt, &mut secret, params)?;
+    Ok(secret)
+}
+
+/// encrypt `keys` with a key derived from `passphrase` at the default (moderate) cost
+///
+/// @param {[NamedSecBuf]} keys - the secrets to bundle
+///
+/// @param {SecBuf} passphrase - the bundle passphrase
+///
+/// @UseReturn {String} - the JSON document
+pub fn encrypt_bundle(
+    keys: &mut [NamedSecBuf],
+    passphrase: &mut SecBuf,
+) -> Result<String, SodiumError> {
+    encrypt_bundle_with_limits(keys, passphrase, PwHashLimits::default())
+}
+
+/// `encrypt_bundle` with the given argon2id cost, which is recorded in the document
+pub fn encrypt_bundle_with_limits(
+    keys: &mut [NamedSecBuf],
+    passphrase: &mut SecBuf,
+    limits: PwHashLimits,
+) -> Result<String, SodiumError> {
+    let mut total = 0;
+    for key in keys.iter() {
+        if key.name.len() > std::u16::MAX as usize || key.secret.len() > std::u32::MAX as usize {
+            return Err(SodiumError::new(&format!(
+                "key {} is too large to bundle",
+                key.name
+            )));
+        }
+        total += ENTRY_HEADER_LEN + key.name.len() + key.secret.len();
+    }
+
+    let mut plain = SecBuf::try_with_secure_any(total)?;
+    {
+        let mut p = plain.write_lock();
+        let mut offset = 0;
+        for key in keys.iter_mut() {
+            let name = key.name.as_bytes();
+            p[offset..offset + 2].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            offset += 2;
+            p[offset..offset + name.len()].copy_from_slice(name);
+            offset += name.len();
+            let len = key.secret.len();
+            p[offset..offset + 4].copy_from_slice(&(len as u32).to_le_bytes());
+            offset += 4;
+            key.secret
+                .peek(|b| p[offset..offset + len].copy_from_slice(b));
+            offset += len;
+        }
+    }
+
+    let mut salt = SecBuf::with_insecure(pwhash::SALTBYTES);
+    random_secbuf(&mut salt);
+    let mut nonce = SecBuf::with_insecure(aead::NONCEBYTES);
+    random_secbuf(&mut nonce);
+    let mut secret = derive_key(passphrase, limits, &mut salt)?;
+
+    let salt = salt.read_lock().to_vec();
+    let mut adata = additional_data(BUNDLE_VERSION, limits, &salt)?;
+    let mut cipher = SecBuf::try_with_insecure(total + aead::ABYTES)?;
+    aead::enc(
+        &mut plain,
+        &mut secret,
+        Some(&mut adata),
+        &mut nonce,
+        &mut cipher,
+    )?;
+
+    let doc = BundleDocument {
+        version: BUNDLE_VERSION,
+        algorithm: ALGORITHM.to_string(),
+        limits,
+        salt: base64::encode(&salt),
+        nonce: base64::encode(&nonce.read_lock()[..]),
+        cipher: base64::encode(&cipher.read_lock()[..]),
+    };
+    serde_json::to_string(&doc).map_err(bad_json)
+}
+
+/// decrypt a document produced by `encrypt_bundle`, each secret is loaded
+/// directly into its own SecBuf backed by secure memory
+///
+/// a wrong passphrase or tampered document returns CouldNotDecrypt,
+/// and other format versions UnsupportedVersion
+///
+/// @param {string} json - the bundle document
+///
+/// @param {SecBuf} passphrase - the bundle passphrase
+pub fn decrypt_bundle(
+    json: &str,
+    passphrase: &mut SecBuf,
+) -> Result<Vec<NamedSecBuf>, SodiumError> {
+    let probe: VersionProbe = serde_json::from_str(json).map_err(bad_json)?;
+    if probe.version != BUNDLE_VERSION {
+        return Err(SodiumError::UnsupportedVersion(probe.version));
+    }
+    let doc: BundleDocument = serde_json::from_str(json).map_err(bad_json)?;
+    if doc.algorithm != ALGORITHM {
+        return Err(SodiumError::new(&format!(
+            "unsupported bundle key derivation: {}",
+            doc.algorithm
+        )));
+    }
+    let limits = doc.limits;
+    // the limits come from the file, so cap how much work they can ask for
+    if limits.ops_limit > pwhash::OPSLIMIT_SENSITIVE
+        || limits.mem_limit > pwhash::MEMLIMIT_SENSITIVE
+    {
+        return Err(SodiumError::OutOfBounds(
+            "bundle pwhash limits too large".to_string(),
+        ));
+    }
+
+    let salt = base64::decode(&doc.salt)?;
+    let nonce = base64::decode(&doc.nonce)?;
+    let cipher = base64::decode(&doc.cipher)?;
+    if salt.len() != pwhash::SALTBYTES
+        || nonce.len() != aead::NONCEBYTES
+        || cipher.len() < aead::ABYTES
+    {
+        return Err(bad_contents());
+    }
+
+    let mut adata = additional_data(doc.version, limits, &salt)?;
+    let mut salt_buf = SecBuf::with_insecure(salt.len());
+    salt_buf.write(0, &salt)?;
+    let mut nonce_buf = SecBuf::with_insecure(nonce.len());
+    nonce_buf.write(0, &nonce)?;
+    let mut cipher_buf = SecBuf::with_insecure(cipher.len());
+    cipher_buf.write(0, &cipher)?;
+
+    let mut secret = derive_key(passphrase, limits, &mut salt_buf)?;
+    let mut plain = SecBuf::try_with_secure_any(cipher.len() - aead::ABYTES)?;
+    aead::dec(
+        &mut plain,
+        &mut secret,
+        Some(&mut adata),
+        &mut nonce_buf,
+        &mut cipher_buf,
+    )?;
+
+    let p = plain.read_lock();
+    let mut keys = Vec::new();
+    let mut offset = 0;
+    while offset < p.len() {
+        if p.len() - offset < 2 {
+            return Err(bad_contents());
+        }
+        let name_len = p[offset] as usize | (p[offset + 1] as usize) << 8;
+        offset += 2;
+        if p.len() - offset < name_len + 4 {
+            return Err(bad_contents());
+        }
+        let name =
+            String::from_utf8(p[offset..offset + name_len].to_vec()).map_err(|_| bad_contents())?;
+        offset += name_len;
+        let len = (0..4).fold(0usize, |len, i| len | (p[offset + i] as usize) << (8 * i));
+        offset += 4;
+        if p.len() - offset < len {
+            return Err(bad_contents());
+        }
+        let mut secret = SecBuf::try_with_secure_any(len)?;
+        secret.write(0, &p[offset..offset + len])?;
+        offset += len;
+        keys.push(NamedSecBuf { name, secret });
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_LIMITS: PwHashLimits = PwHashLimits {
+        ops_limit: pwhash::OPSLIMIT_INTERACTIVE,
+        mem_limit: pwhash::MEMLIMIT_INTERACTIVE,
+    };
+
+    fn passphrase(s: &str) -> SecBuf {
+        SecBuf::with_insecure_from_string(s.to_string())
+    }
+
+    fn named(name: &str, len: usize) -> NamedSecBuf {
+        let mut secret = SecBuf::with_secure_any(len);
+        random_secbuf(&mut secret);
+        NamedSecBuf {
+            name: name.to_string(),
+            secret,
+        }
+    }
+
+    fn test_bundle() -> (Vec<NamedSecBuf>, String) {
+        let mut keys = vec![named("seed", 32), named("sign", 64), named("pin", 7)];
+        let json =
+            encrypt_bundle_with_limits(&mut keys, &mut passphrase("correct horse"), TEST_LIMITS)
+                .unwrap();
+        (keys, json)
+    }
+
+    /// change a field of the bundle document
+    fn edit(json: &str, f: impl FnOnce(&mut serde_json::Value)) -> String {
+        let mut doc: serde_json::Value = serde_json::from_str(json).unwrap();
+        f(&mut doc);
+        doc.to_string()
+    }
+
+    fn assert_could_not_decrypt(json: &str) {
+        match decrypt_bundle(json, &mut passphrase("correct horse")) {
+            Err(SodiumError::CouldNotDecrypt) => (),
+            _ => panic!("expected CouldNotDecrypt"),
+        }
+    }
+
+    #[test]
+    fn it_should_round_trip_keys_of_different_sizes() {
+        let (mut keys, json) = test_bundle();
+        let doc: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(doc["version"], 1);
+        assert_eq!(doc["algorithm"], "argon2id13");
+
+        let mut decrypted = decrypt_bundle(&json, &mut passphrase("correct horse")).unwrap();
+        assert_eq!(3, decrypted.len());
+        for (key, out) in keys.iter_mut().zip(decrypted.iter_mut()) {
+            assert_eq!(key.name, out.name);
+            assert!(out.secret.is_secure());
+            let a = key.secret.read_lock();
+            let b = out.secret.read_lock();
+            assert_eq!(a.debug_unredacted(), b.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_round_trip_odd_name_and_secret_lengths() {
+        let mut keys = vec![named("a", 1), named("odd", 13), named("seventeen", 17)];
+        let json =
+            encrypt_bundle_with_limits(&mut keys, &mut passphrase("correct horse"), TEST_LIMITS)
+                .unwrap();
+        let mut decrypted = decrypt_bundle(&json, &mut passphrase("correct horse")).unwrap();
+        assert_eq!(3, decrypted.len());
+        for (key, out) in keys.iter_mut().zip(decrypted.iter_mut()) {
+            assert_eq!(key.name, out.name);
+            assert_eq!(key.secret.len(), out.secret.len());
+            assert_eq!(
+                key.secret.read_lock().debug_unredacted(),
+                out.secret.read_lock().debug_unredacted()
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_round_trip_an_empty_bundle() {
+        let json =
+            encrypt_bundle_with_limits(&mut [], &mut passphrase("correct horse"), TEST_LIMITS)
+                .unwrap();
+        assert!(decrypt_bundle(&json, &mut passphrase("correct horse"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn it_should_reject_a_wrong_passphrase() {
+        let (_, json) = test_bundle();
+        match decrypt_bundle(&json, &mut passphrase("battery staple")) {
+            Err(SodiumError::CouldNotDecrypt) => (),
+            _ => panic!("expected CouldNotDecrypt"),
+        }
+    }
+
+    #[test]
+    fn it_should_reject_tampered_cipher_and_parameters() {
+        let (_, json) = test_bundle();
+        assert_could_not_decrypt(&edit(&json, |doc| {
+            let mut cipher = base64::decode(doc["cipher"].as_str().unwrap()).unwrap();
+            cipher[3] ^= 0x01;
+            doc["cipher"] = base64::encode(&cipher).into();
+        }));
+        assert_could_not_decrypt(&edit(&json, |doc| {
+            doc["limits"]["ops_limit"] = (pwhash::OPSLIMIT_INTERACTIVE + 1).into();
+        }));
+    }
+
+    #[test]
+    fn it_should_reject_unknown_versions() {
+        let (_, json) = test_bundle();
+        let json = edit(&json, |doc| {
+            doc["version"] = 2.into();
+            doc["cipher"] = "not what version 1 expects".into();
+        });
+        match decrypt_bundle(&json, &mut passphrase("correct horse")) {
+            Err(SodiumError::UnsupportedVersion(2)) => (),
+            _ => panic!("expected UnsupportedVersion"),
+        }
+        assert!(decrypt_bundle("{}", &mut passphrase("correct horse")).is_err());
+    }
+
+    #[test]
+    fn it_should_reject_limits_below_the_libsodium_minimum() {
+        let zero = PwHashLimits {
+            ops_limit: 0,
+            mem_limit: 0,
+        };
+        let mut keys = vec![named("seed", 32)];
+        match encrypt_bundle_with_limits(&mut keys, &mut passphrase("correct horse"), zero) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+
+        let (_, json) = test_bundle();
+        for (ops_limit, mem_limit) in &[(0, pwhash::MEMLIMIT_INTERACTIVE), (1, 0)] {
+            let json = edit(&json, |doc| {
+                doc["limits"]["ops_limit"] = (*ops_limit).into();
+                doc["limits"]["mem_limit"] = (*mem_limit).into();
+            });
+            match decrypt_bundle(&json, &mut passphrase("any passphrase")) {
+                Err(SodiumError::OutOfBounds(_)) => (),
+                _ => panic!("expected OutOfBounds"),
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_reject_limits_above_the_sensitive_cost() {
+        let (_, json) = test_bundle();
+        let json = edit(&json, |doc| {
+            doc["limits"]["ops_limit"] = (pwhash::OPSLIMIT_SENSITIVE + 1).into();
+        });
+        match decrypt_bundle(&json, &mut passphrase("any passphrase")) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! This module provides lz4 compression of SecBufs, for large DHT values
+//! that are compressed before they are encrypted
+//!
+//! compressed data is the uncompressed length as a 4 byte little endian
+//! integer followed by an lz4 block
+
+use super::secbuf::SecBuf;
+use crate::error::SodiumError;
+
+/// bytes taken by the uncompressed length prefix
+const LEN_PREFIX: usize = 4;
+
+/// an lz4 block never expands data by more than this factor,
+/// so a larger prefix is corrupt and is not allocated
+const MAX_RATIO: usize = 255;
+
+impl SecBuf {
+    /// lz4 compress the contents of this SecBuf
+    ///
+    /// @UseReturn {Vec<u8>} - the uncompressed length (u32 LE) followed by the lz4 block,
+    /// OutOfBounds if this SecBuf is longer than a u32 can describe
+    pub fn compress(&mut self) -> Result<Vec<u8>, SodiumError> {
+        if self.len() > u32::max_value() as usize {
+            return Err(SodiumError::OutOfBounds(format!(
+                "cannot compress a Buffer of length {}",
+                self.len()
+            )));
+        }
+        let b = self.read_lock();
+        let block = lz4_flex::block::compress(&b);
+        let mut out = Vec::with_capacity(LEN_PREFIX + block.len());
+        out.extend_from_slice(&(b.len() as u32).to_le_bytes());
+        out.extend_from_slice(&block);
+        Ok(out)
+    }
+
+    /// expand data written by `compress` into a new SecBuf backed by insecure memory
+    ///
+    /// @UseReturn {SecBuf} - the uncompressed data,
+    /// Decompress or LengthMismatch if `data` is corrupt
+    pub fn expand(data: &[u8]) -> Result<SecBuf, SodiumError> {
+        if data.len() < LEN_PREFIX {
+            return Err(SodiumError::LengthMismatch(format!(
+                "compressed data must be at least {} bytes long, got {}.",
+                LEN_PREFIX,
+                data.len()
+            )));
+        }
+        let (prefix, block) = data.split_at(LEN_PREFIX);
+        let mut len = [0u8; LEN_PREFIX];
+        len.copy_from_slice(prefix);
+        let len = u32::from_le_bytes(len) as usize;
+        if len > block.len().saturating_mul(MAX_RATIO) {
+            return Err(SodiumError::LengthMismatch(format!(
+                "{} compressed bytes cannot expand to {}",
+                block.len(),
+                len
+            )));
+        }
+        let mut out = SecBuf::try_with_insecure(len)?;
+        {
+            let mut o = out.write_lock();
+            let written = lz4_flex::block::decompress_into(block, &mut o)?;
+            if written != len {
+                return Err(SodiumError::LengthMismatch(format!(
+                    "compressed data must expand to {} bytes, got {}.",
+                    len, written
+                )));
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::random_secbuf;
+
+    #[test]
+    fn it_should_compress_repeated_bytes() {
+        let mut b = SecBuf::with_insecure(1024);
+        b.fill(0x42);
+        let compressed = b.compress().unwrap();
+        assert!(compressed.len() < 1024 / 4);
+        assert_eq!(&[0, 4, 0, 0], &compressed[..LEN_PREFIX]);
+
+        let mut expanded = SecBuf::expand(&compressed).unwrap();
+        assert_eq!(
+            b.read_lock().debug_unredacted(),
+            expanded.read_lock().debug_unredacted()
+        );
+    }
+
+    #[test]
+    fn it_should_expand_incompressible_bytes() {
+        let mut b = SecBuf::with_secure(1024);
+        random_secbuf(&mut b);
+        let compressed = b.compress().unwrap();
+        let mut expanded = SecBuf::expand(&compressed).unwrap();
+        assert!(!expanded.is_secure());
+        assert_eq!(
+            b.read_lock().debug_unredacted(),
+            expanded.read_lock().debug_unredacted()
+        );
+
+        let mut empty = SecBuf::with_insecure(0);
+        let compressed = empty.compress().unwrap();
+        assert_eq!(0, SecBuf::expand(&compressed).unwrap().len());
+    }
+
+    #[test]
+    fn it_should_reject_corrupt_compressed_data() {
+        let mut b = SecBuf::with_insecure(1024);
+        b.fill(1);
+        let mut compressed = b.compress().unwrap();
+        assert!(SecBuf::expand(&compressed[..2]).is_err());
+
+        // claims far more than the block could hold
+        compressed[3] = 0x7f;
+        match SecBuf::expand(&compressed) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+
+        compressed[3] = 0;
+        let truncated = &compressed[..compressed.len() - 2];
+        assert!(SecBuf::expand(truncated).is_err());
+    }
+}
@@ -0,0 +1,1662 @@
+//! This module provides human friendly string encodings for SecBufs,
+//! protected by reed-solomon parity bytes
+
+use reed_solomon::{Decoder, DecoderError, Encoder};
+// rust-base58 rather than bs58: core_types already renders addresses with it,
+// so both crates agree on the alphabet and no new dependency is pulled in
+use rust_base58::{FromBase58, ToBase58};
+use std::{fmt, str::FromStr};
+
+use super::{check_init, secbuf::SecBuf, util};
+use crate::error::{ReedSolomonError, SodiumError};
+
+/// number of reed-solomon parity bytes appended by `SecBuf::render`
+const PARITY_LEN: usize = 5;
+
+/// reed-solomon can repair up to half as many bytes as there are parity bytes
+const CORRECTABLE: usize = PARITY_LEN / 2;
+
+/// reed-solomon codewords over GF(256) cannot exceed 255 bytes
+const MAX_CODEWORD_LEN: usize = 255;
+
+/// the base64 lengths of the shortest (one data byte) and longest codewords
+const MIN_BASE64_LEN: usize = (PARITY_LEN + 1 + 2) / 3 * 4;
+const MAX_BASE64_LEN: usize = (MAX_CODEWORD_LEN + 2) / 3 * 4;
+
+/// the unpadded base32 lengths of the shortest and longest codewords
+const MIN_BASE32_LEN: usize = ((PARITY_LEN + 1) * 8 + 4) / 5;
+const MAX_BASE32_LEN: usize = (MAX_CODEWORD_LEN * 8 + 4) / 5;
+
+/// the base58 lengths of the shortest (all zero) and longest codewords,
+/// 255 bytes are at most 255 * log58(256) = 348.2 digits
+const MIN_BASE58_LEN: usize = PARITY_LEN + 1;
+const MAX_BASE58_LEN: usize = 349;
+
+/// the format version written by `render_versioned`
+pub const RENDER_VERSION: u8 = 0x01;
+
+/// The base64 alphabet / padding used by `render` and the corrected decoders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base64Config {
+    /// standard alphabet (`+` / `/`) with padding
+    Standard,
+    /// url-safe alphabet (`-` / `_`) with padding, the default
+    UrlSafe,
+    /// url-safe alphabet, no padding on encode,
+    /// decoding accepts input with or without padding
+    UrlSafeNoPad,
+}
+
+impl Default for Base64Config {
+    fn default() -> Self {
+        Base64Config::UrlSafe
+    }
+}
+
+impl Base64Config {
+    fn config(self) -> base64::Config {
+        match self {
+            Base64Config::Standard => base64::STANDARD,
+            Base64Config::UrlSafe => base64::URL_SAFE,
+            Base64Config::UrlSafeNoPad => base64::URL_SAFE_NO_PAD,
+        }
+    }
+
+    /// strip padding from the input if this config decodes leniently
+    fn prepare<'a>(self, s: &'a str) -> &'a str {
+        match self {
+            Base64Config::UrlSafeNoPad => s.trim_end_matches('='),
+            _ => s,
+        }
+    }
+}
+
+/// reed_solomon only reports that there were too many errors,
+/// so the most we can say is that there was one more than could be repaired
+fn uncorrectable(e: DecoderError) -> SodiumError {
+    SodiumError::Uncorrectable {
+        errors_detected: CORRECTABLE + 1,
+        source: Some(ReedSolomonError(e)),
+    }
+}
+
+/// LengthMismatch unless a corrected payload is `expected_len` bytes
+fn check_exact_len(len: usize, expected_len: usize) -> Result<(), SodiumError> {
+    if len != expected_len {
+        return Err(SodiumError::LengthMismatch(format!(
+            "corrected payload must be {} bytes, got {}",
+            expected_len, len
+        )));
+    }
+    Ok(())
+}
+
+/// The base32 alphabet used by `render_base32_with` and the base32 decoders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base32Alphabet {
+    /// RFC 4648 (`A-Z2-7`), unpadded, the default
+    Rfc4648,
+    /// Crockford (`0-9A-Z` without `ILOU`), decoding maps `I` / `L` to `1`,
+    /// `O` to `0` and ignores hyphens, which suits printed recovery codes
+    Crockford,
+}
+
+impl Default for Base32Alphabet {
+    fn default() -> Self {
+        Base32Alphabet::Rfc4648
+    }
+}
+
+impl Base32Alphabet {
+    fn symbols(self) -> &'static [u8; 32] {
+        match self {
+            Base32Alphabet::Rfc4648 => b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567",
+            Base32Alphabet::Crockford => b"0123456789ABCDEFGHJKMNPQRSTVWXYZ",
+        }
+    }
+
+    /// the 5 bit value of an (uppercased) input symbol,
+    /// `Ok(None)` for symbols that are skipped
+    fn value(self, c: u8) -> Result<Option<u8>, ()> {
+        match (self, c) {
+            (Base32Alphabet::Rfc4648, c @ b'A'..=b'Z') => Ok(Some(c - b'A')),
+            (Base32Alphabet::Rfc4648, c @ b'2'..=b'7') => Ok(Some(c - b'2' + 26)),
+            (Base32Alphabet::Crockford, b'-') => Ok(None),
+            (Base32Alphabet::Crockford, b'O') => Ok(Some(0)),
+            (Base32Alphabet::Crockford, b'I') | (Base32Alphabet::Crockford, b'L') => Ok(Some(1)),
+            (Base32Alphabet::Crockford, c) => match self.symbols().iter().position(|s| *s == c) {
+                Some(v) => Ok(Some(v as u8)),
+                None => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+
+    /// unpadded base32 encoding
+    fn encode(self, data: &[u8]) -> String {
+        let symbols = self.symbols();
+        let mut out = String::with_capacity((data.len() * 8 + 4) / 5);
+        let mut acc = 0u16;
+        let mut bits = 0;
+        for byte in data {
+            acc = (acc << 8) | u16::from(*byte);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                out.push(symbols[((acc >> bits) & 0x1f) as usize] as char);
+            }
+        }
+        if bits > 0 {
+            out.push(symbols[((acc << (5 - bits)) & 0x1f) as usize] as char);
+        }
+        out
+    }
+
+    /// case-insensitive unpadded base32 decoding, trailing partial bits are dropped
+    fn decode(self, s: &str) -> Result<Vec<u8>, SodiumError> {
+        let mut out = Vec::with_capacity(s.len() * 5 / 8);
+        let mut acc = 0u16;
+        let mut bits = 0;
+        for (position, c) in s.bytes().enumerate() {
+            let v = match self.value(c.to_ascii_uppercase()) {
+                Ok(Some(v)) => v,
+                Ok(None) => continue,
+                Err(_) => return Err(SodiumError::Base32Decode { position }),
+            };
+            acc = (acc << 5) | u16::from(v);
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((acc >> bits) as u8);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// map a hand-typed character onto the base64url alphabet,
+/// `None` if it should be dropped
+///
+/// only unambiguous look-alikes are mapped: 0 / O and 1 / l / I are all valid
+/// base64url, so confusions between them are left for reed-solomon to repair
+fn normalize_char(c: char) -> Option<char> {
+    match c {
+        c if c.is_whitespace() => None,
+        '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{feff}' => None,
+        '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' | '\u{2014}' | '\u{2212}' => Some('-'),
+        // fullwidth ascii
+        '\u{ff01}'..='\u{ff5e}' => std::char::from_u32(c as u32 - 0xfee0),
+        // cyrillic look-alikes
+        'А' => Some('A'),
+        'В' => Some('B'),
+        'Е' => Some('E'),
+        'К' => Some('K'),
+        'М' => Some('M'),
+        'Н' => Some('H'),
+        'О' => Some('O'),
+        'Р' => Some('P'),
+        'С' => Some('C'),
+        'Т' => Some('T'),
+        'Х' => Some('X'),
+        'а' => Some('a'),
+        'е' => Some('e'),
+        'о' => Some('o'),
+        'р' => Some('p'),
+        'с' => Some('c'),
+        'у' => Some('y'),
+        'х' => Some('x'),
+        // greek look-alikes
+        'Α' => Some('A'),
+        'Β' => Some('B'),
+        'Ε' => Some('E'),
+        'Ζ' => Some('Z'),
+        'Η' => Some('H'),
+        'Κ' => Some('K'),
+        'Μ' => Some('M'),
+        'Ν' => Some('N'),
+        'Ο' => Some('O'),
+        'Ρ' => Some('P'),
+        'Τ' => Some('T'),
+        'Υ' => Some('Y'),
+        'Χ' => Some('X'),
+        'ο' => Some('o'),
+        c => Some(c),
+    }
+}
+
+/// the alphabet of a base64 string for `securely_corrected` / `insecurely_corrected`:
+/// standard if it contains `+` or `/`, otherwise url-safe,
+/// a string mixing `+` / `/` with `-` / `_` is an error at the first character
+/// from the second alphabet
+fn detect_base64_config(s: &str) -> Result<Base64Config, SodiumError> {
+    let mut seen = None;
+    for (i, c) in s.bytes().enumerate() {
+        let config = match c {
+            b'+' | b'/' => Base64Config::Standard,
+            b'-' | b'_' => Base64Config::UrlSafe,
+            _ => continue,
+        };
+        match seen {
+            Some(prev) if prev != config => {
+                return Err(base64::DecodeError::InvalidByte(i, c).into());
+            }
+            _ => seen = Some(config),
+        }
+    }
+    Ok(seen.unwrap_or_default())
+}
+
+/// clean up a hand-typed rendered identity before decoding:
+/// strips whitespace and maps unambiguous homoglyphs onto the base64url alphabet
+pub fn normalize_identity_input(s: &str) -> String {
+    s.chars().filter_map(normalize_char).collect()
+}
+
+impl SecBuf {
+    /// number of reed-solomon parity bytes appended by `render`
+    pub const PARITY_LEN: usize = PARITY_LEN;
+
+    /// the length of the bytes `render` encodes (this SecBuf plus its parity bytes),
+    /// before base64 encoding
+    pub fn encoded_len(&self) -> usize {
+        self.len() + PARITY_LEN
+    }
+
+    /// render a base64url encoded string of this SecBuf with reed-solomon parity bytes
+    /// manages its own read lock, restoring the prior protect state on exit
+    pub fn render(&mut self) -> Result<String, SodiumError> {
+        self.render_with(Base64Config::default())
+    }
+
+    /// render this SecBuf with reed-solomon parity bytes, using the given base64 config
+    pub fn render_with(&mut self, config: Base64Config) -> Result<String, SodiumError> {
+        self.render_codeword(|enc| base64::encode_config(enc, config.config()))
+    }
+
+    /// reed-solomon encode this SecBuf and render the codeword with `render`,
+    /// the codeword copy is zeroed before returning
+    fn render_codeword<F>(&mut self, render: F) -> Result<String, SodiumError>
+    where
+        F: FnOnce(&[u8]) -> String,
+    {
+        if self.len() + PARITY_LEN > MAX_CODEWORD_LEN {
+            return Err(SodiumError::new("SecBuf too large to render"));
+        }
+        let enc = Encoder::new(PARITY_LEN);
+        let mut enc = self.peek(|b| *enc.encode(b));
+        let out = render(&enc[..]);
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(enc), enc.len());
+        }
+        Ok(out)
+    }
+
+    /// take a base64url encoded string produced by `render`,
+    /// apply reed-solomon parity correction,
+    /// and load the result into a new SecBuf backed by secure memory
+    ///
+    /// the input is first cleaned up with `normalize_identity_input`,
+    /// strings in the standard base64 alphabet (`render_with(Base64Config::Standard)`)
+    /// are accepted too, but not strings mixing both alphabets
+    ///
+    /// the secret bytes only ever live in secure memory while being decoded,
+    /// and the output is exactly the payload length, whether or not it is
+    /// a multiple of 8 (see `try_with_secure_any`)
+    pub fn securely_corrected(s: &str) -> Result<SecBuf, SodiumError> {
+        SecBuf::securely_corrected_checked(s, None, SecBuf::try_with_secure_any)
+    }
+
+    /// `securely_corrected` into buffers from `alloc`, checking the payload is
+    /// `expected_len` bytes (if given) before the output is allocated
+    fn securely_corrected_checked<F>(
+        s: &str,
+        expected_len: Option<usize>,
+        alloc: F,
+    ) -> Result<SecBuf, SodiumError>
+    where
+        F: FnMut(usize) -> Result<SecBuf, SodiumError>,
+    {
+        let mut s = normalize_identity_input(s);
+        let res = detect_base64_config(&s)
+            .and_then(|config| SecBuf::correct_checked_with(&s, config, expected_len, alloc));
+        unsafe {
+            let s = s.as_bytes_mut();
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(s), s.len());
+        }
+        res
+    }
+
+    /// `securely_corrected` for strings rendered with the given base64 config
+    pub fn securely_corrected_with(s: &str, config: Base64Config) -> Result<SecBuf, SodiumError> {
+        SecBuf::correct_with(s, config, SecBuf::try_with_secure_any)
+    }
+
+    /// take a base64url encoded string produced by `render`,
+    /// apply reed-solomon parity correction,
+    /// and load the result into a new SecBuf backed by insecure memory
+    /// the input is first cleaned up with `normalize_identity_input`,
+    /// and may use either base64 alphabet, as for `securely_corrected`
+    pub fn insecurely_corrected(s: &str) -> Result<SecBuf, SodiumError> {
+        let s = normalize_identity_input(s);
+        SecBuf::insecurely_corrected_with(&s, detect_base64_config(&s)?)
+    }
+
+    /// `insecurely_corrected` for strings rendered with the given base64 config
+    pub fn insecurely_corrected_with(s: &str, config: Base64Config) -> Result<SecBuf, SodiumError> {
+        SecBuf::correct_with(s, config, |n| Ok(SecBuf::with_insecure(n)))
+    }
+
+    /// `securely_corrected`, rejecting with LengthMismatch any string
+    /// whose corrected payload is not `expected_len` bytes
+    pub fn securely_corrected_exact(s: &str, expected_len: usize) -> Result<SecBuf, SodiumError> {
+        SecBuf::securely_corrected_checked(s, Some(expected_len), SecBuf::try_with_secure_any)
+    }
+
+    /// `insecurely_corrected`, rejecting with LengthMismatch any string
+    /// whose corrected payload is not `expected_len` bytes
+    pub fn insecurely_corrected_exact(s: &str, expected_len: usize) -> Result<SecBuf, SodiumError> {
+        let b = SecBuf::insecurely_corrected(s)?;
+        check_exact_len(b.len(), expected_len)?;
+        Ok(b)
+    }
+
+    /// render this SecBuf split across `blocks` reed-solomon codewords,
+    /// with their bytes interleaved so a burst of errors (e.g. a smudge)
+    /// is spread thinly across the codewords
+    ///
+    /// payload byte `i` goes to codeword `i % blocks`, and byte `r` of codeword `j`
+    /// is written at `r * blocks + j`. One block is the same layout as `render`
+    pub fn render_interleaved(&mut self, blocks: usize) -> Result<String, SodiumError> {
+        let len = self.len();
+        if blocks == 0 || blocks > len {
+            return Err(SodiumError::new("bad interleave block count"));
+        }
+        if (len + blocks - 1) / blocks + PARITY_LEN > MAX_CODEWORD_LEN {
+            return Err(SodiumError::new("SecBuf too large to render"));
+        }
+        let enc = Encoder::new(PARITY_LEN);
+        let mut out = vec![0; len + blocks * PARITY_LEN];
+        self.peek(|b| {
+            let mut block = [0u8; MAX_CODEWORD_LEN];
+            for j in 0..blocks {
+                let k = (len - j + blocks - 1) / blocks;
+                for (r, byte) in block.iter_mut().take(k).enumerate() {
+                    *byte = b[r * blocks + j];
+                }
+                for (r, byte) in enc.encode(&block[..k]).iter().enumerate() {
+                    out[r * blocks + j] = *byte;
+                }
+            }
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(block), block.len());
+            }
+        });
+        Ok(base64::encode_config(
+            &out,
+            Base64Config::default().config(),
+        ))
+    }
+
+    /// take a string produced by `render_interleaved` with the same `blocks`,
+    /// apply reed-solomon correction to each codeword,
+    /// and load the result into a new SecBuf backed by secure memory
+    pub fn securely_corrected_interleaved(s: &str, blocks: usize) -> Result<SecBuf, SodiumError> {
+        SecBuf::correct_interleaved_with(s, blocks, SecBuf::try_with_secure_any)
+    }
+
+    /// take a string produced by `render_interleaved` with the same `blocks`,
+    /// apply reed-solomon correction to each codeword,
+    /// and load the result into a new SecBuf backed by insecure memory
+    pub fn insecurely_corrected_interleaved(s: &str, blocks: usize) -> Result<SecBuf, SodiumError> {
+        SecBuf::correct_interleaved_with(s, blocks, |n| Ok(SecBuf::with_insecure(n)))
+    }
+
+    /// de-interleave and correct each codeword of a `render_interleaved` string
+    /// the scratch and output buffers come from `alloc`, scratch is zeroed before drop
+    fn correct_interleaved_with<F>(
+        s: &str,
+        blocks: usize,
+        mut alloc: F,
+    ) -> Result<SecBuf, SodiumError>
+    where
+        F: FnMut(usize) -> Result<SecBuf, SodiumError>,
+    {
+        check_init();
+        let s = normalize_identity_input(s);
+        let config = Base64Config::default();
+        if blocks == 0 {
+            return Err(SodiumError::new("bad interleave block count"));
+        }
+        // `blocks` full codewords is the most a rendering can hold
+        let max_len = blocks
+            .checked_mul(MAX_CODEWORD_LEN)
+            .map_or(std::usize::MAX, |n| (n + 2) / 3 * 4);
+        if s.len() > max_len {
+            return Err(SodiumError::WrongLength {
+                expected_multiple: 4,
+                got: s.len(),
+                bounds: Some((MIN_BASE64_LEN, max_len)),
+            });
+        }
+
+        // room for the base64 bytes, followed by one codeword
+        let decoded_len = (s.len() + 3) / 4 * 3;
+        let scratch_len = (decoded_len + MAX_CODEWORD_LEN + 7) / 8 * 8;
+        let mut scratch = alloc(scratch_len)?;
+        let mut scratch = scratch.write_lock();
+
+        let result = (|| {
+            let (decoded, block) = scratch.split_at_mut(decoded_len);
+            let dec_len = base64::decode_config_slice(&s, config.config(), decoded)?;
+            if dec_len <= blocks * PARITY_LEN {
+                return Err(SodiumError::new("bad rendered SecBuf length"));
+            }
+            let len = dec_len - blocks * PARITY_LEN;
+            if blocks > len || (len + blocks - 1) / blocks + PARITY_LEN > MAX_CODEWORD_LEN {
+                return Err(SodiumError::new("bad rendered SecBuf length"));
+            }
+
+            let mut out = alloc(len)?;
+            {
+                let mut out = out.write_lock();
+                let dec = Decoder::new(PARITY_LEN);
+                for j in 0..blocks {
+                    let k = (len - j + blocks - 1) / blocks;
+                    for (r, byte) in block.iter_mut().take(k + PARITY_LEN).enumerate() {
+                        *byte = decoded[r * blocks + j];
+                    }
+                    let mut corrected = dec
+                        .correct(&block[..k + PARITY_LEN], None)
+                        .map_err(uncorrectable)?;
+                    for (r, byte) in corrected.data().iter().enumerate() {
+                        out[r * blocks + j] = *byte;
+                    }
+                    unsafe {
+                        rust_sodium_sys::sodium_memzero(raw_ptr_void!(corrected), corrected.len());
+                    }
+                }
+            }
+            Ok(out)
+        })();
+
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(scratch), scratch.len());
+        }
+        result
+    }
+
+    /// render this SecBuf with a leading format version byte,
+    /// which is covered by the reed-solomon parity along with the data
+    ///
+    /// this is the recommended encoding for agent ids
+    pub fn render_versioned(&mut self) -> Result<String, SodiumError> {
+        let len = self.len() + 1;
+        if len + PARITY_LEN > MAX_CODEWORD_LEN {
+            return Err(SodiumError::new("SecBuf too large to render"));
+        }
+        let mut payload = [0u8; MAX_CODEWORD_LEN];
+        payload[0] = RENDER_VERSION;
+        self.peek(|b| payload[1..len].copy_from_slice(b));
+        let enc = Encoder::new(PARITY_LEN);
+        let mut enc = *enc.encode(&payload[..len]);
+        let out = base64::encode_config(&enc[..], Base64Config::default().config());
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(payload), payload.len());
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(enc), enc.len());
+        }
+        Ok(out)
+    }
+
+    /// take a string produced by `render_versioned`, apply reed-solomon correction,
+    /// check the format version and load the data into a new SecBuf
+    /// backed by insecure memory
+    pub fn corrected_versioned(s: &str) -> Result<SecBuf, SodiumError> {
+        let mut payload = SecBuf::insecurely_corrected(s)?;
+        let payload = payload.read_lock();
+        match payload[0] {
+            RENDER_VERSION => {
+                let mut out = SecBuf::with_insecure(payload.len() - 1);
+                out.write(0, &payload[1..])?;
+                Ok(out)
+            }
+            v => Err(SodiumError::UnsupportedVersion(v)),
+        }
+    }
+
+    /// render this SecBuf padded (ISO/IEC 7816-4, via sodium_pad) to a multiple of
+    /// `block` bytes before adding parity, so the output length only reveals
+    /// the padded length. At least one byte of padding is always added
+    pub fn render_padded(&mut self, block: usize) -> Result<String, SodiumError> {
+        check_init();
+        let len = self.len();
+        let padded_len = util::padded_len(len, block)?;
+        if padded_len > MAX_CODEWORD_LEN - PARITY_LEN {
+            return Err(SodiumError::new("SecBuf too large to render"));
+        }
+        let mut payload = SecBuf::try_with_secure_any(padded_len)?;
+        self.peek(|b| payload.write(0, b))?;
+        let written = util::pad(&mut payload, len, block)?;
+        let enc = Encoder::new(PARITY_LEN);
+        let mut enc = payload.peek(|p| *enc.encode(&p[..written]));
+        let out = base64::encode_config(&enc[..], Base64Config::default().config());
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(enc), enc.len());
+        }
+        Ok(out)
+    }
+
+    /// take a string produced by `render_padded` with the same `block`,
+    /// apply reed-solomon correction, remove the padding
+    /// and load the result into a new SecBuf backed by secure memory
+    ///
+    /// neither the padded nor the unpadded length has to be a multiple of 8,
+    /// see `try_with_secure_any`
+    pub fn securely_corrected_padded(s: &str, block: usize) -> Result<SecBuf, SodiumError> {
+        let mut payload = SecBuf::securely_corrected_checked(s, None, SecBuf::try_with_secure_any)?;
+        SecBuf::unpad_into(&mut payload, block, SecBuf::try_with_secure_any)
+    }
+
+    /// take a string produced by `render_padded` with the same `block`,
+    /// apply reed-solomon correction, remove the padding
+    /// and load the result into a new SecBuf backed by insecure memory
+    pub fn insecurely_corrected_padded(s: &str, block: usize) -> Result<SecBuf, SodiumError> {
+        let mut payload = SecBuf::insecurely_corrected(s)?;
+        SecBuf::unpad_into(&mut payload, block, SecBuf::try_with_insecure)
+    }
+
+    /// strip sodium_pad padding from `payload` into a buffer from `alloc`
+    fn unpad_into<F>(payload: &mut SecBuf, block: usize, alloc: F) -> Result<SecBuf, SodiumError>
+    where
+        F: FnOnce(usize) -> Result<SecBuf, SodiumError>,
+    {
+        if block == 0 {
+            return Err(SodiumError::new("bad padding block size"));
+        }
+        let payload = payload.read_lock();
+        let mut len = 0usize;
+        let res = unsafe {
+            rust_sodium_sys::sodium_unpad(
+                &mut len,
+                raw_ptr_char_immut!(payload),
+                payload.len(),
+                block,
+            )
+        };
+        if res != 0 || payload.len() % block != 0 {
+            return Err(SodiumError::InvalidPadding);
+        }
+        let mut out = alloc(len)?;
+        out.write(0, &payload[..len])?;
+        Ok(out)
+    }
+
+    /// decode a hex string (either case) straight into a new SecBuf backed by secure memory,
+    /// for secrets typed in by hand. Decoding is constant time (sodium_hex2bin)
+    /// and the bytes never pass through insecure memory
+    ///
+    /// @UseReturn {SecBuf} - WrongLength for an odd length, HexDecode at the first
+    /// character (or character pair) that is not hex
+    pub fn from_hex_secure(s: &str) -> Result<SecBuf, SodiumError> {
+        check_init();
+        if s.len() % 2 != 0 {
+            return Err(SodiumError::WrongLength {
+                expected_multiple: 2,
+                got: s.len(),
+                bounds: None,
+            });
+        }
+        let mut out = SecBuf::try_with_secure_any(s.len() / 2)?;
+        let position = {
+            let mut o = out.write_lock();
+            let mut bin_len = 0usize;
+            let mut hex_end: *const libc::c_char = std::ptr::null();
+            let res = unsafe {
+                rust_sodium_sys::sodium_hex2bin(
+                    raw_ptr_char!(o),
+                    o.len(),
+                    raw_ptr_ichar_immut!(s),
+                    s.len(),
+                    std::ptr::null(),
+                    &mut bin_len,
+                    &mut hex_end,
+                )
+            };
+            let position = hex_end as usize - s.as_ptr() as usize;
+            if res == 0 && position == s.len() && bin_len == o.len() {
+                None
+            } else {
+                Some(position)
+            }
+        };
+        match position {
+            // dropping `out` zeroes whatever was decoded before the bad character
+            Some(position) => Err(SodiumError::HexDecode { position }),
+            None => Ok(out),
+        }
+    }
+
+    /// decode and correct a rendered string
+    /// every buffer that holds decoded bytes (the base64 scratch and the output)
+    /// is obtained from `alloc`, and the scratch / parity copies are zeroed before drop
+    /// the output is allocated before correcting, so a size `alloc` refuses fails early
+    fn correct_with<F>(s: &str, config: Base64Config, alloc: F) -> Result<SecBuf, SodiumError>
+    where
+        F: FnMut(usize) -> Result<SecBuf, SodiumError>,
+    {
+        SecBuf::correct_checked_with(s, config, None, alloc)
+    }
+
+    /// `correct_with`, returning LengthMismatch before the output is allocated
+    /// unless the payload is `expected_len` bytes (if given)
+    fn correct_checked_with<F>(
+        s: &str,
+        config: Base64Config,
+        expected_len: Option<usize>,
+        mut alloc: F,
+    ) -> Result<SecBuf, SodiumError>
+    where
+        F: FnMut(usize) -> Result<SecBuf, SodiumError>,
+    {
+        check_init();
+        let s = config.prepare(s);
+        let bad_length = || SodiumError::WrongLength {
+            expected_multiple: 4,
+            got: s.len(),
+            bounds: Some((MIN_BASE64_LEN, MAX_BASE64_LEN)),
+        };
+        // refuse oversized input before any secure scratch is allocated for it
+        if s.len() > MAX_BASE64_LEN {
+            return Err(bad_length());
+        }
+
+        // base64 decodes at most 3 bytes per 4 chars, rounded up for alignment
+        let scratch_len = ((s.len() + 3) / 4 * 3 + 7) / 8 * 8;
+        let mut scratch = alloc(scratch_len)?;
+        let mut scratch = scratch.write_lock();
+
+        let result = (|| {
+            let dec_len = base64::decode_config_slice(s, config.config(), &mut scratch).map_err(
+                |e| match e {
+                    base64::DecodeError::InvalidLength => SodiumError::WrongLength {
+                        expected_multiple: 4,
+                        got: s.len(),
+                        bounds: None,
+                    },
+                    e => e.into(),
+                },
+            )?;
+            if dec_len <= PARITY_LEN || dec_len > MAX_CODEWORD_LEN {
+                return Err(bad_length());
+            }
+            if let Some(expected_len) = expected_len {
+                check_exact_len(dec_len - PARITY_LEN, expected_len)?;
+            }
+            let mut out = alloc(dec_len - PARITY_LEN)?;
+            let dec = Decoder::new(PARITY_LEN);
+            let mut dec = dec
+                .correct(&scratch[..dec_len], None)
+                .map_err(uncorrectable)?;
+            let written = out.write(0, dec.data());
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(dec), dec.len());
+            }
+            written.map(|_| out)
+        })();
+
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(scratch), scratch.len());
+        }
+        result
+    }
+
+    /// render a base58 encoded string of this SecBuf with reed-solomon parity bytes,
+    /// mirroring `render`, an error if it is too large to carry parity (over 250 bytes)
+    ///
+    /// base58 is one big number, so a wrong digit in the payload part of the string
+    /// changes many bytes: it is detected, but only wrong trailing digits are corrected
+    pub fn to_base58(&mut self) -> Result<String, SodiumError> {
+        self.render_codeword(|enc| enc.to_base58())
+    }
+
+    /// take a base58 encoded string produced by `to_base58`,
+    /// apply reed-solomon parity correction,
+    /// and load the result into a new SecBuf backed by insecure memory
+    ///
+    /// only wrong trailing digits are corrected (see `to_base58`), a wrong
+    /// digit anywhere else is an error rather than a different SecBuf
+    pub fn from_base58(s: &str) -> Result<SecBuf, SodiumError> {
+        SecBuf::base58_with(s, |n| Ok(SecBuf::with_insecure(n)))
+    }
+
+    /// take a base58 encoded string produced by `to_base58`,
+    /// apply reed-solomon parity correction,
+    /// and load the result into a new SecBuf backed by secure memory,
+    /// corrected as for `from_base58`
+    pub fn securely_from_base58(s: &str) -> Result<SecBuf, SodiumError> {
+        SecBuf::base58_with(s, SecBuf::try_with_secure_any)
+    }
+
+    /// decode and correct a base58 string into a buffer from `alloc`,
+    /// the decoded and parity copies are zeroed before drop
+    fn base58_with<F>(s: &str, alloc: F) -> Result<SecBuf, SodiumError>
+    where
+        F: FnOnce(usize) -> Result<SecBuf, SodiumError>,
+    {
+        check_init();
+        let bad_length = || SodiumError::WrongLength {
+            expected_multiple: 1,
+            got: s.len(),
+            bounds: Some((MIN_BASE58_LEN, MAX_BASE58_LEN)),
+        };
+        if s.len() > MAX_BASE58_LEN {
+            return Err(bad_length());
+        }
+        let mut decoded = s.from_base58()?;
+
+        let result = (|| {
+            if decoded.len() <= PARITY_LEN || decoded.len() > MAX_CODEWORD_LEN {
+                return Err(bad_length());
+            }
+            let mut out = alloc(decoded.len() - PARITY_LEN)?;
+            let dec = Decoder::new(PARITY_LEN);
+            let mut dec = dec.correct(&decoded, None).map_err(uncorrectable)?;
+            let written = out.write(0, dec.data());
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(dec), dec.len());
+            }
+            written.map(|_| out)
+        })();
+
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(decoded), decoded.len());
+        }
+        result
+    }
+
+    /// render an unpadded RFC 4648 base32 encoded string of this SecBuf
+    /// with reed-solomon parity bytes
+    /// the alphabet is case-insensitive, which suits QR codes and reading aloud
+    pub fn render_base32(&mut self) -> Result<String, SodiumError> {
+        self.render_base32_with(Base32Alphabet::default())
+    }
+
+    /// render this SecBuf as base32 with reed-solomon parity bytes, using the given alphabet
+    pub fn render_base32_with(&mut self, alphabet: Base32Alphabet) -> Result<String, SodiumError> {
+        self.render_codeword(|enc| alphabet.encode(enc))
+    }
+
+    /// take a base32 string produced by `render_base32` (in either case),
+    /// apply reed-solomon parity correction,
+    /// and load the result into a new SecBuf backed by insecure memory
+    pub fn corrected_base32(s: &str) -> Result<SecBuf, SodiumError> {
+        SecBuf::insecurely_corrected_base32_with(s, Base32Alphabet::default())
+    }
+
+    /// `corrected_base32` for strings rendered with the given alphabet
+    pub fn insecurely_corrected_base32_with(
+        s: &str,
+        alphabet: Base32Alphabet,
+    ) -> Result<SecBuf, SodiumError> {
+        SecBuf::base32_with(s, alphabet, |n| Ok(SecBuf::with_insecure(n)))
+    }
+
+    /// take a base32 string produced by `render_base32_with` the given alphabet,
+    /// apply reed-solomon parity correction,
+    /// and load the result into a new SecBuf backed by secure memory
+    pub fn securely_corrected_base32_with(
+        s: &str,
+        alphabet: Base32Alphabet,
+    ) -> Result<SecBuf, SodiumError> {
+        SecBuf::base32_with(s, alphabet, SecBuf::try_with_secure_any)
+    }
+
+    /// decode and correct a base32 string into a buffer from `alloc`,
+    /// the decoded and parity copies are zeroed before drop
+    fn base32_with<F>(s: &str, alphabet: Base32Alphabet, alloc: F) -> Result<SecBuf, SodiumError>
+    where
+        F: FnOnce(usize) -> Result<SecBuf, SodiumError>,
+    {
+        check_init();
+        // skipped separators do not count towards the length
+        let symbols = s
+            .bytes()
+            .filter(|c| alphabet.value(c.to_ascii_uppercase()) != Ok(None))
+            .count();
+        let bad_length = || SodiumError::WrongLength {
+            expected_multiple: 1,
+            got: symbols,
+            bounds: Some((MIN_BASE32_LEN, MAX_BASE32_LEN)),
+        };
+        if symbols > MAX_BASE32_LEN {
+            return Err(bad_length());
+        }
+        let mut decoded = alphabet.decode(s)?;
+
+        let result = (|| {
+            if decoded.len() <= PARITY_LEN || decoded.len() > MAX_CODEWORD_LEN {
+                return Err(bad_length());
+            }
+            let mut out = alloc(decoded.len() - PARITY_LEN)?;
+            let dec = Decoder::new(PARITY_LEN);
+            let mut dec = dec.correct(&decoded, None).map_err(uncorrectable)?;
+            let written = out.write(0, dec.data());
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(dec), dec.len());
+            }
+            written.map(|_| out)
+        })();
+
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(decoded), decoded.len());
+        }
+        result
+    }
+}
+
+/// a public key (e.g. an agent identity) as rendered by `SecBuf::render`,
+/// checked and repaired when parsed, and always held in canonical form
+///
+/// renderings of the same key parse to the same canonical string however they were
+/// corrupted, so two PublicIdentities are equal exactly when their decoded bytes are
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PublicIdentity {
+    rendered: String,
+}
+
+impl PublicIdentity {
+    /// the decoded key bytes, backed by insecure memory
+    pub fn to_secbuf(&self) -> Result<SecBuf, SodiumError> {
+        SecBuf::insecurely_corrected(&self.rendered)
+    }
+}
+
+impl FromStr for PublicIdentity {
+    type Err = SodiumError;
+
+    /// normalize and correct `s` as `insecurely_corrected` does,
+    /// then render the result again to get the canonical form
+    fn from_str(s: &str) -> Result<Self, SodiumError> {
+        let rendered = SecBuf::insecurely_corrected(s)?.render()?;
+        Ok(PublicIdentity { rendered })
+    }
+}
+
+impl fmt::Display for PublicIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{random::random_secbuf, secbuf::ProtectState};
+
+    #[test]
+    fn it_should_render_without_outer_lock() {
+        let mut b = SecBuf::with_secure(32);
+        b.write(0, &[42; 32]).unwrap();
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        let rendered = b.render().unwrap();
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        let mut c = SecBuf::securely_corrected(&rendered).unwrap();
+        let c = c.read_lock();
+        assert_eq!(format!("{:?}", [42u8; 32].to_vec()), c.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_correct_through_provided_allocations_only() {
+        for size in [32, 64].iter() {
+            let mut b = SecBuf::with_secure(*size);
+            b.write(0, &vec![7; *size]).unwrap();
+            let rendered = b.render().unwrap();
+
+            let mut allocs = Vec::new();
+            let mut c = SecBuf::correct_with(&rendered, Base64Config::default(), |n| {
+                allocs.push(n);
+                Ok(SecBuf::with_secure(n))
+            })
+            .unwrap();
+            // one scratch buffer for the base64 bytes, one for the output
+            assert_eq!(2, allocs.len());
+            assert_eq!(*size, allocs[1]);
+
+            let c = c.read_lock();
+            assert_eq!(format!("{:?}", vec![7u8; *size]), c.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_render_keeping_prior_lock() {
+        let mut b = SecBuf::with_insecure(16);
+        let mut b = b.read_lock();
+        let rendered = b.render().unwrap();
+        assert_eq!(ProtectState::ReadOnly, b.protect_state());
+        assert_eq!(
+            rendered,
+            SecBuf::insecurely_corrected(&rendered)
+                .unwrap()
+                .render()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_round_trip_all_base64_configs() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        for config in [
+            Base64Config::Standard,
+            Base64Config::UrlSafe,
+            Base64Config::UrlSafeNoPad,
+        ]
+        .iter()
+        {
+            let rendered = b.render_with(*config).unwrap();
+            let mut c = SecBuf::insecurely_corrected_with(&rendered, *config).unwrap();
+            assert_eq!(rendered, c.render_with(*config).unwrap());
+        }
+    }
+
+    #[test]
+    fn it_should_keep_default_render_url_safe_padded() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let standard = b.render_with(Base64Config::Standard).unwrap();
+        assert_eq!(
+            standard.replace("+", "-").replace("/", "_"),
+            b.render().unwrap()
+        );
+        assert!(b.render().unwrap().ends_with('='));
+    }
+
+    #[test]
+    fn it_should_decode_padded_with_lenient_no_pad() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let padded = b.render_with(Base64Config::UrlSafe).unwrap();
+        let unpadded = b.render_with(Base64Config::UrlSafeNoPad).unwrap();
+        assert!(!unpadded.ends_with('='));
+
+        let mut c = SecBuf::insecurely_corrected_with(&padded, Base64Config::UrlSafeNoPad).unwrap();
+        assert_eq!(padded, c.render().unwrap());
+    }
+
+    #[test]
+    fn it_should_round_trip_versioned() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let rendered = b.render_versioned().unwrap();
+        assert!(rendered.starts_with('A'));
+
+        let mut c = SecBuf::corrected_versioned(&rendered).unwrap();
+        assert_eq!(32, c.len());
+        assert_eq!(b.render().unwrap(), c.render().unwrap());
+    }
+
+    #[test]
+    fn it_should_reject_unknown_version() {
+        let mut payload = [0u8; 33];
+        payload[0] = 9;
+        let enc = Encoder::new(PARITY_LEN);
+        let enc = *enc.encode(&payload[..]);
+        let rendered = base64::encode_config(&enc[..], base64::URL_SAFE);
+
+        match SecBuf::corrected_versioned(&rendered) {
+            Err(SodiumError::UnsupportedVersion(9)) => (),
+            _ => panic!("expected UnsupportedVersion(9)"),
+        }
+    }
+
+    #[test]
+    fn it_should_correct_corrupted_version_byte() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let rendered = b.render_versioned().unwrap();
+        let corrupted = format!("B{}", &rendered[1..]);
+
+        let mut c = SecBuf::corrected_versioned(&corrupted).unwrap();
+        assert_eq!(b.render().unwrap(), c.render().unwrap());
+    }
+
+    #[test]
+    fn it_should_round_trip_base58() {
+        for size in [8, 16, 32, 64].iter() {
+            let mut b = SecBuf::with_insecure(*size);
+            random_secbuf(&mut b);
+            let encoded = b.to_base58().unwrap();
+
+            let mut c = SecBuf::from_base58(&encoded).unwrap();
+            assert_eq!(encoded, c.to_base58().unwrap());
+
+            let mut c = SecBuf::securely_from_base58(&encoded).unwrap();
+            assert_eq!(ProtectState::NoAccess, c.protect_state());
+            assert_eq!(encoded, c.to_base58().unwrap());
+        }
+    }
+
+    #[test]
+    fn it_should_correct_base58_substitution() {
+        const ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+        let mut b = SecBuf::with_insecure(32);
+        b.write(0, &[42; 32]).unwrap();
+        let encoded = b.to_base58().unwrap();
+
+        // nudging the last digit by one touches at most the trailing parity bytes
+        let last = encoded.chars().last().unwrap();
+        let i = ALPHABET.find(last).unwrap();
+        let sub = ALPHABET
+            .chars()
+            .nth(if i == 0 { 1 } else { i - 1 })
+            .unwrap();
+        let corrupted = format!("{}{}", &encoded[..encoded.len() - 1], sub);
+        assert_ne!(encoded, corrupted);
+
+        let mut c = SecBuf::securely_from_base58(&corrupted).unwrap();
+        assert_eq!(encoded, c.to_base58().unwrap());
+
+        // a wrong payload digit is never decoded as some other key
+        for i in [0, 10, 20].iter() {
+            let digit = encoded.chars().nth(*i).unwrap();
+            let j = ALPHABET.find(digit).unwrap();
+            let sub = ALPHABET
+                .chars()
+                .nth(if j == 0 { 1 } else { j - 1 })
+                .unwrap();
+            let corrupted = format!("{}{}{}", &encoded[..*i], sub, &encoded[*i + 1..]);
+            assert_ne!(encoded, corrupted);
+            if let Ok(mut c) = SecBuf::from_base58(&corrupted) {
+                assert_eq!(encoded, c.to_base58().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_refuse_to_render_base58_too_large() {
+        assert!(SecBuf::with_insecure(251).to_base58().is_err());
+        assert!(SecBuf::with_insecure(250).to_base58().is_ok());
+    }
+
+    #[test]
+    fn it_should_reject_invalid_base58() {
+        match SecBuf::from_base58("0OIl") {
+            Err(SodiumError::Base58DecodeError(_)) => (),
+            _ => panic!("expected Base58DecodeError"),
+        }
+    }
+
+    #[test]
+    fn it_should_report_invalid_base64_position() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let rendered = b.render().unwrap();
+        let corrupted = format!("{}*{}", &rendered[..17], &rendered[18..]);
+
+        match SecBuf::securely_corrected(&corrupted) {
+            Err(SodiumError::Base64Decode { position, .. }) => assert_eq!(17, position),
+            _ => panic!("expected Base64Decode"),
+        }
+    }
+
+    #[test]
+    fn it_should_report_wrong_length() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let rendered = b.render().unwrap();
+        // 37 bytes render as 48 chars plus `xx==`, cutting 3 leaves a dangling char
+        let truncated = &rendered[..rendered.len() - 3];
+
+        match SecBuf::securely_corrected(truncated) {
+            Err(SodiumError::WrongLength {
+                expected_multiple,
+                got,
+                bounds: None,
+            }) => {
+                assert_eq!(4, expected_multiple);
+                assert_eq!(49, got);
+            }
+            _ => panic!("expected WrongLength"),
+        }
+
+        // well formed base64, but too short to carry the parity bytes
+        match SecBuf::securely_corrected("AAAA") {
+            Err(e @ SodiumError::WrongLength { .. }) => {
+                assert_eq!("bad encoded length 4, expected 8 to 340", e.to_string())
+            }
+            _ => panic!("expected WrongLength"),
+        }
+    }
+
+    #[test]
+    fn it_should_report_uncorrectable() {
+        let mut b = SecBuf::with_insecure(32);
+        b.write(0, &[42; 32]).unwrap();
+        let rendered = b.render().unwrap();
+        let mangled: String = rendered
+            .chars()
+            .enumerate()
+            .map(|(i, c)| match (i < 12, c) {
+                (true, 'A') => 'B',
+                (true, _) => 'A',
+                (false, c) => c,
+            })
+            .collect();
+
+        match SecBuf::securely_corrected(&mangled) {
+            Err(err @ SodiumError::Uncorrectable { .. }) => {
+                assert!(err.to_string().contains(&(CORRECTABLE + 1).to_string()));
+                assert!(std::error::Error::source(&err)
+                    .unwrap()
+                    .downcast_ref::<ReedSolomonError>()
+                    .is_some());
+            }
+            _ => panic!("expected Uncorrectable"),
+        }
+    }
+
+    #[test]
+    fn it_should_securely_decode_any_payload_length() {
+        for len in &[20, 33] {
+            let mut b = SecBuf::with_insecure(*len);
+            random_secbuf(&mut b);
+            let expected = b.read_lock().debug_unredacted();
+            let hex = b
+                .read_lock()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+            let decoded = vec![
+                SecBuf::securely_corrected(&b.render().unwrap()),
+                SecBuf::securely_corrected_with(
+                    &b.render_with(Base64Config::Standard).unwrap(),
+                    Base64Config::Standard,
+                ),
+                SecBuf::securely_corrected_exact(&b.render().unwrap(), *len),
+                SecBuf::securely_corrected_interleaved(&b.render_interleaved(3).unwrap(), 3),
+                SecBuf::from_hex_secure(&hex),
+                SecBuf::securely_from_base58(&b.to_base58().unwrap()),
+                SecBuf::securely_corrected_base32_with(
+                    &b.render_base32().unwrap(),
+                    Base32Alphabet::default(),
+                ),
+            ];
+            for d in decoded {
+                let mut d = d.unwrap();
+                assert!(d.is_secure());
+                assert_eq!(*len, d.len());
+                assert_eq!(expected, d.read_lock().debug_unredacted());
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_not_panic_decoding_arbitrary_base64() {
+        for i in 0..300 {
+            let mut b = SecBuf::with_insecure(i % 97 + 1);
+            random_secbuf(&mut b);
+            let s = {
+                let b = b.read_lock();
+                base64::encode_config(&b[..], base64::URL_SAFE)
+            };
+            let _ = SecBuf::securely_corrected(&s);
+            let _ = SecBuf::insecurely_corrected(&s);
+        }
+    }
+
+    #[test]
+    fn it_should_encode_rfc4648_base32() {
+        let rfc = Base32Alphabet::Rfc4648;
+        assert_eq!("", rfc.encode(b""));
+        assert_eq!("MY", rfc.encode(b"f"));
+        assert_eq!("MZXW6YTBOI", rfc.encode(b"foobar"));
+        assert_eq!(b"foobar".to_vec(), rfc.decode("mzxw6YTBOI").unwrap());
+    }
+
+    #[test]
+    fn it_should_round_trip_base32() {
+        for size in [32, 64].iter() {
+            let mut b = SecBuf::with_insecure(*size);
+            random_secbuf(&mut b);
+            let rendered = b.render_base32().unwrap();
+            assert!(!rendered.contains('='));
+
+            let mut c = SecBuf::corrected_base32(&rendered).unwrap();
+            assert_eq!(rendered, c.render_base32().unwrap());
+
+            let mixed: String = rendered
+                .chars()
+                .enumerate()
+                .map(|(i, c)| {
+                    if i % 2 == 0 {
+                        c.to_ascii_lowercase()
+                    } else {
+                        c
+                    }
+                })
+                .collect();
+            let mut c = SecBuf::corrected_base32(&mixed).unwrap();
+            assert_eq!(rendered, c.render_base32().unwrap());
+        }
+    }
+
+    #[test]
+    fn it_should_correct_flipped_base32_chars() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let rendered = b.render_base32().unwrap();
+
+        // chars 0 and 8 each fall entirely within a single byte (0 and 5)
+        let flip = |c: char| if c == 'A' { 'B' } else { 'A' };
+        let corrupted: String = rendered
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if i == 0 || i == 8 { flip(c) } else { c })
+            .collect();
+        assert_ne!(rendered, corrupted);
+
+        let mut c = SecBuf::corrected_base32(&corrupted).unwrap();
+        assert_eq!(rendered, c.render_base32().unwrap());
+
+        match SecBuf::corrected_base32("MZXW1") {
+            Err(SodiumError::Base32Decode { position }) => assert_eq!(4, position),
+            _ => panic!("expected Base32Decode"),
+        }
+    }
+
+    #[test]
+    fn it_should_normalize_identity_input() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let rendered = b.render().unwrap();
+
+        // whitespace and unambiguous look-alikes never reach reed-solomon
+        let typed: String = rendered
+            .chars()
+            .enumerate()
+            .flat_map(|(i, c)| {
+                let c = match c {
+                    'O' => 'О',
+                    '-' => '–',
+                    'A' => 'Ａ',
+                    c => c,
+                };
+                if i % 7 == 6 {
+                    vec![c, ' ']
+                } else {
+                    vec![c]
+                }
+            })
+            .collect();
+        let typed = format!(" {}\n\t", typed);
+        assert_eq!(rendered, normalize_identity_input(&typed));
+
+        let mut c = SecBuf::securely_corrected(&typed).unwrap();
+        assert_eq!(rendered, c.render().unwrap());
+        let mut c = SecBuf::insecurely_corrected(&typed).unwrap();
+        assert_eq!(rendered, c.render().unwrap());
+    }
+
+    #[test]
+    fn it_should_leave_ambiguous_homoglyphs_for_reed_solomon() {
+        assert_eq!("0Ol1I", normalize_identity_input("0O l1I"));
+
+        let mut b = SecBuf::with_insecure(32);
+        b.write(0, &[0; 32]).unwrap();
+        let rendered = b.render().unwrap();
+        // 32 zero bytes render as a run of 'A's, swap two of them for look-alikes
+        // chars 0 and 4 each fall within a single byte (0 and 3)
+        assert!(rendered.starts_with("AAAAAAAAAAAAAAAA"));
+        let typed = format!("0AAAl{} ", &rendered[5..]);
+
+        let mut c = SecBuf::securely_corrected(&typed).unwrap();
+        assert_eq!(rendered, c.render().unwrap());
+    }
+
+    #[test]
+    fn it_should_round_trip_crockford_base32() {
+        let crockford = Base32Alphabet::Crockford;
+        assert_eq!("CSQPYRK1E8", crockford.encode(b"foobar"));
+        assert_eq!(b"foobar".to_vec(), crockford.decode("csqp-yrkie8").unwrap());
+
+        for size in [32, 64].iter() {
+            let mut b = SecBuf::with_insecure(*size);
+            random_secbuf(&mut b);
+            let rendered = b.render_base32_with(crockford).unwrap();
+
+            let mut c = SecBuf::securely_corrected_base32_with(&rendered, crockford).unwrap();
+            assert_eq!(rendered, c.render_base32_with(crockford).unwrap());
+            let mut c = SecBuf::insecurely_corrected_base32_with(&rendered, crockford).unwrap();
+            assert_eq!(rendered, c.render_base32_with(crockford).unwrap());
+        }
+    }
+
+    #[test]
+    fn it_should_correct_ambiguous_crockford_substitution() {
+        let crockford = Base32Alphabet::Crockford;
+        let mut b = SecBuf::with_insecure(32);
+        b.write(0, &[0; 32]).unwrap();
+        let rendered = b.render_base32_with(crockford).unwrap();
+        assert!(rendered.starts_with("00000000"));
+
+        // O for 0 is mapped by the alphabet, an outright substitution is left for RS
+        let typed = format!("O{}Z{}", &rendered[1..8], &rendered[9..]);
+        let mut c = SecBuf::insecurely_corrected_base32_with(&typed, crockford).unwrap();
+        assert_eq!(rendered, c.render_base32_with(crockford).unwrap());
+
+        match SecBuf::insecurely_corrected_base32_with("CSQPU", crockford) {
+            Err(SodiumError::Base32Decode { position }) => assert_eq!(4, position),
+            _ => panic!("expected Base32Decode"),
+        }
+    }
+
+    #[test]
+    fn it_should_round_trip_interleaved() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        assert_eq!(b.render().unwrap(), b.render_interleaved(1).unwrap());
+
+        for blocks in [1, 3, 4, 7].iter() {
+            let rendered = b.render_interleaved(*blocks).unwrap();
+            let mut c = SecBuf::securely_corrected_interleaved(&rendered, *blocks).unwrap();
+            assert_eq!(rendered, c.render_interleaved(*blocks).unwrap());
+            let mut c = SecBuf::insecurely_corrected_interleaved(&rendered, *blocks).unwrap();
+            assert_eq!(rendered, c.render_interleaved(*blocks).unwrap());
+        }
+        assert!(b.render_interleaved(0).is_err());
+        assert!(b.render_interleaved(33).is_err());
+    }
+
+    #[test]
+    fn it_should_correct_burst_errors_when_interleaved() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let rendered = b.render_interleaved(4).unwrap();
+
+        // chars 16..24 are exactly bytes 12..18, a run longer than PARITY_LEN
+        // which lands at most 2 bytes in each of the 4 codewords
+        let flip = |c: char| if c == 'A' { 'B' } else { 'A' };
+        let corrupted: String = rendered
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if i / 8 == 2 { flip(c) } else { c })
+            .collect();
+
+        let mut c = SecBuf::securely_corrected_interleaved(&corrupted, 4).unwrap();
+        assert_eq!(rendered, c.render_interleaved(4).unwrap());
+    }
+
+    #[test]
+    fn it_should_round_trip_padded() {
+        let mut lengths = Vec::new();
+        for len in [1usize, 8, 17, 31].iter() {
+            let mut b = SecBuf::with_insecure(*len);
+            random_secbuf(&mut b);
+            let rendered = b.render_padded(32).unwrap();
+            lengths.push(rendered.len());
+
+            let mut c = SecBuf::insecurely_corrected_padded(&rendered, 32).unwrap();
+            assert_eq!(*len, c.len());
+            let b = b.read_lock();
+            let c = c.read_lock();
+            assert_eq!(b.debug_unredacted(), c.debug_unredacted());
+        }
+        assert!(lengths.iter().all(|l| *l == lengths[0]));
+
+        // unpadded lengths and blocks that are not a multiple of 8 suit secure memory too
+        for (len, block) in [(16usize, 24usize), (5, 16), (31, 16), (5, 12)].iter() {
+            let mut b = SecBuf::with_insecure(*len);
+            random_secbuf(&mut b);
+            let rendered = b.render_padded(*block).unwrap();
+            let mut c = SecBuf::securely_corrected_padded(&rendered, *block).unwrap();
+            assert!(c.is_secure());
+            assert_eq!(*len, c.len());
+            let b = b.read_lock();
+            let c = c.read_lock();
+            assert_eq!(b.debug_unredacted(), c.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_reject_bad_padding() {
+        // no 0x80 marker, so this was never padded
+        let mut b = SecBuf::with_insecure(16);
+        b.fill(0);
+        let rendered = b.render().unwrap();
+        match SecBuf::insecurely_corrected_padded(&rendered, 16) {
+            Err(SodiumError::InvalidPadding) => (),
+            _ => panic!("expected InvalidPadding"),
+        }
+
+        // padded to 16, but read back with a block size it is not a multiple of
+        let mut b = SecBuf::with_insecure(5);
+        b.fill(1);
+        let rendered = b.render_padded(16).unwrap();
+        assert!(SecBuf::insecurely_corrected_padded(&rendered, 12).is_err());
+        assert!(b.render_padded(0).is_err());
+        assert!(b.render_padded(std::usize::MAX).is_err());
+    }
+
+    #[test]
+    fn it_should_report_encoded_len() {
+        for len in [1, 16, 32, 64].iter() {
+            let mut b = SecBuf::with_insecure(*len);
+            b.fill(0x5a);
+            assert_eq!(len + SecBuf::PARITY_LEN, b.encoded_len());
+
+            let rendered = b.render().unwrap();
+            let decoded = base64::decode_config(&rendered, base64::URL_SAFE).unwrap();
+            assert_eq!(b.encoded_len(), decoded.len());
+        }
+    }
+
+    #[test]
+    fn it_should_correct_exact_lengths() {
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let rendered = b.render().unwrap();
+
+        let mut c = SecBuf::securely_corrected_exact(&rendered, 32).unwrap();
+        assert!(c.is_secure());
+        let mut d = SecBuf::insecurely_corrected_exact(&rendered, 32).unwrap();
+        assert!(!d.is_secure());
+        let b = b.read_lock();
+        let c = c.read_lock();
+        let d = d.read_lock();
+        assert_eq!(b.debug_unredacted(), c.debug_unredacted());
+        assert_eq!(b.debug_unredacted(), d.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_reject_unexpected_corrected_lengths() {
+        let mut b = SecBuf::with_insecure(31);
+        random_secbuf(&mut b);
+        let rendered = b.render().unwrap();
+        assert_eq!(
+            31,
+            SecBuf::securely_corrected_exact(&rendered, 31)
+                .unwrap()
+                .len()
+        );
+
+        // a wrong length is reported before the output is allocated
+        for expected in &[32, 33] {
+            match SecBuf::securely_corrected_exact(&rendered, *expected) {
+                Err(SodiumError::LengthMismatch(_)) => (),
+                _ => panic!("expected LengthMismatch"),
+            }
+        }
+        match SecBuf::insecurely_corrected_exact(&rendered, 32) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+    }
+
+    #[test]
+    fn it_should_decode_either_base64_alphabet() {
+        let mut b = SecBuf::with_insecure(32);
+        b.fill(0xfb);
+        let url_safe = b.render().unwrap();
+        let standard = b.render_with(Base64Config::Standard).unwrap();
+        assert!(url_safe.contains('-') && url_safe.contains('_'));
+        assert!(standard.contains('+') && standard.contains('/'));
+
+        for s in &[&url_safe, &standard] {
+            let mut c = SecBuf::securely_corrected(s).unwrap();
+            assert!(c.is_secure());
+            assert_eq!(
+                b.read_lock().debug_unredacted(),
+                c.read_lock().debug_unredacted()
+            );
+            let mut c = SecBuf::insecurely_corrected(s).unwrap();
+            assert_eq!(
+                b.read_lock().debug_unredacted(),
+                c.read_lock().debug_unredacted()
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_reject_mixed_base64_alphabets() {
+        let mut b = SecBuf::with_insecure(32);
+        b.fill(0xfb);
+        let url_safe = b.render().unwrap();
+        let first = url_safe.find(|c| c == '-' || c == '_').unwrap();
+        let mut mixed = url_safe.clone();
+        mixed.replace_range(first..=first, "+");
+
+        let second = mixed.find(|c| c == '-' || c == '_').unwrap();
+        match SecBuf::securely_corrected(&mixed) {
+            Err(SodiumError::Base64Decode { position, .. }) => assert_eq!(second, position),
+            _ => panic!("expected Base64Decode"),
+        }
+        assert!(SecBuf::insecurely_corrected(&mixed).is_err());
+    }
+
+    #[test]
+    fn it_should_decode_hex_into_secure_memory() {
+        let mut b = SecBuf::from_hex_secure("00ff10Ab7f80c3dE").unwrap();
+        assert!(b.is_secure());
+        assert_eq!(
+            "[0, 255, 16, 171, 127, 128, 195, 222]",
+            b.read_lock().debug_unredacted()
+        );
+        assert_eq!(0, SecBuf::from_hex_secure("").unwrap().len());
+
+        match SecBuf::from_hex_secure("00ff10ab7f80c3d") {
+            Err(SodiumError::WrongLength {
+                expected_multiple: 2,
+                got: 15,
+                bounds: None,
+            }) => (),
+            _ => panic!("expected WrongLength"),
+        }
+        match SecBuf::from_hex_secure("00ff10zb7f80c3de") {
+            Err(SodiumError::HexDecode { position }) => assert_eq!(6, position),
+            _ => panic!("expected HexDecode"),
+        }
+        match SecBuf::from_hex_secure("00ff10az7f80c3de") {
+            Err(SodiumError::HexDecode { position }) => assert_eq!(6, position),
+            _ => panic!("expected HexDecode"),
+        }
+        assert_eq!(4, SecBuf::from_hex_secure("00ff10ab").unwrap().len());
+    }
+
+    /// `s` with the chars at `at` each replaced by a different base64url char,
+    /// a char at a multiple of 4 only touches one decoded byte
+    fn corrupt(s: &str, at: &[usize]) -> String {
+        s.char_indices()
+            .map(|(i, c)| match (at.contains(&i), c) {
+                (false, _) => c,
+                (true, 'A') => 'B',
+                (true, _) => 'A',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_should_parse_a_public_identity_into_canonical_form() {
+        let mut key = SecBuf::with_insecure(32);
+        random_secbuf(&mut key);
+        let canonical = key.render().unwrap();
+
+        // one wrong char and stray whitespace are repaired
+        let typed = format!(" {} ", corrupt(&canonical, &[0]));
+        let id: PublicIdentity = typed.parse().unwrap();
+        assert_eq!(canonical, id.to_string());
+        let mut decoded = id.to_secbuf().unwrap();
+        assert!(!decoded.is_secure());
+        assert_eq!(
+            key.read_lock().debug_unredacted(),
+            decoded.read_lock().debug_unredacted()
+        );
+
+        // three wrong bytes are more than the parity can repair
+        assert!(corrupt(&canonical, &[0, 4, 8])
+            .parse::<PublicIdentity>()
+            .is_err());
+        assert!("not an identity".parse::<PublicIdentity>().is_err());
+    }
+
+    #[test]
+    fn it_should_compare_public_identities_by_decoded_bytes() {
+        let mut key = SecBuf::with_insecure(32);
+        random_secbuf(&mut key);
+        let canonical = key.render().unwrap();
+        let a: PublicIdentity = corrupt(&canonical, &[0]).parse().unwrap();
+        let b: PublicIdentity = corrupt(&canonical, &[8]).parse().unwrap();
+        let c: PublicIdentity = canonical.parse().unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a, c);
+
+        let mut other = SecBuf::with_insecure(32);
+        random_secbuf(&mut other);
+        let d: PublicIdentity = other.render().unwrap().parse().unwrap();
+        assert_ne!(a, d);
+    }
+
+    #[test]
+    fn it_should_refuse_oversized_input_before_decoding() {
+        fn assert_too_long(res: Result<SecBuf, SodiumError>, got: usize, max: usize) {
+            match res {
+                Err(SodiumError::WrongLength {
+                    got: g,
+                    bounds: Some((_, m)),
+                    ..
+                }) => {
+                    assert_eq!(got, g);
+                    assert_eq!(max, m);
+                }
+                _ => panic!("expected WrongLength"),
+            }
+        }
+        let long = "A".repeat(1 << 20);
+        assert_too_long(SecBuf::securely_corrected(&long), 1 << 20, 340);
+        assert_too_long(SecBuf::securely_corrected_exact(&long, 32), 1 << 20, 340);
+        assert_too_long(
+            SecBuf::securely_corrected_base32_with(&long, Base32Alphabet::Rfc4648),
+            1 << 20,
+            408,
+        );
+        assert_too_long(
+            SecBuf::securely_corrected_interleaved(&long, 2),
+            1 << 20,
+            680,
+        );
+        assert_too_long(
+            SecBuf::securely_from_base58(&"2".repeat(1 << 20)),
+            1 << 20,
+            349,
+        );
+
+        // the longest renderings are still accepted
+        let mut b = SecBuf::with_insecure(MAX_CODEWORD_LEN - PARITY_LEN);
+        random_secbuf(&mut b);
+        assert_eq!(340, b.render().unwrap().len());
+        assert!(SecBuf::insecurely_corrected(&b.render().unwrap()).is_ok());
+        assert!(b.render_base32().unwrap().len() <= 408);
+        assert!(SecBuf::corrected_base32(&b.render_base32().unwrap()).is_ok());
+        assert!(b.to_base58().unwrap().len() <= 349);
+        assert!(SecBuf::from_base58(&b.to_base58().unwrap()).is_ok());
+    }
+}
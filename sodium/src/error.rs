@@ -1,23 +1,499 @@
 /// Error for Sodium lib to use in your code.
 use holochain_core_types::error::HolochainError;
+use std::{error::Error, fmt, io};
 
+use crate::secbuf::{ProtectState, SecureMemoryStats};
+
+/// new variants may be added, so matches should include a wildcard arm
+///
+/// every variant has its own Display message, and `source()` returns
+/// the underlying base58 / base64 error for the variants wrapping one
+///
+/// the three length errors are kept apart by what was wrong:
+/// `OutputLength` for the output of a hash or key derivation,
+/// otherwise `SizeMismatch` for a buffer (key, seed, nonce, ...) that must be one fixed size,
+/// and `LengthMismatch` for a length outside an allowed range or set,
+/// or two lengths that must agree
 #[derive(Debug)]
 pub enum SodiumError {
     Generic(String),
+    /// a hash, kdf or pwhash output length the primitive can't produce
     OutputLength(String),
+    /// a numeric parameter (e.g. a pwhash limit) outside its bounds
+    OutOfBounds(String),
+    /// a length outside an allowed range or set, or not matching another length
+    LengthMismatch(String),
+    DecryptionFailed,
+    UnsupportedVersion(u8),
+    UnknownMnemonicWord(usize),
+    Base58DecodeError(rust_base58::base58::FromBase58Error),
+    /// the character at `position` is not valid for the base64 alphabet
+    Base64Decode {
+        position: usize,
+        source: base64::DecodeError,
+    },
+    /// any other base64 decode failure
+    Base64(base64::DecodeError),
+    /// the encoded input length must be a multiple of `expected_multiple`,
+    /// and within `bounds` (inclusive) where the encoding has a shortest and longest length
+    WrongLength {
+        expected_multiple: usize,
+        got: usize,
+        bounds: Option<(usize, usize)>,
+    },
+    /// more errors than the parity bytes can repair, `errors_detected` is a lower bound,
+    /// `source` is the reed_solomon failure when a single decode was attempted
+    Uncorrectable {
+        errors_detected: usize,
+        source: Option<ReedSolomonError>,
+    },
+    /// a buffer that must be exactly one size, e.g. a key, seed or nonce
+    SizeMismatch(String),
+    UnsupportedKeyLength(usize),
+    /// the character at `position` is not valid for the base32 alphabet
+    Base32Decode {
+        position: usize,
+    },
+    /// the character at `position` (or the pair starting there) is not valid hex
+    HexDecode {
+        position: usize,
+    },
+    PoolExhausted,
+    /// memory of the `requested` size could not be allocated,
+    /// `stats` is the secure memory usage at the time of the failure
+    AllocationFailed {
+        requested: usize,
+        stats: SecureMemoryStats,
+    },
+    /// the SecBuf was already locked in the given state
+    LockState(ProtectState),
+    /// a libsodium function returned a failure code
+    Libsodium {
+        function: &'static str,
+        code: i32,
+    },
+    /// a debug canary next to a SecBuf was overwritten
+    BufferOverflow {
+        before: bool,
+        after: bool,
+    },
+    /// the ISO/IEC 7816-4 padding of a decoded SecBuf was invalid
+    InvalidPadding,
+    /// the aead tag did not verify for the given secret, nonce and adata
+    CouldNotDecrypt,
+    /// a secretstream ended without its final chunk
+    StreamTruncated,
+    /// at least one input is required
+    EmptyInput,
+    /// an aead::NonceCounter has handed out every nonce it can
+    NonceExhausted,
+    /// a nonce counter was already at its largest value
+    CounterOverflow,
+    /// a `crypto_pwhash_str` hash string could not be parsed
+    MalformedHash,
+    /// reading or writing a file failed
+    Io(io::Error),
+    /// an aligned allocation was asked for an alignment that is not a power of two
+    InvalidAlignment(usize),
+    /// secure memory could not be allocated, so an insecure buffer was used instead
+    InsecureFallback(Box<SodiumError>),
+    /// sodium_init failed, so no libsodium function can be used
+    InitFailed,
+    /// a public key was a low order point, so the shared secret would be all zero
+    WeakKey,
+    /// no keystore secret has this name
+    KeyNotFound(String),
+    /// a keystore secret already has this name
+    KeyExists(String),
+    /// a one-time pad was all zero, so it has already been used
+    PadUsed,
+    /// compressed data could not be expanded
+    #[cfg(feature = "compression")]
+    Decompress(lz4_flex::block::DecompressError),
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// a reed_solomon decoder failure, wrapped so it can be a `source()`
+#[derive(Debug)]
+pub struct ReedSolomonError(pub reed_solomon::DecoderError);
+
+impl fmt::Display for ReedSolomonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "reed solomon decoder: {:?}", self.0)
+    }
 }
 
+impl Error for ReedSolomonError {}
+
 impl SodiumError {
     pub fn new(msg: &str) -> SodiumError {
         SodiumError::Generic(msg.to_string())
     }
 }
 
+impl fmt::Display for SodiumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SodiumError::Generic(s)
+            | SodiumError::OutputLength(s)
+            | SodiumError::OutOfBounds(s)
+            | SodiumError::LengthMismatch(s)
+            | SodiumError::SizeMismatch(s) => write!(f, "{}", s),
+            SodiumError::DecryptionFailed => write!(f, "decryption failed"),
+            SodiumError::UnsupportedVersion(v) => write!(f, "unsupported format version: {}", v),
+            SodiumError::UnknownMnemonicWord(i) => {
+                write!(f, "unknown mnemonic word at index {}", i)
+            }
+            SodiumError::Base58DecodeError(e) => write!(f, "base58 decode error: {}", e),
+            SodiumError::Base64Decode { position, source } => {
+                write!(f, "character {} is not valid ({})", position, source)
+            }
+            SodiumError::Base64(e) => write!(f, "base64 decode error: {}", e),
+            SodiumError::WrongLength {
+                got,
+                bounds: Some((min, max)),
+                ..
+            } => write!(f, "bad encoded length {}, expected {} to {}", got, min, max),
+            SodiumError::WrongLength {
+                expected_multiple,
+                got,
+                bounds: None,
+            } => write!(
+                f,
+                "bad encoded length {}, expected a multiple of {}",
+                got, expected_multiple
+            ),
+            SodiumError::Uncorrectable {
+                errors_detected, ..
+            } => write!(
+                f,
+                "uncorrectable, at least {} errors detected",
+                errors_detected
+            ),
+            SodiumError::UnsupportedKeyLength(s) => {
+                write!(f, "unsupported secure key length: {}", s)
+            }
+            SodiumError::Base32Decode { position } => {
+                write!(f, "character {} is not valid", position)
+            }
+            SodiumError::HexDecode { position } => {
+                write!(f, "character {} is not valid hex", position)
+            }
+            SodiumError::PoolExhausted => write!(f, "SecBuf pool exhausted"),
+            SodiumError::AllocationFailed { requested, stats } => write!(
+                f,
+                "secure allocation of {} bytes failed: {}",
+                requested, stats
+            ),
+            SodiumError::LockState(p) => write!(f, "SecBuf already locked: {:?}", p),
+            SodiumError::Libsodium { function, code } => {
+                write!(f, "{} failed with code {}", function, code)
+            }
+            SodiumError::BufferOverflow { before, after } => write!(
+                f,
+                "SecBuf canary overwritten: {}",
+                match (before, after) {
+                    (true, true) => "before the start and after the end",
+                    (true, false) => "before the start",
+                    _ => "after the end",
+                }
+            ),
+            SodiumError::InvalidPadding => write!(f, "invalid padding"),
+            SodiumError::CouldNotDecrypt => write!(f, "could not decrypt"),
+            SodiumError::StreamTruncated => write!(f, "stream ended without a final chunk"),
+            SodiumError::EmptyInput => write!(f, "at least one input is required"),
+            SodiumError::NonceExhausted => write!(f, "nonce counter exhausted"),
+            SodiumError::CounterOverflow => write!(f, "counter overflow"),
+            SodiumError::MalformedHash => write!(f, "malformed password hash string"),
+            SodiumError::Io(e) => write!(f, "io error: {}", e),
+            SodiumError::InvalidAlignment(a) => {
+                write!(f, "alignment {} is not a power of two", a)
+            }
+            SodiumError::InsecureFallback(e) => {
+                write!(f, "fell back to insecure memory: {}", e)
+            }
+            SodiumError::InitFailed => write!(f, "libsodium failed to initialize"),
+            SodiumError::WeakKey => write!(f, "weak (low order) public key"),
+            SodiumError::KeyNotFound(name) => write!(f, "no key named {}", name),
+            SodiumError::KeyExists(name) => write!(f, "a key named {} already exists", name),
+            SodiumError::PadUsed => write!(f, "one-time pad has already been used"),
+            #[cfg(feature = "compression")]
+            SodiumError::Decompress(e) => write!(f, "decompression failed: {}", e),
+            SodiumError::__Nonexhaustive => write!(f, "unknown sodium error"),
+        }
+    }
+}
+
+impl Error for SodiumError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SodiumError::Base58DecodeError(e) => Some(e),
+            SodiumError::Base64Decode { source, .. } => Some(source),
+            SodiumError::Base64(e) => Some(e),
+            SodiumError::Io(e) => Some(e),
+            SodiumError::InsecureFallback(e) => Some(e.as_ref()),
+            #[cfg(feature = "compression")]
+            SodiumError::Decompress(e) => Some(e),
+            SodiumError::Uncorrectable {
+                source: Some(e), ..
+            } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<base64::DecodeError> for SodiumError {
+    fn from(error: base64::DecodeError) -> Self {
+        match error {
+            base64::DecodeError::InvalidByte(position, _)
+            | base64::DecodeError::InvalidLastSymbol(position, _) => SodiumError::Base64Decode {
+                position,
+                source: error,
+            },
+            _ => SodiumError::Base64(error),
+        }
+    }
+}
+
+impl From<io::Error> for SodiumError {
+    fn from(error: io::Error) -> Self {
+        SodiumError::Io(error)
+    }
+}
+
+#[cfg(feature = "compression")]
+impl From<lz4_flex::block::DecompressError> for SodiumError {
+    fn from(error: lz4_flex::block::DecompressError) -> Self {
+        SodiumError::Decompress(error)
+    }
+}
+
+impl From<rust_base58::base58::FromBase58Error> for SodiumError {
+    fn from(error: rust_base58::base58::FromBase58Error) -> Self {
+        SodiumError::Base58DecodeError(error)
+    }
+}
+
 impl From<SodiumError> for HolochainError {
     fn from(error: SodiumError) -> Self {
-        match error {
-            SodiumError::Generic(s) => HolochainError::new(&s),
-            SodiumError::OutputLength(s) => HolochainError::new(&s),
+        HolochainError::new(&error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_display_details() {
+        assert_eq!(
+            "decryption failed",
+            SodiumError::DecryptionFailed.to_string()
+        );
+        assert_eq!(
+            "bad encoded length 49, expected a multiple of 4",
+            SodiumError::WrongLength {
+                expected_multiple: 4,
+                got: 49,
+                bounds: None,
+            }
+            .to_string()
+        );
+        assert_eq!(
+            "bad encoded length 344, expected 8 to 340",
+            SodiumError::WrongLength {
+                expected_multiple: 4,
+                got: 344,
+                bounds: Some((8, 340)),
+            }
+            .to_string()
+        );
+        assert_eq!(
+            "sodium_mlock failed with code -1",
+            SodiumError::Libsodium {
+                function: "sodium_mlock",
+                code: -1,
+            }
+            .to_string()
+        );
+        assert_eq!(
+            "SecBuf already locked: ReadOnly",
+            SodiumError::LockState(ProtectState::ReadOnly).to_string()
+        );
+        assert_eq!(
+            "unknown sodium error",
+            SodiumError::__Nonexhaustive.to_string()
+        );
+
+        let err: SodiumError = base64::DecodeError::InvalidByte(17, b'*').into();
+        assert!(err.to_string().starts_with("character 17 is not valid"));
+    }
+
+    #[test]
+    fn it_should_downcast_through_source() {
+        let err: SodiumError = base64::DecodeError::InvalidByte(3, b'*').into();
+        match err.source().unwrap().downcast_ref::<base64::DecodeError>() {
+            Some(base64::DecodeError::InvalidByte(3, b'*')) => (),
+            _ => panic!("expected the base64 error"),
+        }
+
+        let err: SodiumError = base64::DecodeError::InvalidLength.into();
+        assert_eq!(
+            Some(&base64::DecodeError::InvalidLength),
+            err.source().unwrap().downcast_ref::<base64::DecodeError>()
+        );
+
+        assert!(SodiumError::PoolExhausted.source().is_none());
+    }
+
+    #[test]
+    fn it_should_convert_to_holochain_error() {
+        let err: HolochainError = SodiumError::UnsupportedKeyLength(20).into();
+        assert_eq!(
+            HolochainError::new("unsupported secure key length: 20"),
+            err
+        );
+    }
+
+    /// one of every variant
+    fn all_variants() -> Vec<SodiumError> {
+        let mut variants = vec![
+            SodiumError::new("generic"),
+            SodiumError::OutputLength("output length".to_string()),
+            SodiumError::OutOfBounds("out of bounds".to_string()),
+            SodiumError::LengthMismatch("length mismatch".to_string()),
+            SodiumError::DecryptionFailed,
+            SodiumError::UnsupportedVersion(2),
+            SodiumError::UnknownMnemonicWord(3),
+            SodiumError::Base58DecodeError(
+                rust_base58::base58::FromBase58Error::InvalidBase58Length,
+            ),
+            base64::DecodeError::InvalidByte(4, b'*').into(),
+            base64::DecodeError::InvalidLength.into(),
+            SodiumError::WrongLength {
+                expected_multiple: 4,
+                got: 5,
+                bounds: None,
+            },
+            SodiumError::Uncorrectable {
+                errors_detected: 3,
+                source: None,
+            },
+            SodiumError::SizeMismatch("size mismatch".to_string()),
+            SodiumError::UnsupportedKeyLength(20),
+            SodiumError::Base32Decode { position: 6 },
+            SodiumError::HexDecode { position: 7 },
+            SodiumError::PoolExhausted,
+            SodiumError::AllocationFailed {
+                requested: 4096,
+                stats: crate::secbuf::secure_memory_stats(),
+            },
+            SodiumError::LockState(ProtectState::ReadOnly),
+            SodiumError::Libsodium {
+                function: "sodium_mlock",
+                code: -1,
+            },
+            SodiumError::BufferOverflow {
+                before: false,
+                after: true,
+            },
+            SodiumError::InvalidPadding,
+            SodiumError::CouldNotDecrypt,
+            SodiumError::StreamTruncated,
+            SodiumError::EmptyInput,
+            SodiumError::NonceExhausted,
+            SodiumError::CounterOverflow,
+            SodiumError::MalformedHash,
+            io::Error::new(io::ErrorKind::NotFound, "no key file").into(),
+            SodiumError::InvalidAlignment(3),
+            SodiumError::InsecureFallback(Box::new(SodiumError::PoolExhausted)),
+            SodiumError::InitFailed,
+            SodiumError::WeakKey,
+            SodiumError::KeyNotFound("seed".to_string()),
+            SodiumError::KeyExists("seed".to_string()),
+            SodiumError::PadUsed,
+        ];
+        #[cfg(feature = "compression")]
+        variants.push(lz4_flex::block::decompress(&[0xff], 16).unwrap_err().into());
+        variants
+    }
+
+    #[test]
+    fn it_should_display_every_variant_distinctly() {
+        let messages: Vec<String> = all_variants().iter().map(|e| e.to_string()).collect();
+        assert!(messages.iter().all(|m| !m.is_empty()));
+        let mut unique = messages.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(messages.len(), unique.len());
+        assert!(SodiumError::AllocationFailed {
+            requested: 4096,
+            stats: crate::secbuf::secure_memory_stats(),
+        }
+        .to_string()
+        .contains("4096"));
+    }
+
+    #[test]
+    fn it_should_have_a_source_only_for_wrapped_variants() {
+        for err in all_variants() {
+            let wrapped = match err {
+                SodiumError::Base58DecodeError(_)
+                | SodiumError::Base64Decode { .. }
+                | SodiumError::Base64(_)
+                | SodiumError::Io(_)
+                | SodiumError::InsecureFallback(_)
+                | SodiumError::Uncorrectable {
+                    source: Some(_), ..
+                } => true,
+                #[cfg(feature = "compression")]
+                SodiumError::Decompress(_) => true,
+                _ => false,
+            };
+            assert_eq!(wrapped, err.source().is_some(), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn it_should_be_a_thread_safe_std_error() {
+        fn assert_error<E: Error + Send + Sync + 'static>() {}
+        assert_error::<SodiumError>();
+    }
+
+    /// an application error wrapping a SodiumError, as `anyhow` / `thiserror` would
+    #[derive(Debug)]
+    struct LoadKeyError(SodiumError);
+
+    impl fmt::Display for LoadKeyError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "could not load key")
+        }
+    }
+
+    impl Error for LoadKeyError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn it_should_chain_to_the_original_error() {
+        let top = LoadKeyError(base64::DecodeError::InvalidByte(9, b'#').into());
+        let mut chain = vec![];
+        let mut next: Option<&(dyn Error + 'static)> = Some(&top);
+        while let Some(e) = next {
+            chain.push(e.to_string());
+            next = e.source();
+            if next.is_none() {
+                assert_eq!(
+                    Some(&base64::DecodeError::InvalidByte(9, b'#')),
+                    e.downcast_ref::<base64::DecodeError>()
+                );
+            }
         }
+        assert_eq!(3, chain.len());
+        assert_eq!("could not load key", chain[0]);
+        assert!(chain[1].starts_with("character 9 is not valid"));
     }
 }
@@ -9,13 +9,269 @@ pub const BYTES256: usize = rust_sodium_sys::crypto_hash_sha256_BYTES as usize;
 /// Size of return value while converting to sha512
 pub const BYTES512: usize = rust_sodium_sys::crypto_hash_sha512_BYTES as usize;
 
+/// The range of BLAKE2b output sizes
+pub const GENERICHASH_BYTES_MIN: usize = rust_sodium_sys::crypto_generichash_BYTES_MIN as usize;
+pub const GENERICHASH_BYTES_MAX: usize = rust_sodium_sys::crypto_generichash_BYTES_MAX as usize;
+
+/// The range of BLAKE2b key sizes
+pub const GENERICHASH_KEYBYTES_MIN: usize =
+    rust_sodium_sys::crypto_generichash_KEYBYTES_MIN as usize;
+pub const GENERICHASH_KEYBYTES_MAX: usize =
+    rust_sodium_sys::crypto_generichash_KEYBYTES_MAX as usize;
+
+fn check_generichash_params(key_len: Option<usize>, out_len: usize) -> Result<(), SodiumError> {
+    if out_len < GENERICHASH_BYTES_MIN || out_len > GENERICHASH_BYTES_MAX {
+        return Err(SodiumError::OutputLength(format!(
+            "Invalid hash length:{}",
+            out_len
+        )));
+    }
+
+    match key_len {
+        Some(l) if l < GENERICHASH_KEYBYTES_MIN || l > GENERICHASH_KEYBYTES_MAX => {
+            Err(SodiumError::LengthMismatch(format!(
+                "hash key must be {} to {} bytes, got {}",
+                GENERICHASH_KEYBYTES_MIN, GENERICHASH_KEYBYTES_MAX, l
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// BLAKE2b of `input` into `out`, keyed if `key` is given
+fn generichash_into(out: &mut [u8], input: &[u8], key: Option<&[u8]>) -> Result<(), SodiumError> {
+    check_init();
+    check_generichash_params(key.map(|k| k.len()), out.len())?;
+    let key = key.unwrap_or(&[]);
+    unsafe {
+        rust_sodium_sys::crypto_generichash(
+            raw_ptr_char!(out),
+            out.len(),
+            raw_ptr_char_immut!(input),
+            input.len() as libc::c_ulonglong,
+            if key.is_empty() {
+                std::ptr::null()
+            } else {
+                raw_ptr_char_immut!(key)
+            },
+            key.len(),
+        );
+    }
+    Ok(())
+}
+
+/// BLAKE2b of `input` in a new insecure SecBuf, keyed if `key` is given
+fn generichash(input: &[u8], key: Option<&[u8]>, out_len: usize) -> Result<SecBuf, SodiumError> {
+    check_generichash_params(key.map(|k| k.len()), out_len)?;
+    let mut out = SecBuf::with_insecure(out_len);
+    {
+        let mut out = out.write_lock();
+        generichash_into(&mut out, input, key)?;
+    }
+    Ok(out)
+}
+
+/// a keyed hash is only a MAC while its key stays secret
+fn check_mac_key(key: &SecBuf) -> Result<(), SodiumError> {
+    if !key.is_secure() {
+        return Err(SodiumError::new("hash key must be backed by secure memory"));
+    }
+    Ok(())
+}
+
+/// BLAKE2b of `input` into `output`, whose length (16 to 64 bytes) sets the hash size
+///
+/// with a key this is a MAC, so the key must be backed by secure memory,
+/// the output may be insecure as hashes are not secret
+///
+/// @param {SecBuf} output - GENERICHASH_BYTES_MIN to GENERICHASH_BYTES_MAX long
+///
+/// @param {SecBuf} input - the data to hash
+///
+/// @param {SecBuf} key - optional secure key, GENERICHASH_KEYBYTES_MIN to MAX long
+pub fn blake2b(
+    output: &mut SecBuf,
+    input: &mut SecBuf,
+    key: Option<&mut SecBuf>,
+) -> Result<(), SodiumError> {
+    let key_len = key.as_ref().map(|k| k.len());
+    check_generichash_params(key_len, output.len())?;
+    let mut output = output.write_lock();
+    let input = input.read_lock();
+    match key {
+        Some(key) => {
+            check_mac_key(key)?;
+            let key = key.read_lock();
+            generichash_into(&mut output, &input, Some(&key[..]))
+        }
+        None => generichash_into(&mut output, &input, None),
+    }
+}
+
+/// Incremental BLAKE2b, for large entries that are hashed in pieces
+pub struct Blake2bState {
+    state: rust_sodium_sys::crypto_generichash_state,
+    out_len: usize,
+}
+
+impl Blake2bState {
+    /// start a hash producing `out_len` bytes, a MAC if `key` is given
+    ///
+    /// @param {SecBuf} key - optional secure key, 16 to 64 bytes
+    pub fn init(out_len: usize, key: Option<&mut SecBuf>) -> Result<Self, SodiumError> {
+        check_init();
+        let mut hash = Blake2bState {
+            state: Default::default(),
+            out_len,
+        };
+        match key {
+            Some(key) => {
+                check_generichash_params(Some(key.len()), out_len)?;
+                check_mac_key(key)?;
+                let key = key.read_lock();
+                unsafe {
+                    rust_sodium_sys::crypto_generichash_init(
+                        &mut hash.state,
+                        raw_ptr_char_immut!(key),
+                        key.len(),
+                        out_len,
+                    );
+                }
+            }
+            None => {
+                check_generichash_params(None, out_len)?;
+                unsafe {
+                    rust_sodium_sys::crypto_generichash_init(
+                        &mut hash.state,
+                        std::ptr::null(),
+                        0,
+                        out_len,
+                    );
+                }
+            }
+        }
+        Ok(hash)
+    }
+
+    /// feed `input` into the hash
+    pub fn update(&mut self, input: &[u8]) {
+        unsafe {
+            rust_sodium_sys::crypto_generichash_update(
+                &mut self.state,
+                raw_ptr_char_immut!(input),
+                input.len() as libc::c_ulonglong,
+            );
+        }
+    }
+
+    /// feed the contents of `input` into the hash
+    pub fn update_secbuf(&mut self, input: &mut SecBuf) {
+        let input = input.read_lock();
+        self.update(&input);
+    }
+
+    /// finish the hash into `out`, which must be the `out_len` given to `init`
+    pub fn finalize(mut self, out: &mut SecBuf) -> Result<(), SodiumError> {
+        if out.len() != self.out_len {
+            return Err(SodiumError::OutputLength(format!(
+                "hash output must be a Buffer of length: {}, got {}.",
+                self.out_len,
+                out.len()
+            )));
+        }
+        let mut out = out.write_lock();
+        unsafe {
+            rust_sodium_sys::crypto_generichash_final(
+                &mut self.state,
+                raw_ptr_char!(out),
+                self.out_len,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// the state of a keyed hash is derived from the key
+impl Drop for Blake2bState {
+    fn drop(&mut self) {
+        zero_state(&mut self.state);
+    }
+}
+
+impl SecBuf {
+    /// BLAKE2b hash of this SecBuf, `out_len` must be within
+    /// GENERICHASH_BYTES_MIN and GENERICHASH_BYTES_MAX
+    ///
+    /// @UseReturn {SecBuf} - the hash, insecure and NoAccess
+    pub fn hash(&mut self, out_len: usize) -> Result<SecBuf, SodiumError> {
+        let input = self.read_lock();
+        generichash(&input, None, out_len)
+    }
+
+    /// keyed BLAKE2b hash of this SecBuf
+    ///
+    /// @param {SecBuf} key - secure, 16 to 64 bytes
+    ///
+    /// @UseReturn {SecBuf} - the hash, insecure and NoAccess
+    pub fn keyed_hash(&mut self, key: &mut SecBuf, out_len: usize) -> Result<SecBuf, SodiumError> {
+        check_mac_key(key)?;
+        let input = self.read_lock();
+        let key = key.read_lock();
+        generichash(&input, Some(&key[..]), out_len)
+    }
+
+    /// BLAKE2b hash of the contents of `inputs` in order, as if they were concatenated,
+    /// streamed through a `Blake2bState` so nothing is copied
+    ///
+    /// @UseReturn {SecBuf} - the hash, insecure and NoAccess, EmptyInput if there are no inputs
+    pub fn multi_hash(inputs: &mut [&mut SecBuf], out_len: usize) -> Result<SecBuf, SodiumError> {
+        if inputs.is_empty() {
+            return Err(SodiumError::EmptyInput);
+        }
+        let mut hash = Blake2bState::init(out_len, None)?;
+        for input in inputs.iter_mut() {
+            hash.update_secbuf(input);
+        }
+        let mut out = SecBuf::with_insecure(out_len);
+        hash.finalize(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// hashing secrets (e.g. a seed) leaves them in the sha2 block buffer
+fn zero_state<T>(state: &mut T) {
+    unsafe {
+        rust_sodium_sys::sodium_memzero(
+            state as *mut T as *mut libc::c_void,
+            std::mem::size_of::<T>(),
+        );
+    }
+}
+
+/// sha2 digests are a fixed size, so `output` must be exactly `bytes` long
+fn check_sha_output(output: &SecBuf, bytes: usize) -> Result<(), SodiumError> {
+    if output.len() != bytes {
+        return Err(SodiumError::OutputLength(format!(
+            "sha output must be a Buffer of length: {}, got {}.",
+            bytes,
+            output.len()
+        )));
+    }
+    Ok(())
+}
+
 /// Compute the sha256 hash of input buffer
+///
+/// unlike `blake2b` this takes `input` first, it predates the blake2b
+/// functions and callers (e.g. conductor_api's test key loader) rely on it,
+/// both arguments are SecBufs so swapping them would not fail to compile
 /// ****
 /// @param {SecBuf} input - the data to hash
 ///
-/// @param {SecBuf} output - Empty Buffer to be used as output
+/// @param {SecBuf} output - Empty Buffer to be used as output, BYTES256 long
 pub fn sha256(input: &mut SecBuf, output: &mut SecBuf) -> Result<(), SodiumError> {
     check_init();
+    check_sha_output(output, BYTES256)?;
     let input_len = input.len() as libc::c_ulonglong;
     let input = input.read_lock();
     let mut output = output.write_lock();
@@ -29,13 +285,14 @@ pub fn sha256(input: &mut SecBuf, output: &mut SecBuf) -> Result<(), SodiumError
     Ok(())
 }
 
-/// Compute the sha512 hash of input buffer
+/// Compute the sha512 hash of input buffer, `input` first as for `sha256`
 /// ****
 /// @param {Buffer} input - the data to hash
 ///
-/// @param {SecBuf} output - Empty Buffer to be used as output
+/// @param {SecBuf} output - Empty Buffer to be used as output, BYTES512 long
 pub fn sha512(input: &mut SecBuf, output: &mut SecBuf) -> Result<(), SodiumError> {
     check_init();
+    check_sha_output(output, BYTES512)?;
     let input = input.read_lock();
     let mut output = output.write_lock();
     let input_len = input.len() as libc::c_ulonglong;
@@ -49,9 +306,112 @@ pub fn sha512(input: &mut SecBuf, output: &mut SecBuf) -> Result<(), SodiumError
     Ok(())
 }
 
+/// Incremental sha256, for files and other data hashed chunk by chunk
+pub struct Sha256State {
+    state: rust_sodium_sys::crypto_hash_sha256_state,
+}
+
+impl Sha256State {
+    pub fn init() -> Self {
+        check_init();
+        let mut hash = Sha256State {
+            state: Default::default(),
+        };
+        unsafe {
+            rust_sodium_sys::crypto_hash_sha256_init(&mut hash.state);
+        }
+        hash
+    }
+
+    /// feed `input` into the hash
+    pub fn update(&mut self, input: &[u8]) {
+        unsafe {
+            rust_sodium_sys::crypto_hash_sha256_update(
+                &mut self.state,
+                raw_ptr_char_immut!(input),
+                input.len() as libc::c_ulonglong,
+            );
+        }
+    }
+
+    /// feed the contents of `input` into the hash
+    pub fn update_secbuf(&mut self, input: &mut SecBuf) {
+        let input = input.read_lock();
+        self.update(&input);
+    }
+
+    /// finish the hash into `out`, which must be BYTES256 long
+    pub fn finalize(mut self, out: &mut SecBuf) -> Result<(), SodiumError> {
+        check_sha_output(out, BYTES256)?;
+        let mut out = out.write_lock();
+        unsafe {
+            rust_sodium_sys::crypto_hash_sha256_final(&mut self.state, raw_ptr_char!(out));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Sha256State {
+    fn drop(&mut self) {
+        zero_state(&mut self.state);
+    }
+}
+
+/// Incremental sha512, for files and other data hashed chunk by chunk
+pub struct Sha512State {
+    state: rust_sodium_sys::crypto_hash_sha512_state,
+}
+
+impl Sha512State {
+    pub fn init() -> Self {
+        check_init();
+        let mut hash = Sha512State {
+            state: Default::default(),
+        };
+        unsafe {
+            rust_sodium_sys::crypto_hash_sha512_init(&mut hash.state);
+        }
+        hash
+    }
+
+    /// feed `input` into the hash
+    pub fn update(&mut self, input: &[u8]) {
+        unsafe {
+            rust_sodium_sys::crypto_hash_sha512_update(
+                &mut self.state,
+                raw_ptr_char_immut!(input),
+                input.len() as libc::c_ulonglong,
+            );
+        }
+    }
+
+    /// feed the contents of `input` into the hash
+    pub fn update_secbuf(&mut self, input: &mut SecBuf) {
+        let input = input.read_lock();
+        self.update(&input);
+    }
+
+    /// finish the hash into `out`, which must be BYTES512 long
+    pub fn finalize(mut self, out: &mut SecBuf) -> Result<(), SodiumError> {
+        check_sha_output(out, BYTES512)?;
+        let mut out = out.write_lock();
+        unsafe {
+            rust_sodium_sys::crypto_hash_sha512_final(&mut self.state, raw_ptr_char!(out));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Sha512State {
+    fn drop(&mut self) {
+        zero_state(&mut self.state);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{random::random_secbuf, secbuf::ProtectState};
     #[test]
     fn it_should_sha256() {
         let mut input = SecBuf::with_insecure(2);
@@ -65,7 +425,7 @@ mod tests {
             sha256(&mut input, &mut output).unwrap();
         }
         let output = output.read_lock();
-        assert_eq!("[193, 152, 204, 150, 33, 27, 103, 169, 2, 6, 174, 153, 35, 55, 117, 177, 84, 115, 121, 1, 166, 185, 242, 227, 116, 245, 129, 11, 9, 35, 188, 36]", format!("{:?}", *output));
+        assert_eq!("[193, 152, 204, 150, 33, 27, 103, 169, 2, 6, 174, 153, 35, 55, 117, 177, 84, 115, 121, 1, 166, 185, 242, 227, 116, 245, 129, 11, 9, 35, 188, 36]", output.debug_unredacted());
     }
 
     #[test]
@@ -81,6 +441,319 @@ mod tests {
             sha512(&mut input, &mut output).unwrap();
         }
         let output = output.write_lock();
-        assert_eq!("[7, 117, 152, 125, 243, 201, 32, 78, 241, 175, 174, 114, 145, 29, 183, 142, 198, 91, 47, 209, 111, 35, 223, 28, 65, 246, 126, 147, 48, 171, 241, 88, 26, 108, 130, 55, 221, 6, 221, 45, 125, 138, 41, 184, 144, 190, 203, 31, 96, 247, 207, 176, 74, 129, 12, 29, 134, 172, 216, 180, 31, 1, 61, 59]", format!("{:?}", *output));
+        assert_eq!("[7, 117, 152, 125, 243, 201, 32, 78, 241, 175, 174, 114, 145, 29, 183, 142, 198, 91, 47, 209, 111, 35, 223, 28, 65, 246, 126, 147, 48, 171, 241, 88, 26, 108, 130, 55, 221, 6, 221, 45, 125, 138, 41, 184, 144, 190, 203, 31, 96, 247, 207, 176, 74, 129, 12, 29, 134, 172, 216, 180, 31, 1, 61, 59]", output.debug_unredacted());
+    }
+
+    fn render(b: &mut SecBuf) -> String {
+        let b = b.read_lock();
+        b.debug_unredacted()
+    }
+
+    #[test]
+    fn it_should_blake2b_deterministically() {
+        let mut input = SecBuf::with_insecure(64);
+        random_secbuf(&mut input);
+        let mut a = input.hash(32).unwrap();
+        let mut b = input.hash(32).unwrap();
+        assert_eq!(32, a.len());
+        assert_eq!(ProtectState::NoAccess, a.protect_state());
+        assert!(!a.is_secure());
+        assert_eq!(render(&mut a), render(&mut b));
+
+        // avalanche: one flipped bit changes the output
+        {
+            let mut input = input.write_lock();
+            input[17] ^= 0x01;
+        }
+        let mut c = input.hash(32).unwrap();
+        assert_ne!(render(&mut a), render(&mut c));
+    }
+
+    #[test]
+    fn it_should_match_blake2b_vector() {
+        let mut input = SecBuf::with_insecure(3);
+        input.write(0, b"abc").unwrap();
+        let mut out = input.hash(64).unwrap();
+        let out = out.read_lock();
+        let hex: String = out.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+            hex
+        );
+    }
+
+    #[test]
+    fn it_should_keyed_hash() {
+        let mut input = SecBuf::with_insecure(64);
+        random_secbuf(&mut input);
+        let mut key1 = SecBuf::with_secure(32);
+        random_secbuf(&mut key1);
+        let mut key2 = SecBuf::with_secure(32);
+        random_secbuf(&mut key2);
+
+        let mut a = input.keyed_hash(&mut key1, 32).unwrap();
+        let mut b = input.keyed_hash(&mut key1, 32).unwrap();
+        let mut c = input.keyed_hash(&mut key2, 32).unwrap();
+        let mut d = input.hash(32).unwrap();
+        assert_eq!(render(&mut a), render(&mut b));
+        assert_ne!(render(&mut a), render(&mut c));
+        assert_ne!(render(&mut a), render(&mut d));
+    }
+
+    #[test]
+    fn it_should_stream_hash() {
+        let mut input = SecBuf::with_insecure(64);
+        random_secbuf(&mut input);
+        let mut key = SecBuf::with_secure(32);
+        random_secbuf(&mut key);
+
+        let (mut first, mut second) = (SecBuf::with_insecure(24), SecBuf::with_insecure(40));
+        {
+            let input = input.read_lock();
+            first.write(0, &input[..24]).unwrap();
+            second.write(0, &input[24..]).unwrap();
+        }
+
+        let mut state = Blake2bState::init(48, None).unwrap();
+        state.update_secbuf(&mut first);
+        state.update_secbuf(&mut second);
+        let mut streamed = SecBuf::with_insecure(48);
+        state.finalize(&mut streamed).unwrap();
+        assert_eq!(render(&mut input.hash(48).unwrap()), render(&mut streamed));
+
+        let mut state = Blake2bState::init(32, Some(&mut key)).unwrap();
+        state.update_secbuf(&mut first);
+        state.update_secbuf(&mut second);
+        let mut streamed = SecBuf::with_insecure(32);
+        state.finalize(&mut streamed).unwrap();
+        assert_eq!(
+            render(&mut input.keyed_hash(&mut key, 32).unwrap()),
+            render(&mut streamed)
+        );
+    }
+
+    #[test]
+    fn it_should_reject_bad_hash_lengths() {
+        let mut input = SecBuf::with_insecure(8);
+        match input.hash(8) {
+            Err(SodiumError::OutputLength(_)) => (),
+            _ => panic!("expected OutputLength"),
+        }
+        assert!(input.hash(65).is_err());
+        let mut short_key = SecBuf::with_secure(8);
+        match input.keyed_hash(&mut short_key, 32) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+        assert!(Blake2bState::init(100, None).is_err());
+    }
+
+    #[test]
+    fn it_should_multi_hash_like_the_concatenation() {
+        let mut a = SecBuf::with_insecure(32);
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut a);
+        random_secbuf(&mut b);
+        let mut concat = SecBuf::with_insecure(64);
+        concat.write(0, &a.read_lock()).unwrap();
+        concat.write(32, &b.read_lock()).unwrap();
+
+        let mut ab = SecBuf::multi_hash(&mut [&mut a, &mut b], 32).unwrap();
+        let mut ba = SecBuf::multi_hash(&mut [&mut b, &mut a], 32).unwrap();
+        let mut expected = concat.hash(32).unwrap();
+        assert_eq!(ProtectState::NoAccess, ab.protect_state());
+        assert_eq!(
+            expected.read_lock().debug_unredacted(),
+            ab.read_lock().debug_unredacted()
+        );
+        assert_ne!(
+            ab.read_lock().debug_unredacted(),
+            ba.read_lock().debug_unredacted()
+        );
+
+        for len in &[GENERICHASH_BYTES_MIN, GENERICHASH_BYTES_MAX] {
+            let h = SecBuf::multi_hash(&mut [&mut a, &mut b], *len).unwrap();
+            assert_eq!(*len, h.len());
+        }
+        assert!(SecBuf::multi_hash(&mut [&mut a], GENERICHASH_BYTES_MAX + 1).is_err());
+        match SecBuf::multi_hash(&mut [], 32) {
+            Err(SodiumError::EmptyInput) => (),
+            _ => panic!("expected EmptyInput"),
+        }
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn secbuf_from(bytes: &[u8], secure: bool) -> SecBuf {
+        let mut b = if secure {
+            SecBuf::with_secure(bytes.len())
+        } else {
+            SecBuf::with_insecure(bytes.len())
+        };
+        b.write(0, bytes).unwrap();
+        b
+    }
+
+    #[test]
+    fn it_should_match_official_blake2b_vectors() {
+        // RFC 7693 appendix A, and the empty message
+        for (input, expected) in &[
+            (&b"abc"[..], "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"),
+            (&b""[..], "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce"),
+        ] {
+            let mut input = secbuf_from(input, false);
+            let mut out = SecBuf::with_insecure(64);
+            blake2b(&mut out, &mut input, None).unwrap();
+            assert_eq!(format!("{:?}", hex(expected)), render(&mut out));
+        }
+
+        // blake2b-kat.txt from the reference implementation, keyed with 00..3f
+        let key: Vec<u8> = (0..64).collect();
+        let mut key = secbuf_from(&key, true);
+        for (input, expected) in &[
+            (&b""[..], "10ebb67700b1868efb4417987acf4690ae9d972fb7a590c2f02871799aaa4786b5e996e8f0f4eb981fc214b005f42d2ff4233499391653df7aefcbc13fc51568"),
+            (&b"\x00"[..], "961f6dd1e4dd30f63901690c512e78e4b45e4742ed197c3c5e45c549fd25f2e4187b0bc9fe30492b16b0d0bc4ef9b0f34c7003fac09a5ef1532e69430234cebd"),
+        ] {
+            let mut input = secbuf_from(input, false);
+            let mut out = SecBuf::with_insecure(64);
+            blake2b(&mut out, &mut input, Some(&mut key)).unwrap();
+            assert_eq!(format!("{:?}", hex(expected)), render(&mut out));
+        }
+    }
+
+    #[test]
+    fn it_should_blake2b_keyed_and_unkeyed_differently() {
+        let mut input = SecBuf::with_insecure(100);
+        random_secbuf(&mut input);
+        let mut key = SecBuf::with_secure(32);
+        random_secbuf(&mut key);
+        let mut keyed = SecBuf::with_insecure(32);
+        let mut unkeyed = SecBuf::with_insecure(32);
+        blake2b(&mut keyed, &mut input, Some(&mut key)).unwrap();
+        blake2b(&mut unkeyed, &mut input, None).unwrap();
+        assert_ne!(render(&mut keyed), render(&mut unkeyed));
+        assert_eq!(render(&mut input.hash(32).unwrap()), render(&mut unkeyed));
+
+        let mut insecure_key = SecBuf::with_insecure(32);
+        assert!(blake2b(&mut keyed, &mut input, Some(&mut insecure_key)).is_err());
+        assert!(Blake2bState::init(32, Some(&mut insecure_key)).is_err());
+        let mut short = SecBuf::with_insecure(8);
+        match blake2b(&mut short, &mut input, None) {
+            Err(SodiumError::OutputLength(_)) => (),
+            _ => panic!("expected OutputLength"),
+        }
+    }
+
+    #[test]
+    fn it_should_stream_blake2b_like_the_one_shot() {
+        let mut input = SecBuf::with_insecure(1000);
+        random_secbuf(&mut input);
+        let mut key = SecBuf::with_secure(64);
+        random_secbuf(&mut key);
+        let mut one_shot = SecBuf::with_insecure(16);
+        blake2b(&mut one_shot, &mut input, Some(&mut key)).unwrap();
+
+        let mut state = Blake2bState::init(16, Some(&mut key)).unwrap();
+        {
+            let input = input.read_lock();
+            for chunk in input.chunks(128) {
+                state.update(chunk);
+            }
+        }
+        let mut streamed = SecBuf::with_insecure(16);
+        state.finalize(&mut streamed).unwrap();
+        assert_eq!(render(&mut one_shot), render(&mut streamed));
+
+        let state = Blake2bState::init(16, None).unwrap();
+        match state.finalize(&mut SecBuf::with_insecure(32)) {
+            Err(SodiumError::OutputLength(_)) => (),
+            _ => panic!("expected OutputLength"),
+        }
+    }
+
+    #[test]
+    fn it_should_match_nist_sha2_vectors() {
+        let million_a = vec![b'a'; 1_000_000];
+        let vectors: Vec<(&[u8], &str, &str)> = vec![
+            (&b""[..], "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+             "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"),
+            (&b"abc"[..], "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+             "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"),
+            (&million_a[..], "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+             "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973ebde0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b"),
+        ];
+        for (input, expected256, expected512) in vectors {
+            let mut input = secbuf_from(input, false);
+            let mut out256 = SecBuf::with_insecure(BYTES256);
+            let mut out512 = SecBuf::with_insecure(BYTES512);
+            sha256(&mut input, &mut out256).unwrap();
+            sha512(&mut input, &mut out512).unwrap();
+            assert_eq!(format!("{:?}", hex(expected256)), render(&mut out256));
+            assert_eq!(format!("{:?}", hex(expected512)), render(&mut out512));
+        }
+    }
+
+    #[test]
+    fn it_should_stream_sha2_like_the_one_shot() {
+        let mut input = SecBuf::with_insecure(1000);
+        random_secbuf(&mut input);
+        let mut one_shot256 = SecBuf::with_insecure(BYTES256);
+        let mut one_shot512 = SecBuf::with_insecure(BYTES512);
+        sha256(&mut input, &mut one_shot256).unwrap();
+        sha512(&mut input, &mut one_shot512).unwrap();
+
+        let mut state256 = Sha256State::init();
+        let mut state512 = Sha512State::init();
+        {
+            let input = input.read_lock();
+            for chunk in input.chunks(77) {
+                state256.update(chunk);
+                state512.update(chunk);
+            }
+        }
+        let mut streamed256 = SecBuf::with_insecure(BYTES256);
+        let mut streamed512 = SecBuf::with_insecure(BYTES512);
+        state256.finalize(&mut streamed256).unwrap();
+        state512.finalize(&mut streamed512).unwrap();
+        assert_eq!(render(&mut one_shot256), render(&mut streamed256));
+        assert_eq!(render(&mut one_shot512), render(&mut streamed512));
+
+        let mut state = Sha256State::init();
+        state.update_secbuf(&mut input);
+        let mut streamed = SecBuf::with_insecure(BYTES256);
+        state.finalize(&mut streamed).unwrap();
+        assert_eq!(render(&mut one_shot256), render(&mut streamed));
+    }
+
+    #[test]
+    fn it_should_reject_bad_sha2_output_lengths() {
+        let mut input = SecBuf::with_insecure(8);
+        for bad in &[0, 31, 33, 64] {
+            match sha256(&mut input, &mut SecBuf::with_insecure(*bad)) {
+                Err(SodiumError::OutputLength(_)) => (),
+                _ => panic!("expected OutputLength"),
+            }
+        }
+        assert!(sha512(&mut input, &mut SecBuf::with_insecure(BYTES256)).is_err());
+        assert!(Sha512State::init()
+            .finalize(&mut SecBuf::with_insecure(BYTES256))
+            .is_err());
+    }
+
+    #[test]
+    fn it_should_refuse_an_insecure_keyed_hash_key() {
+        let mut input = SecBuf::with_insecure(8);
+        let mut key = SecBuf::with_insecure(32);
+        random_secbuf(&mut key);
+        assert!(input.keyed_hash(&mut key, 32).is_err());
+        let mut key = SecBuf::with_secure(32);
+        random_secbuf(&mut key);
+        assert!(input.keyed_hash(&mut key, 32).is_ok());
     }
 }
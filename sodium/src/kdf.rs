@@ -6,88 +6,424 @@ use crate::error::SodiumError;
 pub const CONTEXTBYTES: usize = rust_sodium_sys::crypto_kdf_CONTEXTBYTES as usize;
 pub const MINBYTES: usize = rust_sodium_sys::crypto_kdf_BYTES_MIN as usize;
 pub const MAXBYTES: usize = rust_sodium_sys::crypto_kdf_BYTES_MAX as usize;
+pub const KEYBYTES: usize = rust_sodium_sys::crypto_kdf_KEYBYTES as usize;
 
-/// Derive a subkey from a parent key
+/// The eight byte context that keeps subkeys derived for different purposes
+/// (e.g. device, application and revocation seeds) apart
+///
+/// a context of any other length would be silently truncated or padded by libsodium,
+/// so they can only be built from exactly CONTEXTBYTES
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct KdfContext([u8; CONTEXTBYTES]);
+
+/// the context of the keystore's device seeds
+pub const CONTEXT_DEVICE: KdfContext = KdfContext::from_bytes(*b"hcdevice");
+/// the context of the keystore's revocation seeds
+pub const CONTEXT_REVOKE: KdfContext = KdfContext::from_bytes(*b"hcrevoke");
+/// the context of the keystore's application seeds
+pub const CONTEXT_APP: KdfContext = KdfContext::from_bytes(*b"hcappkey");
+
+impl KdfContext {
+    /// a context from a string of exactly CONTEXTBYTES ASCII characters
+    pub fn new(context: &str) -> Result<Self, SodiumError> {
+        if context.len() != CONTEXTBYTES {
+            return Err(SodiumError::SizeMismatch(format!(
+                "kdf context must be {} ASCII characters, got {} bytes",
+                CONTEXTBYTES,
+                context.len()
+            )));
+        }
+        if !context.is_ascii() {
+            return Err(SodiumError::new("kdf context must be ASCII"));
+        }
+        let mut bytes = [0u8; CONTEXTBYTES];
+        bytes.copy_from_slice(context.as_bytes());
+        Ok(KdfContext(bytes))
+    }
+
+    /// for compile time constants such as `CONTEXT_DEVICE`
+    pub const fn from_bytes(bytes: [u8; CONTEXTBYTES]) -> Self {
+        KdfContext(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; CONTEXTBYTES] {
+        &self.0
+    }
+}
+
+/// the context as text, with any non-ASCII bytes escaped
+impl std::fmt::Display for KdfContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for b in self.0.iter() {
+            for c in std::ascii::escape_default(*b) {
+                write!(f, "{}", c as char)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for KdfContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "KdfContext(\"{}\")", self)
+    }
+}
+
+/// Derive a child key from a parent key
 /// ****
-/// @param {SecBuf} out - Empty Buffer to be used as output
+/// @param {SecBuf} child - Empty Buffer to be used as output, MINBYTES to MAXBYTES long
 ///
-/// @param {number} index - subkey index
+/// @param {number} index - child index
 ///
-/// @param {Buffer} context - eight bytes context
+/// @param {KdfContext} context - eight bytes context
 ///
-/// @param {SecBuf} parent - the parent key to derive from
-
+/// @param {SecBuf} parent - the KEYBYTES parent key to derive from, backed by secure memory
 pub fn derive(
-    out: &mut SecBuf,
+    child: &mut SecBuf,
     index: u64,
-    context: &mut SecBuf,
+    context: &KdfContext,
     parent: &mut SecBuf,
 ) -> Result<(), SodiumError> {
-    check_init();
-    {
-        let out = out.read_lock();
-        let o = out.len();
-        let context = context.read_lock();
-        let c = context.len();
-        if o < MINBYTES || o > MAXBYTES {
-            return Err(SodiumError::OutputLength(format!(
-                "Invalid 'out' Buffer length:{}",
-                o
-            )));
-        } else if c != CONTEXTBYTES {
-            return Err(SodiumError::OutputLength(format!(
-                "context must be a Buffer of length: {}.",
-                CONTEXTBYTES
-            )));
+    if !parent.is_secure() {
+        return Err(SodiumError::new(
+            "parent key must be backed by secure memory",
+        ));
+    }
+    derive_from_key_into(parent, index, context, child)
+}
+
+/// Walk `path` from `parent`, deriving a KEYBYTES child at each level from the one before,
+/// each intermediate seed is zeroed as soon as the next level has been derived
+///
+/// @param {SecBuf} parent - the KEYBYTES root key, backed by secure memory
+///
+/// @param {&[(u64, KdfContext)]} path - the index and context of each level
+///
+/// @UseReturn {SecBuf} - the last child, in secure memory
+pub fn derive_path(parent: &mut SecBuf, path: &[(u64, KdfContext)]) -> Result<SecBuf, SodiumError> {
+    let (first, rest) = path.split_first().ok_or(SodiumError::EmptyInput)?;
+    let mut seed = derive_seed(parent, first)?;
+    for level in rest {
+        let mut child = derive_seed(&mut seed, level)?;
+        std::mem::swap(&mut seed, &mut child);
+        let mut intermediate = child.write_lock();
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(intermediate), intermediate.len());
         }
     }
+    Ok(seed)
+}
+
+/// one level of `derive_path`
+fn derive_seed(parent: &mut SecBuf, level: &(u64, KdfContext)) -> Result<SecBuf, SodiumError> {
+    let mut child = SecBuf::try_with_secure(KEYBYTES)?;
+    derive(&mut child, level.0, &level.1, parent)?;
+    Ok(child)
+}
+
+/// Derive the subkey `id` of `master` into `out`, so a hot loop can reuse
+/// one output SecBuf rather than allocating one per subkey
+///
+/// @param {SecBuf} master - KEYBYTES master key
+///
+/// @param {number} id - subkey index
+///
+/// @param {KdfContext} context - eight bytes context
+///
+/// @param {SecBuf} out - the subkey, between MINBYTES and MAXBYTES long
+pub fn derive_from_key_into(
+    master: &mut SecBuf,
+    id: u64,
+    context: &KdfContext,
+    out: &mut SecBuf,
+) -> Result<(), SodiumError> {
+    check_init();
+    if master.len() != KEYBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "master key must be {} bytes, got {}",
+            KEYBYTES,
+            master.len()
+        )));
+    }
+    if out.len() < MINBYTES || out.len() > MAXBYTES {
+        return Err(SodiumError::OutputLength(format!(
+            "subkey must be {} to {} bytes, got {}",
+            MINBYTES,
+            MAXBYTES,
+            out.len()
+        )));
+    }
     let mut out = out.write_lock();
-    let parent = parent.read_lock();
-    let context = context.read_lock();
+    let master = master.read_lock();
     unsafe {
         rust_sodium_sys::crypto_kdf_derive_from_key(
             raw_ptr_char!(out),
             out.len(),
-            index,
-            raw_ptr_ichar_immut!(context),
-            raw_ptr_char_immut!(parent),
+            id,
+            context.0.as_ptr() as *const libc::c_char,
+            raw_ptr_char_immut!(master),
         );
     }
     Ok(())
 }
 
+impl SecBuf {
+    /// Derive a subkey of `out_len` bytes from this master key,
+    /// into a new SecBuf backed by secure memory
+    /// ****
+    /// @param {number} index - subkey index
+    ///
+    /// @param {KdfContext} context - eight bytes context
+    ///
+    /// @param {number} out_len - subkey length, between MINBYTES and MAXBYTES
+    pub fn derive_subkey(
+        &mut self,
+        index: u64,
+        context: &KdfContext,
+        out_len: usize,
+    ) -> Result<SecBuf, SodiumError> {
+        if out_len < MINBYTES || out_len > MAXBYTES {
+            return Err(SodiumError::OutputLength(format!(
+                "subkey must be {} to {} bytes, got {}",
+                MINBYTES, MAXBYTES, out_len
+            )));
+        }
+        let mut out = SecBuf::try_with_secure(out_len)?;
+        derive_from_key_into(self, index, context, &mut out)?;
+        Ok(out)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::random::random_secbuf;
+    use crate::{random::random_secbuf, secbuf::ProtectState};
 
     #[test]
     fn it_should_derive_consistantly() {
-        let mut context = SecBuf::with_secure(CONTEXTBYTES);
+        let context = KdfContext::new("consist_").unwrap();
         let mut parent = SecBuf::with_secure(32);
-        random_secbuf(&mut context);
         random_secbuf(&mut parent);
         let mut out1 = SecBuf::with_secure(32);
         let mut out2 = SecBuf::with_secure(32);
         {
-            derive(&mut out1, 3, &mut context, &mut parent).unwrap();
+            derive(&mut out1, 3, &context, &mut parent).unwrap();
         }
         {
-            derive(&mut out2, 3, &mut context, &mut parent).unwrap();
+            derive(&mut out2, 3, &context, &mut parent).unwrap();
         }
         let out1 = out1.read_lock();
         let out2 = out2.read_lock();
-        assert_eq!(format!("{:?}", *out1), format!("{:?}", *out2));
+        assert_eq!(out1.debug_unredacted(), out2.debug_unredacted());
     }
     #[test]
     fn it_should_return_error_on_bad_output_buffer() {
-        let mut context = SecBuf::with_secure(8);
+        let context = KdfContext::new("badout__").unwrap();
         let mut parent = SecBuf::with_secure(32);
-        random_secbuf(&mut context);
         random_secbuf(&mut parent);
         let mut out = SecBuf::with_insecure(2);
         {
-            derive(&mut out, 3, &mut context, &mut parent).expect_err("should have failed");
+            derive(&mut out, 3, &context, &mut parent).expect_err("should have failed");
         }
     }
+
+    #[test]
+    fn it_should_derive_distinct_reproducible_subkeys() {
+        let mut master = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut master);
+        let context = &KdfContext::new("hc_dpki_").unwrap();
+
+        let render = |b: &mut SecBuf| b.read_lock().debug_unredacted();
+        let first: Vec<String> = (0..10)
+            .map(|i| render(&mut master.derive_subkey(i, context, 32).unwrap()))
+            .collect();
+        for (i, a) in first.iter().enumerate() {
+            for b in first[i + 1..].iter() {
+                assert_ne!(a, b);
+            }
+        }
+
+        let second: Vec<String> = (0..10)
+            .map(|i| render(&mut master.derive_subkey(i, context, 32).unwrap()))
+            .collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn it_should_reject_bad_subkey_params() {
+        let context = KdfContext::new("context_").unwrap();
+        let mut master = SecBuf::with_secure(16);
+        match master.derive_subkey(0, &context, 32) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+
+        let mut master = SecBuf::with_secure(KEYBYTES);
+        assert!(master.derive_subkey(0, &context, 8).is_err());
+        assert!(master.derive_subkey(0, &context, 72).is_err());
+
+        let subkey = master.derive_subkey(0, &context, 64).unwrap();
+        assert!(subkey.is_secure());
+        assert_eq!(64, subkey.len());
+    }
+
+    #[test]
+    fn it_should_derive_into_a_reused_buffer() {
+        let mut master = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut master);
+        let context = KdfContext::new("reusebuf").unwrap();
+
+        let mut out = SecBuf::with_secure(32);
+        for id in 0..5 {
+            derive_from_key_into(&mut master, id, &context, &mut out).unwrap();
+            assert_eq!(ProtectState::NoAccess, out.protect_state());
+
+            let mut expected = master.derive_subkey(id, &context, 32).unwrap();
+            let expected = expected.read_lock();
+            let out = out.read_lock();
+            assert_eq!(expected.debug_unredacted(), out.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_reject_bad_derive_into_lengths() {
+        let mut master = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut master);
+        let mut out = SecBuf::with_insecure(MAXBYTES + 1);
+        match derive_from_key_into(&mut master, 1, &CONTEXT_DEVICE, &mut out) {
+            Err(SodiumError::OutputLength(_)) => (),
+            _ => panic!("expected OutputLength"),
+        }
+        let mut short_master = SecBuf::with_secure(16);
+        let mut out = SecBuf::with_secure(32);
+        assert!(derive_from_key_into(&mut short_master, 1, &CONTEXT_DEVICE, &mut out).is_err());
+    }
+
+    /// the child `index` of `parent` in `context`, rendered for comparison
+    fn child(parent: &mut SecBuf, index: u64, context: &str, len: usize) -> String {
+        let mut out = SecBuf::with_secure(len);
+        derive(&mut out, index, &KdfContext::new(context).unwrap(), parent).unwrap();
+        let out = out.read_lock();
+        out.debug_unredacted()
+    }
+
+    #[test]
+    fn it_should_separate_children_by_index_and_context() {
+        let mut root = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut root);
+        assert_eq!(
+            child(&mut root, 1, "device__", 32),
+            child(&mut root, 1, "device__", 32)
+        );
+        assert_ne!(
+            child(&mut root, 1, "device__", 32),
+            child(&mut root, 2, "device__", 32)
+        );
+        assert_ne!(
+            child(&mut root, 1, "device__", 32),
+            child(&mut root, 1, "revoke__", 32)
+        );
+        for len in &[16, 32, 64] {
+            let mut out = SecBuf::with_secure(*len);
+            derive(
+                &mut out,
+                1,
+                &KdfContext::new("device__").unwrap(),
+                &mut root,
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn it_should_require_a_secure_parent() {
+        let mut root = SecBuf::with_insecure(KEYBYTES);
+        let mut out = SecBuf::with_secure(32);
+        assert!(derive(
+            &mut out,
+            1,
+            &KdfContext::new("device__").unwrap(),
+            &mut root
+        )
+        .is_err());
+        let mut root = SecBuf::with_secure(16);
+        match derive(
+            &mut out,
+            1,
+            &KdfContext::new("device__").unwrap(),
+            &mut root,
+        ) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+    }
+
+    #[test]
+    fn it_should_derive_a_path_like_the_manual_steps() {
+        let mut root = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut root);
+        let device = KdfContext::new("device__").unwrap();
+        let app = KdfContext::new("app_____").unwrap();
+
+        let mut leaf = derive_path(&mut root, &[(3, device), (7, app)]).unwrap();
+        assert!(leaf.is_secure());
+        assert_eq!(ProtectState::NoAccess, leaf.protect_state());
+
+        let mut intermediate = SecBuf::with_secure(KEYBYTES);
+        derive(&mut intermediate, 3, &device, &mut root).unwrap();
+        assert_eq!(
+            child(&mut intermediate, 7, "app_____", KEYBYTES),
+            leaf.read_lock().debug_unredacted()
+        );
+
+        let mut one = derive_path(&mut root, &[(3, device)]).unwrap();
+        assert_eq!(
+            intermediate.read_lock().debug_unredacted(),
+            one.read_lock().debug_unredacted()
+        );
+        match derive_path(&mut root, &[]) {
+            Err(SodiumError::EmptyInput) => (),
+            _ => panic!("expected EmptyInput"),
+        }
+    }
+
+    #[test]
+    fn it_should_reject_contexts_of_the_wrong_size() {
+        for s in &["device_", "device___", ""] {
+            match KdfContext::new(s) {
+                Err(SodiumError::SizeMismatch(_)) => (),
+                r => panic!("expected SizeMismatch for {:?}, got {:?}", s, r),
+            }
+        }
+        // eight bytes, but not eight ASCII characters
+        assert!(KdfContext::new("devicé_").is_err());
+        assert_eq!(b"device__", KdfContext::new("device__").unwrap().as_bytes());
+    }
+
+    #[test]
+    fn it_should_display_contexts() {
+        assert_eq!("hcdevice", CONTEXT_DEVICE.to_string());
+        assert_eq!("KdfContext(\"hcrevoke\")", format!("{:?}", CONTEXT_REVOKE));
+        assert_eq!(
+            "ab\\x00cdefg",
+            KdfContext::from_bytes(*b"ab\0cdefg").to_string()
+        );
+    }
+
+    #[test]
+    fn it_should_derive_distinct_children_for_the_keystore_contexts() {
+        let mut root = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut root);
+        let children: Vec<String> = [CONTEXT_DEVICE, CONTEXT_REVOKE, CONTEXT_APP]
+            .iter()
+            .map(|context| {
+                let mut out = root.derive_subkey(0, context, 32).unwrap();
+                let out = out.read_lock();
+                out.debug_unredacted()
+            })
+            .collect();
+        assert_ne!(children[0], children[1]);
+        assert_ne!(children[0], children[2]);
+        assert_ne!(children[1], children[2]);
+    }
 }
@@ -0,0 +1,349 @@
+//! This module provides passphrase encrypted key files, for keeping
+//! a single private key on disk between restarts
+//!
+//! a key file is the argon2id salt, the secretbox nonce and the secretbox cipher text,
+//! concatenated. The key is derived at the moderate pwhash cost
+
+use std::{fs, io::Write, path::Path};
+
+use super::{
+    check_init,
+    pwhash::{self, PwHashParams},
+    random::random_secbuf,
+    secbuf::SecBuf,
+    secretbox,
+};
+use crate::error::SodiumError;
+
+/// the shortest possible key file, holding an empty SecBuf
+const HEADER_LEN: usize = pwhash::SALTBYTES + secretbox::NONCEBYTES;
+
+/// derive the secretbox key from the passphrase
+fn derive_key(
+    passphrase: &mut SecBuf,
+    salt: &mut SecBuf,
+    params: PwHashParams,
+) -> Result<SecBuf, SodiumError> {
+    let mut key = SecBuf::try_with_secure(secretbox::KEYBYTES)?;
+    pwhash::hash_with_params(passphrase, salt, &mut key, params)?;
+    Ok(key)
+}
+
+/// create (or truncate) `path`, readable and writable only by its owner on unix
+pub(crate) fn create_private(path: &Path) -> Result<fs::File, SodiumError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(path)?;
+        // `mode` only applies when the file is created
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(options.open(path)?)
+    }
+}
+
+/// write `parts` to a private temp file next to `path`, then rename it over `path`,
+/// so a crash mid-write never leaves a truncated key file behind
+fn write_private_atomic(path: &Path, parts: &[&[u8]]) -> Result<(), SodiumError> {
+    let mut name = path
+        .file_name()
+        .ok_or_else(|| SodiumError::new("key file path has no file name"))?
+        .to_os_string();
+    name.push(".tmp");
+    let tmp = path.with_file_name(name);
+    let res = create_private(&tmp).and_then(|mut file| {
+        for part in parts {
+            file.write_all(part)?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    });
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+impl SecBuf {
+    /// encrypt this SecBuf with a key derived from `passphrase` and a fresh random salt,
+    /// and write it to `path` (mode 0o600 on unix), replacing any existing file
+    ///
+    /// @param {Path} path - the key file to write
+    ///
+    /// @param {SecBuf} passphrase - the key file passphrase
+    pub fn write_to_file(
+        &mut self,
+        path: &Path,
+        passphrase: &mut SecBuf,
+    ) -> Result<(), SodiumError> {
+        self.write_to_file_with_params(path, passphrase, PwHashParams::moderate())
+    }
+
+    /// `write_to_file` at the given pwhash cost, which `read_from_file_with_params`
+    /// must be given again as key files do not record it
+    fn write_to_file_with_params(
+        &mut self,
+        path: &Path,
+        passphrase: &mut SecBuf,
+        params: PwHashParams,
+    ) -> Result<(), SodiumError> {
+        check_init();
+        let mut salt = SecBuf::try_with_insecure(pwhash::SALTBYTES)?;
+        random_secbuf(&mut salt);
+        let mut nonce = SecBuf::try_with_insecure(secretbox::NONCEBYTES)?;
+        random_secbuf(&mut nonce);
+        let mut key = derive_key(passphrase, &mut salt, params)?;
+        let mut cipher = secretbox::encrypt(self, &mut nonce, &mut key)?;
+
+        let salt = salt.read_lock();
+        let nonce = nonce.read_lock();
+        let cipher = cipher.read_lock();
+        write_private_atomic(path, &[&salt[..], &nonce[..], &cipher[..]])
+    }
+
+    /// read a key file written by `write_to_file` and decrypt it with `passphrase`
+    ///
+    /// @param {Path} path - the key file to read
+    ///
+    /// @param {SecBuf} passphrase - the key file passphrase
+    ///
+    /// @param {bool} secure - whether the key is loaded into secure memory
+    ///
+    /// @UseReturn {SecBuf} - the key, DecryptionFailed for a wrong passphrase
+    pub fn read_from_file(
+        path: &Path,
+        passphrase: &mut SecBuf,
+        secure: bool,
+    ) -> Result<SecBuf, SodiumError> {
+        SecBuf::read_from_file_with_params(path, passphrase, secure, PwHashParams::moderate())
+    }
+
+    /// `read_from_file` for a key file written at the given pwhash cost
+    fn read_from_file_with_params(
+        path: &Path,
+        passphrase: &mut SecBuf,
+        secure: bool,
+        params: PwHashParams,
+    ) -> Result<SecBuf, SodiumError> {
+        check_init();
+        let contents = fs::read(path)?;
+        if contents.len() < HEADER_LEN + secretbox::MACBYTES {
+            return Err(SodiumError::LengthMismatch(format!(
+                "key file must be at least {} bytes long, got {}.",
+                HEADER_LEN + secretbox::MACBYTES,
+                contents.len()
+            )));
+        }
+        let (salt, rest) = contents.split_at(pwhash::SALTBYTES);
+        let (nonce, cipher) = rest.split_at(secretbox::NONCEBYTES);
+        let mut salt_buf = SecBuf::try_with_insecure(salt.len())?;
+        salt_buf.write(0, salt)?;
+        let mut key = derive_key(passphrase, &mut salt_buf, params)?;
+
+        let len = cipher.len() - secretbox::MACBYTES;
+        let mut out = if secure {
+            SecBuf::try_with_secure_any(len)?
+        } else {
+            SecBuf::try_with_insecure(len)?
+        };
+        let res = {
+            let mut out = out.write_lock();
+            let key = key.read_lock();
+            unsafe {
+                rust_sodium_sys::crypto_secretbox_open_easy(
+                    raw_ptr_char!(out),
+                    raw_ptr_char_immut!(cipher),
+                    cipher.len() as libc::c_ulonglong,
+                    raw_ptr_char_immut!(nonce),
+                    raw_ptr_char_immut!(key),
+                )
+            }
+        };
+        if res != 0 {
+            return Err(SodiumError::DecryptionFailed);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// a key file path unique to this test run, removed on drop
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            TempPath(std::env::temp_dir().join(format!(
+                "holochain_sodium_keyfile_{}_{}",
+                std::process::id(),
+                name
+            )))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// the cheapest argon2id cost, the file format is the same at any cost
+    fn test_params() -> PwHashParams {
+        PwHashParams::custom(
+            u64::from(rust_sodium_sys::crypto_pwhash_OPSLIMIT_MIN),
+            rust_sodium_sys::crypto_pwhash_MEMLIMIT_MIN as usize,
+        )
+        .unwrap()
+    }
+
+    fn passphrase(s: &str) -> SecBuf {
+        SecBuf::with_insecure_from_string(s.to_string())
+    }
+
+    #[test]
+    fn it_should_round_trip_a_key_file() {
+        let path = TempPath::new("round_trip");
+        let mut key = SecBuf::with_secure(32);
+        random_secbuf(&mut key);
+        key.write_to_file_with_params(&path.0, &mut passphrase("correct horse"), test_params())
+            .unwrap();
+        assert_eq!(
+            HEADER_LEN + 32 + secretbox::MACBYTES,
+            fs::metadata(&path.0).unwrap().len() as usize
+        );
+
+        let mut secure = SecBuf::read_from_file_with_params(
+            &path.0,
+            &mut passphrase("correct horse"),
+            true,
+            test_params(),
+        )
+        .unwrap();
+        assert!(secure.is_secure());
+        assert_eq!(
+            key.read_lock().debug_unredacted(),
+            secure.read_lock().debug_unredacted()
+        );
+
+        match SecBuf::read_from_file_with_params(
+            &path.0,
+            &mut passphrase("wrong horse"),
+            false,
+            test_params(),
+        ) {
+            Err(SodiumError::DecryptionFailed) => (),
+            _ => panic!("expected DecryptionFailed"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_should_write_an_owner_only_key_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = TempPath::new("mode");
+        fs::write(&path.0, b"old contents").unwrap();
+        fs::set_permissions(&path.0, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut key = SecBuf::with_insecure(20);
+        random_secbuf(&mut key);
+        key.write_to_file_with_params(&path.0, &mut passphrase("pin"), test_params())
+            .unwrap();
+        let mode = fs::metadata(&path.0).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+
+        let mut insecure = SecBuf::read_from_file_with_params(
+            &path.0,
+            &mut passphrase("pin"),
+            false,
+            test_params(),
+        )
+        .unwrap();
+        assert!(!insecure.is_secure());
+        assert_eq!(
+            key.read_lock().debug_unredacted(),
+            insecure.read_lock().debug_unredacted()
+        );
+    }
+
+    #[test]
+    fn it_should_reject_a_short_or_missing_key_file() {
+        let path = TempPath::new("short");
+        fs::write(&path.0, &[0u8; HEADER_LEN][..]).unwrap();
+        match SecBuf::read_from_file_with_params(
+            &path.0,
+            &mut passphrase("pin"),
+            true,
+            test_params(),
+        ) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+        let missing = TempPath::new("missing");
+        match SecBuf::read_from_file_with_params(
+            &missing.0,
+            &mut passphrase("pin"),
+            true,
+            test_params(),
+        ) {
+            Err(SodiumError::Io(_)) => (),
+            _ => panic!("expected Io"),
+        }
+    }
+
+    #[test]
+    fn it_should_read_any_key_length_into_secure_memory() {
+        let path = TempPath::new("secure_any");
+        let mut key = SecBuf::with_insecure(20);
+        random_secbuf(&mut key);
+        key.write_to_file_with_params(&path.0, &mut passphrase("pin"), test_params())
+            .unwrap();
+        let mut secure = SecBuf::read_from_file_with_params(
+            &path.0,
+            &mut passphrase("pin"),
+            true,
+            test_params(),
+        )
+        .unwrap();
+        assert!(secure.is_secure());
+        assert_eq!(
+            key.read_lock().debug_unredacted(),
+            secure.read_lock().debug_unredacted()
+        );
+    }
+
+    #[test]
+    fn it_should_replace_a_key_file_without_leaving_a_temp_file() {
+        let path = TempPath::new("replace");
+        let tmp = TempPath(path.0.with_file_name(format!(
+            "{}.tmp",
+            path.0.file_name().unwrap().to_str().unwrap()
+        )));
+        fs::write(&path.0, b"old contents").unwrap();
+        let mut key = SecBuf::with_insecure(16);
+        random_secbuf(&mut key);
+        key.write_to_file_with_params(&path.0, &mut passphrase("pin"), test_params())
+            .unwrap();
+        assert!(!tmp.0.exists());
+        let mut read = SecBuf::read_from_file_with_params(
+            &path.0,
+            &mut passphrase("pin"),
+            false,
+            test_params(),
+        )
+        .unwrap();
+        assert_eq!(
+            key.read_lock().debug_unredacted(),
+            read.read_lock().debug_unredacted()
+        );
+    }
+}
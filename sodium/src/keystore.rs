@@ -0,0 +1,292 @@
+//! This module provides a Keystore, the named secrets (seeds and keys) an agent
+//! holds at runtime, persisted to disk as a passphrase encrypted bundle
+
+use std::{fs, io::Write, path::Path};
+
+use super::{
+    bundle::{self, NamedSecBuf, PwHashLimits},
+    kdf::{self, KdfContext},
+    keyfile::create_private,
+    secbuf::SecBuf,
+    sign,
+};
+use crate::error::SodiumError;
+
+/// Named secrets, every one backed by secure memory
+#[derive(Default)]
+pub struct Keystore {
+    /// sorted by name, so lookups can binary search and `list` is ordered
+    keys: Vec<NamedSecBuf>,
+}
+
+impl Keystore {
+    pub fn new() -> Self {
+        Keystore { keys: Vec::new() }
+    }
+
+    fn find(&self, name: &str) -> Result<usize, usize> {
+        self.keys.binary_search_by(|k| k.name.as_str().cmp(name))
+    }
+
+    /// add `secret` under `name`, which must not be taken
+    ///
+    /// @param {SecBuf} secret - must be backed by secure memory
+    pub fn add(&mut self, name: &str, secret: SecBuf) -> Result<(), SodiumError> {
+        if !secret.is_secure() {
+            return Err(SodiumError::new(
+                "keystore secrets must be backed by secure memory",
+            ));
+        }
+        match self.find(name) {
+            Ok(_) => Err(SodiumError::KeyExists(name.to_string())),
+            Err(i) => {
+                self.keys.insert(
+                    i,
+                    NamedSecBuf {
+                        name: name.to_string(),
+                        secret,
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// the secret stored under `name`
+    pub fn get(&mut self, name: &str) -> Result<&mut SecBuf, SodiumError> {
+        match self.find(name) {
+            Ok(i) => Ok(&mut self.keys[i].secret),
+            Err(_) => Err(SodiumError::KeyNotFound(name.to_string())),
+        }
+    }
+
+    /// remove the secret stored under `name`, zeroing it before its memory is released
+    pub fn remove(&mut self, name: &str) -> Result<(), SodiumError> {
+        match self.find(name) {
+            Ok(i) => {
+                self.keys.remove(i).secret.wipe();
+                Ok(())
+            }
+            Err(_) => Err(SodiumError::KeyNotFound(name.to_string())),
+        }
+    }
+
+    /// the names of every secret, in order
+    pub fn list(&self) -> Vec<&str> {
+        self.keys.iter().map(|k| k.name.as_str()).collect()
+    }
+
+    /// sign `message` with the ed25519 secret key stored under `name`
+    ///
+    /// @UseReturn {SecBuf} - the BYTES long signature, insecure
+    pub fn sign_with(&mut self, name: &str, message: &mut SecBuf) -> Result<SecBuf, SodiumError> {
+        let mut signature = SecBuf::try_with_insecure(sign::BYTES)?;
+        sign::sign(message, self.get(name)?, &mut signature)?;
+        Ok(signature)
+    }
+
+    /// derive a kdf::KEYBYTES child of the key stored under `src_name`
+    /// and store it under `new_name`, see `kdf::derive`
+    pub fn derive_into(
+        &mut self,
+        new_name: &str,
+        src_name: &str,
+        index: u64,
+        context: &KdfContext,
+    ) -> Result<(), SodiumError> {
+        if self.find(new_name).is_ok() {
+            return Err(SodiumError::KeyExists(new_name.to_string()));
+        }
+        let mut child = SecBuf::try_with_secure(kdf::KEYBYTES)?;
+        kdf::derive(&mut child, index, context, self.get(src_name)?)?;
+        self.add(new_name, child)
+    }
+
+    /// write every secret to `path` (mode 0o600 on unix) as a bundle encrypted with
+    /// `passphrase` at the default (moderate) cost, replacing any existing file
+    pub fn save(&mut self, path: &Path, passphrase: &mut SecBuf) -> Result<(), SodiumError> {
+        self.save_with_limits(path, passphrase, PwHashLimits::default())
+    }
+
+    /// `save` with the given argon2id cost, which is recorded in the file
+    ///
+    /// the secrets are only copied into secure memory, and reach the file as cipher text
+    pub fn save_with_limits(
+        &mut self,
+        path: &Path,
+        passphrase: &mut SecBuf,
+        limits: PwHashLimits,
+    ) -> Result<(), SodiumError> {
+        let json = bundle::encrypt_bundle_with_limits(&mut self.keys, passphrase, limits)?;
+        let mut file = create_private(path)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// read a Keystore written by `save`
+    ///
+    /// @UseReturn {Keystore} - CouldNotDecrypt for a wrong passphrase
+    pub fn load(path: &Path, passphrase: &mut SecBuf) -> Result<Keystore, SodiumError> {
+        let json = fs::read_to_string(path)?;
+        let mut keystore = Keystore::new();
+        for key in bundle::decrypt_bundle(&json, passphrase)? {
+            keystore.add(&key.name, key.secret)?;
+        }
+        Ok(keystore)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pwhash, random::random_secbuf, secbuf::spy::spy};
+    use std::path::PathBuf;
+
+    const TEST_LIMITS: PwHashLimits = PwHashLimits {
+        ops_limit: pwhash::OPSLIMIT_INTERACTIVE,
+        mem_limit: pwhash::MEMLIMIT_INTERACTIVE,
+    };
+
+    /// a keystore path unique to this test run, removed on drop
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            TempPath(std::env::temp_dir().join(format!(
+                "holochain_sodium_keystore_{}_{}",
+                std::process::id(),
+                name
+            )))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn passphrase(s: &str) -> SecBuf {
+        SecBuf::with_insecure_from_string(s.to_string())
+    }
+
+    /// a keystore holding a random "seed" and an ed25519 "sign" key, and its public key
+    fn test_keystore() -> (Keystore, SecBuf) {
+        let mut keystore = Keystore::new();
+        let mut seed = SecBuf::with_secure(kdf::KEYBYTES);
+        random_secbuf(&mut seed);
+        keystore.add("seed", seed).unwrap();
+        let (pk, sk) = sign::keypair().unwrap();
+        keystore.add("sign", sk).unwrap();
+        (keystore, pk)
+    }
+
+    #[test]
+    fn it_should_add_get_and_list_secrets() {
+        let (mut keystore, _) = test_keystore();
+        assert_eq!(vec!["seed", "sign"], keystore.list());
+        assert_eq!(sign::SECRETKEYBYTES, keystore.get("sign").unwrap().len());
+        match keystore.add("seed", SecBuf::with_secure(8)) {
+            Err(SodiumError::KeyExists(name)) => assert_eq!("seed", name),
+            _ => panic!("expected KeyExists"),
+        }
+        match keystore.get("missing") {
+            Err(SodiumError::KeyNotFound(name)) => assert_eq!("missing", name),
+            _ => panic!("expected KeyNotFound"),
+        }
+        assert!(keystore.add("public", SecBuf::with_insecure(8)).is_err());
+        assert_eq!(2, keystore.list().len());
+    }
+
+    #[test]
+    fn it_should_sign_with_and_derive_into() {
+        let (mut keystore, mut pk) = test_keystore();
+        let mut message = SecBuf::with_insecure_from_string("hello".to_string());
+        let mut signature = keystore.sign_with("sign", &mut message).unwrap();
+        assert!(sign::verify(&mut signature, &mut message, &mut pk).unwrap());
+
+        keystore
+            .derive_into("device", "seed", 1, &kdf::CONTEXT_DEVICE)
+            .unwrap();
+        let mut expected = SecBuf::with_secure(kdf::KEYBYTES);
+        kdf::derive(
+            &mut expected,
+            1,
+            &kdf::CONTEXT_DEVICE,
+            keystore.get("seed").unwrap(),
+        )
+        .unwrap();
+        let device = keystore.get("device").unwrap();
+        assert!(device.is_secure());
+        assert_eq!(
+            expected.read_lock().debug_unredacted(),
+            device.read_lock().debug_unredacted()
+        );
+        assert!(keystore
+            .derive_into("device", "seed", 2, &kdf::CONTEXT_DEVICE)
+            .is_err());
+        assert!(keystore
+            .derive_into("app", "missing", 2, &kdf::CONTEXT_APP)
+            .is_err());
+        assert_eq!(vec!["device", "seed", "sign"], keystore.list());
+    }
+
+    #[test]
+    fn it_should_round_trip_through_disk() {
+        let path = TempPath::new("round_trip");
+        let (mut keystore, _) = test_keystore();
+        // a secret whose length is not a multiple of 8
+        let mut pin = SecBuf::with_secure_any(13);
+        random_secbuf(&mut pin);
+        keystore.add("pin", pin).unwrap();
+        keystore
+            .save_with_limits(&path.0, &mut passphrase("correct horse"), TEST_LIMITS)
+            .unwrap();
+        assert!(!fs::read_to_string(&path.0).unwrap().contains("\"seed\""));
+
+        let mut loaded = Keystore::load(&path.0, &mut passphrase("correct horse")).unwrap();
+        assert_eq!(keystore.list(), loaded.list());
+        assert_eq!(vec!["pin", "seed", "sign"], loaded.list());
+        for name in &["pin", "seed", "sign"] {
+            let a = keystore.get(name).unwrap().read_lock().debug_unredacted();
+            let b = loaded.get(name).unwrap();
+            assert!(b.is_secure());
+            assert_eq!(a, b.read_lock().debug_unredacted());
+        }
+
+        match Keystore::load(&path.0, &mut passphrase("wrong horse")) {
+            Err(SodiumError::CouldNotDecrypt) => (),
+            _ => panic!("expected CouldNotDecrypt"),
+        }
+    }
+
+    #[test]
+    fn it_should_zero_removed_secrets() {
+        let (mut keystore, _) = test_keystore();
+        let (secret, zero_on_drop) = spy(true);
+        keystore.add("spy", secret).unwrap();
+        keystore.remove("spy").unwrap();
+        assert_eq!(Some(true), *zero_on_drop.lock().unwrap());
+        assert_eq!(vec!["seed", "sign"], keystore.list());
+        match keystore.remove("spy") {
+            Err(SodiumError::KeyNotFound(_)) => (),
+            _ => panic!("expected KeyNotFound"),
+        }
+    }
+
+    #[test]
+    fn it_should_refuse_to_save_with_too_small_limits() {
+        let (mut keystore, _) = test_keystore();
+        let path = TempPath::new("small_limits");
+        let limits = PwHashLimits {
+            ops_limit: 0,
+            mem_limit: 1024,
+        };
+        match keystore.save_with_limits(&path.0, &mut passphrase("correct horse"), limits) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+        assert!(!path.0.exists());
+    }
+}
@@ -1,11 +1,39 @@
 //! This module provides access to libsodium
-use super::check_init;
 /// Recomended to use secure SecBuf for return values in these functions
 use super::secbuf::SecBuf;
+use super::{check_init, scalarmult};
 use crate::error::SodiumError;
 pub const PUBLICKEYBYTES: usize = rust_sodium_sys::crypto_kx_PUBLICKEYBYTES as usize;
 pub const SECRETKEYBYTES: usize = rust_sodium_sys::crypto_kx_SECRETKEYBYTES as usize;
 pub const SESSIONKEYBYTES: usize = rust_sodium_sys::crypto_kx_SESSIONKEYBYTES as usize;
+pub const SEEDBYTES: usize = rust_sodium_sys::crypto_kx_SEEDBYTES as usize;
+/// Size of an X25519 shared secret
+pub const SCALARMULTBYTES: usize = rust_sodium_sys::crypto_scalarmult_BYTES as usize;
+
+/// every kx key is 32 bytes, but check each against its own constant
+fn check_len(name: &str, buf: &SecBuf, expected: usize) -> Result<(), SodiumError> {
+    if buf.len() != expected {
+        return Err(SodiumError::SizeMismatch(format!(
+            "{} must be a Buffer of length: {}, got {}.",
+            name,
+            expected,
+            buf.len()
+        )));
+    }
+    Ok(())
+}
+
+/// session keys are transport secrets, so they must be backed by secure memory
+fn check_session_keys(rx: &SecBuf, tx: &SecBuf) -> Result<(), SodiumError> {
+    check_len("rx", rx, SESSIONKEYBYTES)?;
+    check_len("tx", tx, SESSIONKEYBYTES)?;
+    if !rx.is_secure() || !tx.is_secure() {
+        return Err(SodiumError::new(
+            "session keys must be backed by secure memory",
+        ));
+    }
+    Ok(())
+}
 
 /// Generate a fresh, random keyexchange keypair
 /// ****
@@ -14,6 +42,8 @@ pub const SESSIONKEYBYTES: usize = rust_sodium_sys::crypto_kx_SESSIONKEYBYTES as
 /// @param {SecBuf} sk - Empty Buffer to be used as secretKey return
 pub fn keypair(pk: &mut SecBuf, sk: &mut SecBuf) -> Result<(), SodiumError> {
     check_init();
+    check_len("pk", pk, PUBLICKEYBYTES)?;
+    check_len("sk", sk, SECRETKEYBYTES)?;
     let mut pk = pk.write_lock();
     let mut sk = sk.write_lock();
     unsafe {
@@ -35,6 +65,9 @@ pub fn seed_keypair(
     sk: &mut SecBuf,
 ) -> Result<(), SodiumError> {
     check_init();
+    check_len("seed", seed, SEEDBYTES)?;
+    check_len("pk", pk, PUBLICKEYBYTES)?;
+    check_len("sk", sk, SECRETKEYBYTES)?;
     let seed = seed.read_lock();
     let mut pk = pk.write_lock();
     let mut sk = sk.write_lock();
@@ -48,82 +81,160 @@ pub fn seed_keypair(
     Ok(())
 }
 
-/// Given a server's public key, derive shared secrets.
+/// Given a server's public key, derive the client's session keys
 /// ****
-/// @param {SecBuf} cliPublic - client's public key
+/// @param {SecBuf} rx - Empty secure Buffer for the key to decrypt from the server
 ///
-/// @param {SecBuf} cliSecret - client's secret key
+/// @param {SecBuf} tx - Empty secure Buffer for the key to encrypt to the server
 ///
-/// @param {SecBuf} srvPublic - server's public key
+/// @param {SecBuf} client_pk - client's public key
 ///
-/// @param {SecBuf} rx - Empty Buffer to be used as secretKey return
+/// @param {SecBuf} client_sk - client's secret key
 ///
-/// @param {SecBuf} tx - Empty Buffer to be used as secretKey return
-pub fn client_session(
+/// @param {SecBuf} server_pk - server's public key
+///
+/// returns WeakKey if libsodium refuses `server_pk`
+pub fn client_session_keys(
+    rx: &mut SecBuf,
+    tx: &mut SecBuf,
     client_pk: &mut SecBuf,
     client_sk: &mut SecBuf,
     server_pk: &mut SecBuf,
-    rx: &mut SecBuf,
-    tx: &mut SecBuf,
 ) -> Result<(), SodiumError> {
     check_init();
+    check_session_keys(rx, tx)?;
+    check_len("client_pk", client_pk, PUBLICKEYBYTES)?;
+    check_len("client_sk", client_sk, SECRETKEYBYTES)?;
+    check_len("server_pk", server_pk, PUBLICKEYBYTES)?;
     let mut rx = rx.write_lock();
     let mut tx = tx.write_lock();
     let client_sk = client_sk.read_lock();
     let client_pk = client_pk.read_lock();
     let server_pk = server_pk.read_lock();
-    unsafe {
+    let res = unsafe {
         rust_sodium_sys::crypto_kx_client_session_keys(
             raw_ptr_char!(rx),
             raw_ptr_char!(tx),
             raw_ptr_char_immut!(client_pk),
             raw_ptr_char_immut!(client_sk),
             raw_ptr_char_immut!(server_pk),
-        );
+        )
+    };
+    if res != 0 {
+        return Err(SodiumError::WeakKey);
     }
     Ok(())
 }
 
-/// Given a client's public key, derive shared secrets.
+/// Given a client's public key, derive the server's session keys
 /// ****
-/// @param {SecBuf} srvPublic - server's public key
+/// @param {SecBuf} rx - Empty secure Buffer for the key to decrypt from the client
 ///
-/// @param {SecBuf} srvSecret - server's secret key
+/// @param {SecBuf} tx - Empty secure Buffer for the key to encrypt to the client
 ///
-/// @param {SecBuf} cliPublic - client's public key
+/// @param {SecBuf} server_pk - server's public key
 ///
-/// @param {SecBuf} rx - Empty Buffer to be used as secretKey return
+/// @param {SecBuf} server_sk - server's secret key
 ///
-/// @param {SecBuf} tx - Empty Buffer to be used as secretKey return
-pub fn server_session(
+/// @param {SecBuf} client_pk - client's public key
+///
+/// returns WeakKey if libsodium refuses `client_pk`
+pub fn server_session_keys(
+    rx: &mut SecBuf,
+    tx: &mut SecBuf,
     server_pk: &mut SecBuf,
     server_sk: &mut SecBuf,
     client_pk: &mut SecBuf,
-    rx: &mut SecBuf,
-    tx: &mut SecBuf,
 ) -> Result<(), SodiumError> {
     check_init();
+    check_session_keys(rx, tx)?;
+    check_len("server_pk", server_pk, PUBLICKEYBYTES)?;
+    check_len("server_sk", server_sk, SECRETKEYBYTES)?;
+    check_len("client_pk", client_pk, PUBLICKEYBYTES)?;
     let mut rx = rx.write_lock();
     let mut tx = tx.write_lock();
     let client_pk = client_pk.read_lock();
     let server_sk = server_sk.read_lock();
     let server_pk = server_pk.read_lock();
-    unsafe {
+    let res = unsafe {
         rust_sodium_sys::crypto_kx_server_session_keys(
             raw_ptr_char!(rx),
             raw_ptr_char!(tx),
             raw_ptr_char_immut!(server_pk),
             raw_ptr_char_immut!(server_sk),
             raw_ptr_char_immut!(client_pk),
-        );
+        )
+    };
+    if res != 0 {
+        return Err(SodiumError::WeakKey);
     }
     Ok(())
 }
 
+/// `client_session_keys`, with the session keys last
+pub fn client_session(
+    client_pk: &mut SecBuf,
+    client_sk: &mut SecBuf,
+    server_pk: &mut SecBuf,
+    rx: &mut SecBuf,
+    tx: &mut SecBuf,
+) -> Result<(), SodiumError> {
+    client_session_keys(rx, tx, client_pk, client_sk, server_pk)
+}
+
+/// `server_session_keys`, with the session keys last
+pub fn server_session(
+    server_pk: &mut SecBuf,
+    server_sk: &mut SecBuf,
+    client_pk: &mut SecBuf,
+    rx: &mut SecBuf,
+    tx: &mut SecBuf,
+) -> Result<(), SodiumError> {
+    server_session_keys(rx, tx, server_pk, server_sk, client_pk)
+}
+
+impl SecBuf {
+    /// generate a fresh X25519 keypair
+    ///
+    /// @UseReturn {(SecBuf, SecBuf)} - (publicKey insecure, secretKey secure) both NoAccess
+    pub fn generate_x25519_keypair() -> Result<(SecBuf, SecBuf), SodiumError> {
+        let mut pk = SecBuf::try_with_insecure(PUBLICKEYBYTES)?;
+        let mut sk = SecBuf::try_with_secure(SECRETKEYBYTES)?;
+        keypair(&mut pk, &mut sk)?;
+        Ok((pk, sk))
+    }
+
+    /// raw X25519: multiply `their_pk` by this secret key
+    /// prefer the session functions, which hash the result with both public keys
+    ///
+    /// @param {SecBuf} their_pk - the other party's PUBLICKEYBYTES public key
+    ///
+    /// @UseReturn {SecBuf} - the shared secret, in secure memory, WeakKey for a low order point
+    pub fn diffie_hellman(&mut self, their_pk: &mut SecBuf) -> Result<SecBuf, SodiumError> {
+        check_init();
+        if self.len() != SECRETKEYBYTES || !self.is_secure() {
+            return Err(SodiumError::SizeMismatch(format!(
+                "secret key must be {} bytes of secure memory, got {} (secure: {})",
+                SECRETKEYBYTES,
+                self.len(),
+                self.is_secure()
+            )));
+        }
+        if their_pk.len() != PUBLICKEYBYTES {
+            return Err(SodiumError::SizeMismatch(format!(
+                "public key must be {} bytes, got {}",
+                PUBLICKEYBYTES,
+                their_pk.len()
+            )));
+        }
+        scalarmult::scalarmult(self, their_pk)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::random::random_secbuf;
+    use crate::{aead, random::random_secbuf};
 
     #[test]
     fn it_should_generate_keypair() {
@@ -198,8 +309,180 @@ mod tests {
             let cli_tx = cli_tx.read_lock();
             let srv_rx = srv_rx.read_lock();
             let srv_tx = srv_tx.read_lock();
-            assert_eq!(format!("{:?}", *cli_rx), format!("{:?}", *srv_tx));
-            assert_eq!(format!("{:?}", *cli_tx), format!("{:?}", *srv_rx));
+            assert_eq!(cli_rx.debug_unredacted(), srv_tx.debug_unredacted());
+            assert_eq!(cli_tx.debug_unredacted(), srv_rx.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_agree_on_diffie_hellman() {
+        let mut pk1 = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut sk1 = SecBuf::with_secure(SECRETKEYBYTES);
+        keypair(&mut pk1, &mut sk1).unwrap();
+        let mut pk2 = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut sk2 = SecBuf::with_secure(SECRETKEYBYTES);
+        keypair(&mut pk2, &mut sk2).unwrap();
+
+        let mut a = sk1.diffie_hellman(&mut pk2).unwrap();
+        let mut b = sk2.diffie_hellman(&mut pk1).unwrap();
+        assert!(a.is_secure());
+        let a = a.read_lock();
+        let b = b.read_lock();
+        assert_eq!(a.debug_unredacted(), b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_reject_weak_and_bad_dh_keys() {
+        let mut pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut sk = SecBuf::with_secure(SECRETKEYBYTES);
+        keypair(&mut pk, &mut sk).unwrap();
+
+        let mut zero_pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        zero_pk.fill(0);
+        match sk.diffie_hellman(&mut zero_pk) {
+            Err(SodiumError::WeakKey) => (),
+            _ => panic!("expected WeakKey"),
+        }
+
+        let mut short_pk = SecBuf::with_insecure(16);
+        match sk.diffie_hellman(&mut short_pk) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+        let mut insecure_sk = SecBuf::with_insecure(SECRETKEYBYTES);
+        assert!(insecure_sk.diffie_hellman(&mut pk).is_err());
+    }
+
+    #[test]
+    fn it_should_generate_x25519_keypair() {
+        let (mut pk1, mut sk1) = SecBuf::generate_x25519_keypair().unwrap();
+        let (mut pk2, mut sk2) = SecBuf::generate_x25519_keypair().unwrap();
+        assert_eq!(PUBLICKEYBYTES, pk1.len());
+        assert!(!pk1.is_secure());
+        assert!(sk1.is_secure());
+
+        let mut a = sk1.diffie_hellman(&mut pk2).unwrap();
+        let mut b = sk2.diffie_hellman(&mut pk1).unwrap();
+        let a = a.read_lock();
+        let b = b.read_lock();
+        assert_eq!(a.debug_unredacted(), b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_exchange_session_keys_for_aead() {
+        let mut client_pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut client_sk = SecBuf::with_secure(SECRETKEYBYTES);
+        keypair(&mut client_pk, &mut client_sk).unwrap();
+        let mut server_pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut server_sk = SecBuf::with_secure(SECRETKEYBYTES);
+        keypair(&mut server_pk, &mut server_sk).unwrap();
+
+        let mut client_rx = SecBuf::with_secure(SESSIONKEYBYTES);
+        let mut client_tx = SecBuf::with_secure(SESSIONKEYBYTES);
+        client_session_keys(
+            &mut client_rx,
+            &mut client_tx,
+            &mut client_pk,
+            &mut client_sk,
+            &mut server_pk,
+        )
+        .unwrap();
+        let mut server_rx = SecBuf::with_secure(SESSIONKEYBYTES);
+        let mut server_tx = SecBuf::with_secure(SESSIONKEYBYTES);
+        server_session_keys(
+            &mut server_rx,
+            &mut server_tx,
+            &mut server_pk,
+            &mut server_sk,
+            &mut client_pk,
+        )
+        .unwrap();
+        assert_eq!(
+            client_tx.read_lock().debug_unredacted(),
+            server_rx.read_lock().debug_unredacted()
+        );
+        assert_eq!(
+            client_rx.read_lock().debug_unredacted(),
+            server_tx.read_lock().debug_unredacted()
+        );
+
+        // the client encrypts with tx, the server decrypts with rx
+        let mut message = SecBuf::with_insecure_from_string("hello server".to_string());
+        let mut nonce = SecBuf::with_insecure(aead::NONCEBYTES);
+        random_secbuf(&mut nonce);
+        let mut cipher = SecBuf::with_insecure(message.len() + aead::ABYTES);
+        aead::enc(&mut message, &mut client_tx, None, &mut nonce, &mut cipher).unwrap();
+        let mut decrypted = SecBuf::with_insecure(message.len());
+        aead::dec(
+            &mut decrypted,
+            &mut server_rx,
+            None,
+            &mut nonce,
+            &mut cipher,
+        )
+        .unwrap();
+        assert_eq!(
+            message.read_lock().debug_unredacted(),
+            decrypted.read_lock().debug_unredacted()
+        );
+        // and the server's tx is not the client's tx
+        let mut wrong = SecBuf::with_insecure(message.len());
+        assert!(aead::dec(&mut wrong, &mut server_tx, None, &mut nonce, &mut cipher).is_err());
+    }
+
+    #[test]
+    fn it_should_reject_bad_session_key_inputs() {
+        let mut pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut sk = SecBuf::with_secure(SECRETKEYBYTES);
+        keypair(&mut pk, &mut sk).unwrap();
+        let mut rx = SecBuf::with_secure(SESSIONKEYBYTES);
+        let mut tx = SecBuf::with_secure(SESSIONKEYBYTES);
+
+        let mut zero_pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        zero_pk.fill(0);
+        match client_session_keys(&mut rx, &mut tx, &mut pk, &mut sk, &mut zero_pk) {
+            Err(SodiumError::WeakKey) => (),
+            _ => panic!("expected WeakKey"),
+        }
+        match server_session_keys(&mut rx, &mut tx, &mut pk, &mut sk, &mut zero_pk) {
+            Err(SodiumError::WeakKey) => (),
+            _ => panic!("expected WeakKey"),
+        }
+
+        let mut short_pk = SecBuf::with_insecure(16);
+        match client_session_keys(&mut rx, &mut tx, &mut pk, &mut sk, &mut short_pk) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+        let mut insecure_rx = SecBuf::with_insecure(SESSIONKEYBYTES);
+        let mut other_pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut other_sk = SecBuf::with_secure(SECRETKEYBYTES);
+        keypair(&mut other_pk, &mut other_sk).unwrap();
+        assert!(
+            client_session_keys(&mut insecure_rx, &mut tx, &mut pk, &mut sk, &mut other_pk)
+                .is_err()
+        );
+        assert!(keypair(&mut short_pk, &mut sk).is_err());
+    }
+
+    #[test]
+    fn it_should_reject_bad_seed_keypair_lengths() {
+        let mut seed = SecBuf::with_secure(SEEDBYTES);
+        let mut pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut sk = SecBuf::with_secure(SECRETKEYBYTES);
+        let mut short_seed = SecBuf::with_secure(16);
+        let mut short_pk = SecBuf::with_insecure(16);
+        let mut short_sk = SecBuf::with_secure(16);
+        for res in vec![
+            seed_keypair(&mut short_seed, &mut pk, &mut sk),
+            seed_keypair(&mut seed, &mut short_pk, &mut sk),
+            seed_keypair(&mut seed, &mut pk, &mut short_sk),
+        ] {
+            match res {
+                Err(SodiumError::SizeMismatch(_)) => (),
+                _ => panic!("expected SizeMismatch"),
+            }
         }
+        assert!(seed_keypair(&mut seed, &mut pk, &mut sk).is_ok());
     }
 }
@@ -6,17 +6,30 @@ extern crate lazy_static;
 
 lazy_static! {
     /// we only need to call sodium_init once
-    static ref INIT: bool = {
-        unsafe {
-            rust_sodium_sys::sodium_init();
-        }
-        true
-    };
+    /// true unless it failed (returned -1)
+    static ref INIT: bool = unsafe { rust_sodium_sys::sodium_init() } >= 0;
 }
 
-/// make sure sodium_init is called
+/// make sure sodium_init is called, panicking if it failed, see `ensure_init`
 pub fn check_init() {
-    assert_eq!(true, *INIT);
+    if let Err(e) = ensure_init() {
+        panic!("{}", e);
+    }
+}
+
+/// make sure sodium_init is called, returning InitFailed if it failed
+/// so callers can refuse to start rather than panic later
+pub fn ensure_init() -> Result<(), error::SodiumError> {
+    if *INIT {
+        Ok(())
+    } else {
+        Err(error::SodiumError::InitFailed)
+    }
+}
+
+/// true if libsodium initialized successfully (initializing it on the first call)
+pub fn is_initialized() -> bool {
+    ensure_init().is_ok()
 }
 
 /// make invoking ffi functions taking SecBuf references more readable
@@ -47,12 +60,35 @@ macro_rules! raw_ptr_ichar_immut {
     };
 }
 pub mod aead;
+pub mod bundle;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod encoding;
 pub mod error;
 pub mod hash;
 pub mod kdf;
+pub mod keyfile;
+pub mod keystore;
 pub mod kx;
+pub mod mnemonic;
+pub mod pool;
 pub mod pwhash;
 pub mod random;
+pub mod scalarmult;
 pub mod secbuf;
+pub mod secretbox;
+pub mod secretstream;
 pub mod sign;
 pub mod util;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_initialize() {
+        assert!(ensure_init().is_ok());
+        assert!(is_initialized());
+        check_init();
+    }
+}
@@ -0,0 +1,436 @@
+//! This module provides BIP39 style mnemonic phrase encodings for SecBufs,
+//! useful for paper backups of seeds
+
+use reed_solomon::{Decoder, Encoder};
+
+use super::{check_init, secbuf::SecBuf};
+use crate::error::SodiumError;
+
+/// the standard BIP39 english wordlist, one word per line, sorted
+const WORDLIST: &str = include_str!("mnemonic_english.txt");
+
+/// each word encodes 11 bits
+const BITS_PER_WORD: usize = 11;
+
+/// scratch space for the largest entropy (32 bytes) plus its checksum byte,
+/// rounded up for alignment
+const SCRATCH_LEN: usize = 40;
+
+/// reed-solomon codewords over GF(256) cannot exceed 255 bytes
+const MAX_CODEWORD_LEN: usize = 255;
+
+/// the most words a full reed-solomon codeword needs
+const MAX_CODEWORD_WORDS: usize = (MAX_CODEWORD_LEN * 8 + BITS_PER_WORD - 1) / BITS_PER_WORD;
+
+/// scratch space for the bits of `MAX_CODEWORD_WORDS` words
+const CODEWORD_SCRATCH_LEN: usize = 256;
+
+lazy_static! {
+    static ref WORDS: Vec<&'static str> = WORDLIST.split_whitespace().collect();
+}
+
+/// checksum bit count for a given entropy byte length, or an error if unsupported
+fn checksum_bits(entropy_len: usize) -> Result<usize, SodiumError> {
+    match entropy_len {
+        16 | 24 | 32 => Ok(entropy_len * 8 / 32),
+        _ => Err(SodiumError::LengthMismatch(format!(
+            "mnemonic entropy must be 16, 24 or 32 bytes, got {}",
+            entropy_len
+        ))),
+    }
+}
+
+/// case-insensitive position of `word` in the wordlist
+fn word_index(word: &str) -> Option<usize> {
+    WORDS
+        .binary_search_by(|w| w.bytes().cmp(word.bytes().map(|c| c.to_ascii_lowercase())))
+        .ok()
+}
+
+/// read bit `pos` (msb first) of `bytes`, bits past the end read as zero
+fn bit(bytes: &[u8], pos: usize) -> usize {
+    match bytes.get(pos / 8) {
+        Some(b) => ((b >> (7 - pos % 8)) & 1) as usize,
+        None => 0,
+    }
+}
+
+/// first byte of the sha256 of `entropy`, computed in secure memory
+fn checksum_byte(entropy: &[u8]) -> u8 {
+    let mut hash = SecBuf::with_secure(32);
+    let mut hash = hash.write_lock();
+    unsafe {
+        rust_sodium_sys::crypto_hash_sha256(
+            raw_ptr_char!(hash),
+            entropy.as_ptr(),
+            entropy.len() as libc::c_ulonglong,
+        );
+    }
+    hash[0]
+}
+
+impl SecBuf {
+    /// render this SecBuf (16, 24 or 32 bytes of entropy) as a
+    /// 12, 18 or 24 word mnemonic phrase, including the checksum bits
+    pub fn to_mnemonic(&mut self) -> Result<String, SodiumError> {
+        check_init();
+        let entropy_len = self.len();
+        let cs_bits = checksum_bits(entropy_len)?;
+
+        let mut scratch = SecBuf::with_secure(SCRATCH_LEN);
+        let mut scratch = scratch.write_lock();
+        self.peek(|b| scratch[..entropy_len].copy_from_slice(b));
+        scratch[entropy_len] = checksum_byte(&scratch[..entropy_len]);
+
+        let word_count = (entropy_len * 8 + cs_bits) / BITS_PER_WORD;
+        let mut words = Vec::with_capacity(word_count);
+        for i in 0..word_count {
+            let mut idx = 0usize;
+            for bit in 0..BITS_PER_WORD {
+                let pos = i * BITS_PER_WORD + bit;
+                idx = (idx << 1) | ((scratch[pos / 8] >> (7 - pos % 8)) & 1) as usize;
+            }
+            words.push(WORDS[idx]);
+        }
+
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(scratch), scratch.len());
+        }
+        Ok(words.join(" "))
+    }
+
+    /// take a mnemonic phrase produced by `to_mnemonic`, verify its checksum
+    /// and load the entropy into a new SecBuf backed by secure memory
+    ///
+    /// words are matched case-insensitively and may be separated by any whitespace
+    pub fn secure_from_mnemonic(phrase: &str) -> Result<SecBuf, SodiumError> {
+        check_init();
+        let word_count = phrase.split_whitespace().count();
+        if word_count == 0 || word_count % 3 != 0 {
+            return Err(SodiumError::LengthMismatch(format!(
+                "mnemonic must be 12, 18 or 24 words, got {}",
+                word_count
+            )));
+        }
+        // 8 entropy bits per 0.25 checksum bits => 33 bits for every 32 bits of entropy
+        let entropy_len = word_count * BITS_PER_WORD * 32 / 33 / 8;
+        let cs_bits = checksum_bits(entropy_len)?;
+
+        let mut scratch = SecBuf::with_secure(SCRATCH_LEN);
+        let mut scratch = scratch.write_lock();
+        // sodium_malloc does not zero, and the word bits are or-ed in below
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(scratch), scratch.len());
+        }
+
+        let result = (|| {
+            for (i, word) in phrase.split_whitespace().enumerate() {
+                let idx = word_index(word).ok_or(SodiumError::UnknownMnemonicWord(i))?;
+                for bit in 0..BITS_PER_WORD {
+                    if (idx >> (BITS_PER_WORD - 1 - bit)) & 1 == 1 {
+                        let pos = i * BITS_PER_WORD + bit;
+                        scratch[pos / 8] |= 0x80 >> (pos % 8);
+                    }
+                }
+            }
+
+            let shift = 8 - cs_bits;
+            if checksum_byte(&scratch[..entropy_len]) >> shift != scratch[entropy_len] >> shift {
+                return Err(SodiumError::new("mnemonic checksum mismatch"));
+            }
+
+            let mut out = SecBuf::with_secure(entropy_len);
+            out.write(0, &scratch[..entropy_len])?;
+            Ok(out)
+        })();
+
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(scratch), scratch.len());
+        }
+        result
+    }
+
+    /// render this SecBuf followed by its reed-solomon parity bytes as mnemonic words,
+    /// 11 bits per word with the last word zero padded
+    ///
+    /// unlike `to_mnemonic` any length up to 250 bytes is accepted,
+    /// and `from_mnemonic_words` repairs a mistyped word
+    pub fn to_mnemonic_words(&mut self) -> Result<Vec<String>, SodiumError> {
+        check_init();
+        let len = self.encoded_len();
+        if len > MAX_CODEWORD_LEN {
+            return Err(SodiumError::new("SecBuf too large to render"));
+        }
+        let enc = Encoder::new(SecBuf::PARITY_LEN);
+        let mut enc = self.peek(|b| *enc.encode(b));
+
+        let word_count = (len * 8 + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        let words = (0..word_count)
+            .map(|i| {
+                let idx = (0..BITS_PER_WORD).fold(0, |idx, b| {
+                    (idx << 1) | bit(&enc[..len], i * BITS_PER_WORD + b)
+                });
+                WORDS[idx].to_string()
+            })
+            .collect();
+
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(enc), enc.len());
+        }
+        Ok(words)
+    }
+
+    /// take words produced by `to_mnemonic_words`, apply reed-solomon correction
+    /// and load the data into a new SecBuf backed by secure memory
+    /// (see `try_with_secure_any`, the data may be any length)
+    ///
+    /// words are matched case-insensitively
+    pub fn from_mnemonic_words(words: &[&str]) -> Result<SecBuf, SodiumError> {
+        check_init();
+        let bits = words.len() * BITS_PER_WORD;
+        let max_len = bits / 8;
+        if max_len <= SecBuf::PARITY_LEN || words.len() > MAX_CODEWORD_WORDS {
+            return Err(SodiumError::LengthMismatch(format!(
+                "bad mnemonic word count {}",
+                words.len()
+            )));
+        }
+
+        let mut scratch = SecBuf::try_with_secure(CODEWORD_SCRATCH_LEN)?;
+        let mut scratch = scratch.write_lock();
+        // sodium_malloc does not zero, and the word bits are or-ed in below
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(scratch), scratch.len());
+        }
+
+        let result = (|| {
+            for (i, word) in words.iter().enumerate() {
+                let idx = word_index(word).ok_or(SodiumError::UnknownMnemonicWord(i))?;
+                for b in 0..BITS_PER_WORD {
+                    if (idx >> (BITS_PER_WORD - 1 - b)) & 1 == 1 {
+                        let pos = i * BITS_PER_WORD + b;
+                        scratch[pos / 8] |= 0x80 >> (pos % 8);
+                    }
+                }
+            }
+
+            // the zero padding can be 8 or more bits, in which case one fewer byte
+            // gives the same word count, so both lengths are tried
+            let mut candidates = vec![max_len];
+            let shorter = max_len - 1;
+            if shorter > SecBuf::PARITY_LEN
+                && (shorter * 8 + BITS_PER_WORD - 1) / BITS_PER_WORD == words.len()
+            {
+                candidates.push(shorter);
+            }
+            // a length whose padding bits are set is only right if the last word was mistyped
+            candidates.sort_by_key(|n| (n * 8..bits).any(|pos| bit(&scratch, pos) == 1));
+
+            let dec = Decoder::new(SecBuf::PARITY_LEN);
+            let uncorrupted: Vec<usize> = candidates
+                .iter()
+                .cloned()
+                .filter(|n| !dec.is_corrupted(&scratch[..*n]))
+                .collect();
+            let mut corrected = uncorrupted
+                .iter()
+                .chain(candidates.iter())
+                .find_map(|n| dec.correct(&scratch[..*n], None).ok())
+                .ok_or(SodiumError::Uncorrectable {
+                    errors_detected: SecBuf::PARITY_LEN / 2 + 1,
+                    source: None,
+                })?;
+
+            let mut out = SecBuf::try_with_secure_any(corrected.data().len())?;
+            let written = out.write(0, corrected.data());
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(corrected), corrected.len());
+            }
+            written.map(|_| out)
+        })();
+
+        unsafe {
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(scratch), scratch.len());
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{random::random_secbuf, secbuf::ProtectState};
+
+    fn from_hex(s: &str) -> SecBuf {
+        let bytes: Vec<u8> = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect();
+        let mut b = SecBuf::with_insecure(bytes.len());
+        b.write(0, &bytes).unwrap();
+        b
+    }
+
+    #[test]
+    fn it_should_load_the_full_wordlist() {
+        assert_eq!(2048, WORDS.len());
+        assert_eq!("abandon", WORDS[0]);
+        assert_eq!("zoo", WORDS[2047]);
+    }
+
+    #[test]
+    fn it_should_round_trip_all_sizes() {
+        for (size, words) in [(16, 12), (24, 18), (32, 24)].iter() {
+            let mut b = SecBuf::with_secure(*size);
+            random_secbuf(&mut b);
+            let phrase = b.to_mnemonic().unwrap();
+            assert_eq!(*words, phrase.split(' ').count());
+
+            let mut c = SecBuf::secure_from_mnemonic(&phrase).unwrap();
+            let mut b = b.read_lock();
+            let c = c.read_lock();
+            assert_eq!(b.debug_unredacted(), c.debug_unredacted());
+            assert_eq!(phrase, b.to_mnemonic().unwrap());
+        }
+    }
+
+    #[test]
+    fn it_should_match_bip39_vectors() {
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            ),
+            (
+                "80808080808080808080808080808080",
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+            ),
+            (
+                "9e885d952ad362caeb4efe34a8e91bd2",
+                "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic",
+            ),
+            (
+                "6610b25967cdcca9d59875f5cb50b0ea75433311869e930b",
+                "gravity machine north sort system female filter attitude volume fold club stay feature office ecology stable narrow fog",
+            ),
+            (
+                "68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+                "hamster diagram private dutch cause delay private meat slide toddler razor book happy fancy gospel tennis maple dilemma loan word shrug inflict delay length",
+            ),
+            (
+                "f585c11aec520db57dd353c69554b21a89b20fb0650966fa0a9d6f74fd989d8f",
+                "void come effort suffer camp survey warrior heavy shoot primary clutch crush open amazing screen patrol group space point ten exist slush involve unfold",
+            ),
+        ];
+        for (hex, phrase) in vectors.iter() {
+            let mut b = from_hex(hex);
+            assert_eq!(*phrase, b.to_mnemonic().unwrap());
+
+            let mut c = SecBuf::secure_from_mnemonic(phrase).unwrap();
+            let mut b = b.read_lock();
+            let c = c.read_lock();
+            assert_eq!(b.debug_unredacted(), c.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_ignore_case_and_whitespace() {
+        let phrase =
+            "  Legal WINNER thank\tyear wave\n sausage worth useful legal  winner thank Yellow ";
+        let mut c = SecBuf::secure_from_mnemonic(phrase).unwrap();
+        let c = c.read_lock();
+        assert_eq!(format!("{:?}", vec![0x7fu8; 16]), c.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_report_unknown_word_index() {
+        let phrase = "legal winner thank year wave sausage worth usefull legal winner thank yellow";
+        match SecBuf::secure_from_mnemonic(phrase) {
+            Err(SodiumError::UnknownMnemonicWord(i)) => assert_eq!(7, i),
+            _ => panic!("expected UnknownMnemonicWord"),
+        }
+    }
+
+    #[test]
+    fn it_should_reject_bad_checksum_and_length() {
+        let phrase = "legal winner thank year wave sausage worth useful legal winner thank year";
+        assert!(SecBuf::secure_from_mnemonic(phrase).is_err());
+        assert!(SecBuf::secure_from_mnemonic("abandon abandon abandon").is_err());
+        assert!(SecBuf::secure_from_mnemonic("").is_err());
+
+        let mut b = SecBuf::with_insecure(20);
+        assert!(b.to_mnemonic().is_err());
+    }
+
+    #[test]
+    fn it_should_round_trip_mnemonic_words() {
+        let mut b = SecBuf::with_secure(32);
+        random_secbuf(&mut b);
+        let words = b.to_mnemonic_words().unwrap();
+        assert_eq!(27, words.len());
+
+        let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+        let mut c = SecBuf::from_mnemonic_words(&words).unwrap();
+        assert!(c.is_secure());
+        assert_eq!(ProtectState::NoAccess, c.protect_state());
+        let b = b.read_lock();
+        let c = c.read_lock();
+        assert_eq!(b.debug_unredacted(), c.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_round_trip_mnemonic_words_of_any_length() {
+        for len in 1..=64 {
+            let mut b = SecBuf::with_insecure(len);
+            random_secbuf(&mut b);
+            let words = b.to_mnemonic_words().unwrap();
+            let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+            let mut c = SecBuf::from_mnemonic_words(&words).unwrap();
+            assert!(c.is_secure());
+            assert_eq!(len, c.len());
+            let b = b.read_lock();
+            let c = c.read_lock();
+            assert_eq!(b.debug_unredacted(), c.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_correct_a_neighbouring_word() {
+        let mut b = SecBuf::with_secure(32);
+        random_secbuf(&mut b);
+        let words = b.to_mnemonic_words().unwrap();
+        // each of these words covers at most two bytes of the codeword
+        for i in [0, 12, 26].iter() {
+            let mut words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+            let idx = word_index(words[*i]).unwrap();
+            words[*i] = WORDS[if idx == 0 { 1 } else { idx - 1 }];
+
+            let mut c = SecBuf::from_mnemonic_words(&words).unwrap();
+            let b = b.read_lock();
+            let c = c.read_lock();
+            assert_eq!(b.debug_unredacted(), c.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_reject_bad_mnemonic_words() {
+        match SecBuf::from_mnemonic_words(&["zoo", "zoo"]) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+        let mut words = vec!["abandon"; 27];
+        words[4] = "notaword";
+        match SecBuf::from_mnemonic_words(&words) {
+            Err(SodiumError::UnknownMnemonicWord(4)) => (),
+            _ => panic!("expected UnknownMnemonicWord"),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! This module provides a pool of pre-allocated secure SecBufs,
+//! amortizing the cost of sodium_malloc in hot paths
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+use super::{check_init, secbuf::SecBuf};
+use crate::error::SodiumError;
+
+/// a fixed size pool of secure SecBufs, all of the same size
+pub struct SecBufPool {
+    free: Arc<Mutex<Vec<SecBuf>>>,
+    buf_size: usize,
+}
+
+impl SecBufPool {
+    /// pre-allocate `capacity` secure SecBufs of `buf_size` bytes each
+    pub fn new(capacity: usize, buf_size: usize) -> Result<Self, SodiumError> {
+        let mut free = Vec::with_capacity(capacity);
+        for _ in 0..capacity {
+            free.push(SecBuf::try_with_secure(buf_size)?);
+        }
+        Ok(SecBufPool {
+            free: Arc::new(Mutex::new(free)),
+            buf_size,
+        })
+    }
+
+    /// the size of each SecBuf in this pool
+    pub fn buf_size(&self) -> usize {
+        self.buf_size
+    }
+
+    /// a panic while the lock was held cannot leave the free list inconsistent,
+    /// so a poisoned lock is simply recovered
+    fn free(&self) -> std::sync::MutexGuard<Vec<SecBuf>> {
+        self.free.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// how many SecBufs are currently available to `acquire`
+    pub fn available(&self) -> usize {
+        self.free().len()
+    }
+
+    /// lease a SecBuf from the pool, it is zeroed and returned when dropped
+    pub fn acquire(&mut self) -> Result<PooledSecBuf, SodiumError> {
+        match self.free().pop() {
+            Some(b) => Ok(PooledSecBuf {
+                b: Some(b),
+                free: self.free.clone(),
+            }),
+            None => Err(SodiumError::PoolExhausted),
+        }
+    }
+}
+
+/// a SecBuf leased from a SecBufPool
+pub struct PooledSecBuf {
+    b: Option<SecBuf>,
+    free: Arc<Mutex<Vec<SecBuf>>>,
+}
+
+impl Deref for PooledSecBuf {
+    type Target = SecBuf;
+
+    fn deref(&self) -> &SecBuf {
+        self.b.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledSecBuf {
+    fn deref_mut(&mut self) -> &mut SecBuf {
+        self.b.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledSecBuf {
+    fn drop(&mut self) {
+        if let Some(mut b) = self.b.take() {
+            check_init();
+            {
+                let mut b = b.write_lock();
+                unsafe {
+                    rust_sodium_sys::sodium_memzero(raw_ptr_void!(b), b.len());
+                }
+            }
+            self.free.lock().unwrap_or_else(|e| e.into_inner()).push(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_lease_and_return_zeroed() {
+        let mut pool = SecBufPool::new(3, 16).unwrap();
+        assert_eq!(16, pool.buf_size());
+
+        let mut leased = Vec::new();
+        for _ in 0..3 {
+            let mut b = pool.acquire().unwrap();
+            b.write(0, &[9; 16]).unwrap();
+            leased.push(b);
+        }
+        assert_eq!(0, pool.available());
+        match pool.acquire() {
+            Err(SodiumError::PoolExhausted) => (),
+            _ => panic!("expected PoolExhausted"),
+        }
+
+        leased.pop();
+        assert_eq!(1, pool.available());
+        let mut b = pool.acquire().unwrap();
+        assert_eq!(16, b.len());
+        let b = b.read_lock();
+        assert_eq!(format!("{:?}", [0u8; 16].to_vec()), b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_reject_bad_buf_size() {
+        assert!(SecBufPool::new(2, 12).is_err());
+    }
+}
@@ -1,7 +1,18 @@
 //! This module provides access to libsodium
 
-use super::{check_init, secbuf::SecBuf};
+use super::{
+    check_init,
+    secbuf::{ProtectState, SecBuf},
+};
 use crate::error::SodiumError;
+use std::{
+    ffi::{CStr, CString},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+};
 
 pub const OPSLIMIT_INTERACTIVE: u64 = rust_sodium_sys::crypto_pwhash_OPSLIMIT_INTERACTIVE as u64;
 pub const MEMLIMIT_INTERACTIVE: usize =
@@ -16,6 +27,7 @@ pub const ALG_ARGON2ID13: i8 = rust_sodium_sys::crypto_pwhash_ALG_ARGON2ID13 as
 
 pub const HASHBYTES: usize = 32 as usize;
 pub const SALTBYTES: usize = rust_sodium_sys::crypto_pwhash_SALTBYTES as usize;
+pub const STRBYTES: usize = rust_sodium_sys::crypto_pwhash_STRBYTES as usize;
 
 /// Calculate a password hash
 ///
@@ -59,10 +71,399 @@ pub fn hash(
     Ok(())
 }
 
+/// The ops / mem cost and algorithm passed to `hash_with_params`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PwHashParams {
+    pub ops_limit: u64,
+    pub mem_limit: usize,
+    pub alg: i8,
+}
+
+impl PwHashParams {
+    /// fast enough for interactive logins
+    pub fn interactive() -> Self {
+        PwHashParams {
+            ops_limit: OPSLIMIT_INTERACTIVE,
+            mem_limit: MEMLIMIT_INTERACTIVE,
+            alg: ALG_ARGON2ID13,
+        }
+    }
+
+    pub fn moderate() -> Self {
+        PwHashParams {
+            ops_limit: OPSLIMIT_MODERATE,
+            mem_limit: MEMLIMIT_MODERATE,
+            alg: ALG_ARGON2ID13,
+        }
+    }
+
+    /// for long term secrets, takes seconds and 1 GiB of memory
+    pub fn sensitive() -> Self {
+        PwHashParams {
+            ops_limit: OPSLIMIT_SENSITIVE,
+            mem_limit: MEMLIMIT_SENSITIVE,
+            alg: ALG_ARGON2ID13,
+        }
+    }
+
+    /// argon2id with the given limits, which must be within libsodium's bounds
+    pub fn custom(ops_limit: u64, mem_limit: usize) -> Result<Self, SodiumError> {
+        check_init();
+        let ops_min = u64::from(rust_sodium_sys::crypto_pwhash_OPSLIMIT_MIN);
+        let ops_max = u64::from(rust_sodium_sys::crypto_pwhash_OPSLIMIT_MAX);
+        if ops_limit < ops_min || ops_limit > ops_max {
+            return Err(SodiumError::OutOfBounds(format!(
+                "pwhash ops limit must be {} to {}, got {}",
+                ops_min, ops_max, ops_limit
+            )));
+        }
+        let mem_min = rust_sodium_sys::crypto_pwhash_MEMLIMIT_MIN as usize;
+        let mem_max = unsafe { rust_sodium_sys::crypto_pwhash_memlimit_max() };
+        if mem_limit < mem_min || mem_limit > mem_max {
+            return Err(SodiumError::OutOfBounds(format!(
+                "pwhash mem limit must be {} to {}, got {}",
+                mem_min, mem_max, mem_limit
+            )));
+        }
+        Ok(PwHashParams {
+            ops_limit,
+            mem_limit,
+            alg: ALG_ARGON2ID13,
+        })
+    }
+}
+
+/// Calculate a password hash into secure memory
+///
+/// @param {SecBuf} password - the password to hash
+///
+/// @param {SecBuf} salt - SALTBYTES of salt
+///
+/// @param {SecBuf} out - the hash, must be backed by secure memory
+///
+/// @param {PwHashParams} params - the cost, e.g. `PwHashParams::moderate()`
+pub fn hash_with_params(
+    password: &mut SecBuf,
+    salt: &mut SecBuf,
+    out: &mut SecBuf,
+    params: PwHashParams,
+) -> Result<(), SodiumError> {
+    check_init();
+    if salt.len() != SALTBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "salt must be a Buffer of length: {}, got {}.",
+            SALTBYTES,
+            salt.len()
+        )));
+    }
+    let out_min = rust_sodium_sys::crypto_pwhash_BYTES_MIN as usize;
+    let out_max = unsafe { rust_sodium_sys::crypto_pwhash_bytes_max() };
+    if out.len() < out_min || out.len() > out_max {
+        return Err(SodiumError::OutputLength(format!(
+            "pwhash output must be {} to {} bytes, got {}",
+            out_min,
+            out_max,
+            out.len()
+        )));
+    }
+    if !out.is_secure() {
+        return Err(SodiumError::new(
+            "pwhash output must be backed by secure memory",
+        ));
+    }
+    let salt = salt.read_lock();
+    let password = password.read_lock();
+    let mut out = out.write_lock();
+    let res = unsafe {
+        rust_sodium_sys::crypto_pwhash(
+            raw_ptr_char!(out),
+            out.len() as libc::c_ulonglong,
+            raw_ptr_ichar_immut!(password),
+            password.len() as libc::c_ulonglong,
+            raw_ptr_char_immut!(salt),
+            params.ops_limit as libc::c_ulonglong,
+            params.mem_limit,
+            libc::c_int::from(params.alg),
+        )
+    };
+    if res != 0 {
+        return Err(SodiumError::Libsodium {
+            function: "crypto_pwhash",
+            code: res,
+        });
+    }
+    Ok(())
+}
+
+/// Calculate a self-contained password hash string (algorithm, limits, salt and hash),
+/// for storing a passphrase verifier
+///
+/// @param {SecBuf} password - the password to hash
+///
+/// @param {PwHashParams} params - the cost, e.g. `PwHashParams::interactive()`
+pub fn hash_str(password: &mut SecBuf, params: PwHashParams) -> Result<String, SodiumError> {
+    check_init();
+    let mut out = vec![0 as libc::c_char; STRBYTES];
+    let password = password.read_lock();
+    let res = unsafe {
+        rust_sodium_sys::crypto_pwhash_str_alg(
+            out.as_mut_ptr(),
+            raw_ptr_ichar_immut!(password),
+            password.len() as libc::c_ulonglong,
+            params.ops_limit as libc::c_ulonglong,
+            params.mem_limit,
+            libc::c_int::from(params.alg),
+        )
+    };
+    if res != 0 {
+        return Err(SodiumError::Libsodium {
+            function: "crypto_pwhash_str_alg",
+            code: res,
+        });
+    }
+    let hash = unsafe { CStr::from_ptr(out.as_ptr()) };
+    Ok(hash.to_string_lossy().into_owned())
+}
+
+/// the nul terminated form of `hash`, if it parses as a hash string
+/// (`crypto_pwhash_str_needs_rehash` returns -1 only when it cannot)
+fn parse_hash_str(hash: &str) -> Result<CString, SodiumError> {
+    check_init();
+    if hash.len() >= STRBYTES {
+        return Err(SodiumError::MalformedHash);
+    }
+    let hash = CString::new(hash).map_err(|_| SodiumError::MalformedHash)?;
+    let res = unsafe {
+        rust_sodium_sys::crypto_pwhash_str_needs_rehash(
+            hash.as_ptr(),
+            rust_sodium_sys::crypto_pwhash_OPSLIMIT_MIN as libc::c_ulonglong,
+            rust_sodium_sys::crypto_pwhash_MEMLIMIT_MIN as usize,
+        )
+    };
+    if res < 0 {
+        return Err(SodiumError::MalformedHash);
+    }
+    Ok(hash)
+}
+
+/// Verify a password against a hash string from `hash_str`
+///
+/// @param {&str} hash - the stored hash string
+///
+/// @param {SecBuf} password - the password to check
+///
+/// @UseReturn Ok(false) if the password does not match, MalformedHash if `hash` does not parse
+pub fn verify_str(hash: &str, password: &mut SecBuf) -> Result<bool, SodiumError> {
+    let hash = parse_hash_str(hash)?;
+    let password = password.read_lock();
+    let res = unsafe {
+        rust_sodium_sys::crypto_pwhash_str_verify(
+            hash.as_ptr(),
+            raw_ptr_ichar_immut!(password),
+            password.len() as libc::c_ulonglong,
+        )
+    };
+    Ok(res == 0)
+}
+
+/// whether a hash string from `hash_str` was made with different `params`
+/// (or does not parse at all), so it should be replaced at the next successful login
+pub fn needs_rehash(hash: &str, params: PwHashParams) -> bool {
+    let hash = match parse_hash_str(hash) {
+        Ok(hash) => hash,
+        Err(_) => return true,
+    };
+    let prefix: &[u8] = if params.alg == ALG_ARGON2I13 {
+        b"$argon2i$"
+    } else {
+        b"$argon2id$"
+    };
+    if !hash.as_bytes().starts_with(prefix) {
+        return true;
+    }
+    let res = unsafe {
+        rust_sodium_sys::crypto_pwhash_str_needs_rehash(
+            hash.as_ptr(),
+            params.ops_limit as libc::c_ulonglong,
+            params.mem_limit,
+        )
+    };
+    res != 0
+}
+
+/// whether the derivation behind a `PwHashHandle` has finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PwHashPoll {
+    Pending,
+    /// `wait()` returns the result without blocking
+    Ready,
+}
+
+fn zero(b: &mut SecBuf) {
+    let mut b = b.write_lock();
+    unsafe {
+        rust_sodium_sys::sodium_memzero(raw_ptr_void!(b), b.len());
+    }
+}
+
+/// the password and salt moved into a `hash_async` worker,
+/// zeroed before the worker returns them, and on drop in case it panicked
+struct WorkerInputs {
+    password: SecBuf,
+    salt: SecBuf,
+}
+
+impl WorkerInputs {
+    fn zero(&mut self) {
+        // a panic inside a Locker could leave a buffer unlocked, zeroing it would panic again
+        for b in &mut [&mut self.password, &mut self.salt] {
+            if b.protect_state() == ProtectState::NoAccess {
+                zero(b);
+            }
+        }
+    }
+}
+
+impl Drop for WorkerInputs {
+    fn drop(&mut self) {
+        self.zero();
+    }
+}
+
+/// A password hash running on its own thread, returned by `hash_async`
+///
+/// the password and salt are zeroed when the worker exits, whether it finished,
+/// was cancelled or panicked, dropping the handle cancels it
+pub struct PwHashHandle {
+    rx: mpsc::Receiver<Result<SecBuf, SodiumError>>,
+    result: Option<Result<SecBuf, SodiumError>>,
+    cancelled: Arc<AtomicBool>,
+    worker: Option<thread::JoinHandle<WorkerInputs>>,
+}
+
+impl PwHashHandle {
+    /// check for the result without blocking
+    pub fn try_poll(&mut self) -> PwHashPoll {
+        if self.result.is_none() {
+            self.result = match self.rx.try_recv() {
+                Ok(result) => Some(result),
+                Err(mpsc::TryRecvError::Empty) => return PwHashPoll::Pending,
+                Err(mpsc::TryRecvError::Disconnected) => Some(Err(worker_panicked())),
+            };
+        }
+        PwHashPoll::Ready
+    }
+
+    /// block until the derivation finishes
+    ///
+    /// @UseReturn the HASHBYTES secure hash, as `hash_with_params` would write
+    pub fn wait(mut self) -> Result<SecBuf, SodiumError> {
+        let result = match self.result.take() {
+            Some(result) => result,
+            None => self.rx.recv().unwrap_or_else(|_| Err(worker_panicked())),
+        };
+        self.join();
+        result
+    }
+
+    /// abandon the derivation, its result is zeroed rather than returned
+    ///
+    /// libsodium cannot interrupt a running hash, so this blocks until it ends,
+    /// after which none of the inputs or output are readable
+    pub fn cancel(mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(Ok(mut out)) = self.result.take() {
+            zero(&mut out);
+        }
+        self.join();
+        if let Ok(Ok(mut out)) = self.rx.try_recv() {
+            zero(&mut out);
+        }
+    }
+
+    /// wait for the worker to exit, `None` if it panicked
+    fn join(&mut self) -> Option<WorkerInputs> {
+        self.worker.take().and_then(|worker| worker.join().ok())
+    }
+}
+
+impl Drop for PwHashHandle {
+    fn drop(&mut self) {
+        if self.worker.is_some() {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+fn worker_panicked() -> SodiumError {
+    SodiumError::new("pwhash worker thread panicked")
+}
+
+/// Calculate a HASHBYTES password hash on a new thread, so a slow `PwHashParams::sensitive()`
+/// hash does not block the caller, the password and salt are moved into the worker
+///
+/// @param {SecBuf} password - the password to hash, zeroed once the worker exits
+///
+/// @param {SecBuf} salt - SALTBYTES of salt, zeroed once the worker exits
+///
+/// @param {PwHashParams} params - the cost, e.g. `PwHashParams::sensitive()`
+pub fn hash_async(password: SecBuf, salt: SecBuf, params: PwHashParams) -> PwHashHandle {
+    spawn_hash(password, salt, params, SecBuf::try_with_secure(HASHBYTES))
+}
+
+/// `hash_async` into the given output buffer, or the error allocating it
+fn spawn_hash(
+    password: SecBuf,
+    salt: SecBuf,
+    params: PwHashParams,
+    out: Result<SecBuf, SodiumError>,
+) -> PwHashHandle {
+    let (tx, rx) = mpsc::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let worker_cancelled = cancelled.clone();
+    let worker = thread::spawn(move || {
+        let mut inputs = WorkerInputs { password, salt };
+        if worker_cancelled.load(Ordering::SeqCst) {
+            if let Ok(mut out) = out {
+                zero(&mut out);
+            }
+            inputs.zero();
+            return inputs;
+        }
+        let result = out.and_then(|mut out| {
+            hash_with_params(&mut inputs.password, &mut inputs.salt, &mut out, params)?;
+            Ok(out)
+        });
+        match result {
+            Ok(mut out) => {
+                if worker_cancelled.load(Ordering::SeqCst) {
+                    zero(&mut out);
+                } else {
+                    let _ = tx.send(Ok(out));
+                }
+            }
+            Err(e) => {
+                let _ = tx.send(Err(e));
+            }
+        }
+        inputs.zero();
+        inputs
+    });
+    PwHashHandle {
+        rx,
+        result: None,
+        cancelled,
+        worker: Some(worker),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::random::random_secbuf;
+    use crate::{
+        random::random_secbuf,
+        secbuf::spy::{panic_on_read, spy, spy_with_len},
+    };
 
     #[test]
     fn it_should_generate_with_random_salt() {
@@ -102,7 +503,7 @@ mod tests {
         )
         .unwrap();
         let pw2_hash = pw2_hash.read_lock();
-        assert_eq!("[84, 166, 168, 46, 130, 222, 122, 144, 123, 49, 206, 167, 35, 180, 246, 154, 25, 43, 218, 177, 95, 218, 12, 241, 234, 207, 230, 93, 127, 174, 221, 106]",  format!("{:?}", *pw2_hash));
+        assert_eq!("[84, 166, 168, 46, 130, 222, 122, 144, 123, 49, 206, 167, 35, 180, 246, 154, 25, 43, 218, 177, 95, 218, 12, 241, 234, 207, 230, 93, 127, 174, 221, 106]",  pw2_hash.debug_unredacted());
     }
     #[test]
     fn it_should_generate_consistantly() {
@@ -132,7 +533,211 @@ mod tests {
         .unwrap();
         let pw1_hash = pw1_hash.read_lock();
         let pw2_hash = pw2_hash.read_lock();
-        assert_eq!(format!("{:?}", *pw1_hash), format!("{:?}", *pw2_hash));
+        assert_eq!(pw1_hash.debug_unredacted(), pw2_hash.debug_unredacted());
+    }
+
+    /// hash `password` with a fixed salt
+    fn hash_params(password: &mut SecBuf, params: PwHashParams) -> String {
+        let mut salt = SecBuf::with_insecure(SALTBYTES);
+        salt.fill(7);
+        let mut out = SecBuf::with_secure(HASHBYTES);
+        hash_with_params(password, &mut salt, &mut out, params).unwrap();
+        let out = out.read_lock();
+        out.debug_unredacted()
+    }
+
+    #[test]
+    fn it_should_hash_with_params_consistently() {
+        let mut password = SecBuf::with_insecure_from_string("pass phrase".to_string());
+        let fast = PwHashParams::custom(1, 8192).unwrap();
+        let slower = PwHashParams::custom(2, 8192).unwrap();
+        assert_eq!(
+            hash_params(&mut password, fast),
+            hash_params(&mut password, fast)
+        );
+        assert_ne!(
+            hash_params(&mut password, fast),
+            hash_params(&mut password, slower)
+        );
+        assert_ne!(
+            hash_params(&mut password, fast),
+            hash_params(&mut password, PwHashParams::interactive())
+        );
+    }
+
+    #[test]
+    fn it_should_map_presets_to_libsodium_limits() {
+        assert_eq!(OPSLIMIT_INTERACTIVE, PwHashParams::interactive().ops_limit);
+        assert_eq!(MEMLIMIT_MODERATE, PwHashParams::moderate().mem_limit);
+        assert_eq!(OPSLIMIT_SENSITIVE, PwHashParams::sensitive().ops_limit);
+        assert_eq!(ALG_ARGON2ID13, PwHashParams::sensitive().alg);
+    }
+
+    #[test]
+    fn it_should_reject_bad_hash_params() {
+        match PwHashParams::custom(0, 8192) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+        assert!(PwHashParams::custom(1, 1024).is_err());
+
+        let mut password = SecBuf::with_insecure_from_string("pass phrase".to_string());
+        let params = PwHashParams::custom(1, 8192).unwrap();
+        let mut salt = SecBuf::with_insecure(SALTBYTES - 1);
+        let mut out = SecBuf::with_secure(HASHBYTES);
+        match hash_with_params(&mut password, &mut salt, &mut out, params) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+        let mut salt = SecBuf::with_insecure(SALTBYTES);
+        let mut out = SecBuf::with_insecure(HASHBYTES);
+        assert!(hash_with_params(&mut password, &mut salt, &mut out, params).is_err());
     }
 
+    #[test]
+    fn it_should_verify_a_hash_str() {
+        let params = PwHashParams::custom(1, 8192).unwrap();
+        let mut password = SecBuf::with_insecure_from_string("pass phrase".to_string());
+        let hash = hash_str(&mut password, params).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_str(&hash, &mut password).unwrap());
+        // salted, so hashing again gives a different string
+        assert_ne!(hash, hash_str(&mut password, params).unwrap());
+
+        let mut wrong = SecBuf::with_insecure_from_string("pass phrasf".to_string());
+        assert!(!verify_str(&hash, &mut wrong).unwrap());
+    }
+
+    #[test]
+    fn it_should_reject_a_malformed_hash_str() {
+        let mut password = SecBuf::with_insecure_from_string("pass phrase".to_string());
+        for hash in &[
+            "",
+            "not a hash",
+            "$argon2id$v=19$m=8,t=1,p=1$",
+            "$argon2id\0$",
+        ] {
+            match verify_str(hash, &mut password) {
+                Err(SodiumError::MalformedHash) => (),
+                r => panic!("expected MalformedHash for {:?}, got {:?}", hash, r),
+            }
+        }
+        assert!(needs_rehash("not a hash", PwHashParams::interactive()));
+    }
+
+    #[test]
+    fn it_should_know_when_to_rehash() {
+        let params = PwHashParams::custom(1, 8192).unwrap();
+        let mut password = SecBuf::with_insecure_from_string("pass phrase".to_string());
+        let hash = hash_str(&mut password, params).unwrap();
+        assert!(!needs_rehash(&hash, params));
+        assert!(needs_rehash(&hash, PwHashParams::custom(2, 8192).unwrap()));
+        assert!(needs_rehash(&hash, PwHashParams::interactive()));
+    }
+
+    #[test]
+    fn it_should_hash_async_like_sync() {
+        let params = PwHashParams::custom(1, 8192).unwrap();
+        let mut password = SecBuf::with_insecure_from_string("pass phrase".to_string());
+        let expected = hash_params(&mut password, params);
+
+        let mut salt = SecBuf::with_insecure(SALTBYTES);
+        salt.fill(7);
+        let mut handle = hash_async(password, salt, params);
+        while handle.try_poll() == PwHashPoll::Pending {
+            thread::yield_now();
+        }
+        assert_eq!(PwHashPoll::Ready, handle.try_poll());
+        let mut out = handle.wait().unwrap();
+        assert!(out.is_secure());
+        assert_eq!(expected, out.read_lock().debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_be_pending_until_the_hash_is_done() {
+        let params = PwHashParams::custom(4, 64 * 1024 * 1024).unwrap();
+        let mut password = SecBuf::with_secure(HASHBYTES);
+        random_secbuf(&mut password);
+        let mut handle = hash_async(password, SecBuf::with_insecure(SALTBYTES), params);
+        assert_eq!(PwHashPoll::Pending, handle.try_poll());
+        while handle.try_poll() == PwHashPoll::Pending {
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(HASHBYTES, handle.wait().unwrap().len());
+    }
+
+    #[test]
+    fn it_should_report_errors_from_the_worker() {
+        let params = PwHashParams::custom(1, 8192).unwrap();
+        let password = SecBuf::with_secure(HASHBYTES);
+        let handle = hash_async(password, SecBuf::with_insecure(SALTBYTES - 1), params);
+        match handle.wait() {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+    }
+
+    #[test]
+    fn it_should_cancel_whether_or_not_the_hash_is_done() {
+        let params = PwHashParams::custom(1, 8192).unwrap();
+        let inputs = || {
+            let mut password = SecBuf::with_secure(HASHBYTES);
+            random_secbuf(&mut password);
+            let mut salt = SecBuf::with_insecure(SALTBYTES);
+            random_secbuf(&mut salt);
+            (password, salt)
+        };
+
+        let (password, salt) = inputs();
+        hash_async(password, salt, params).cancel();
+
+        let (password, salt) = inputs();
+        let mut handle = hash_async(password, salt, params);
+        while handle.try_poll() == PwHashPoll::Pending {
+            thread::yield_now();
+        }
+        handle.cancel();
+
+        let (password, salt) = inputs();
+        drop(hash_async(password, salt, params));
+
+        // cancelling leaves secure memory usable for the next hash
+        let (password, salt) = inputs();
+        assert_eq!(
+            HASHBYTES,
+            hash_async(password, salt, params).wait().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn it_should_leave_nothing_readable_after_cancel() {
+        let params = PwHashParams::custom(1, 8192).unwrap();
+        for &wait_until_ready in &[false, true] {
+            let (password, password_zeroed) = spy(true);
+            let (salt, salt_zeroed) = spy_with_len(false, SALTBYTES);
+            let (out, out_zeroed) = spy_with_len(true, HASHBYTES);
+            let mut handle = spawn_hash(password, salt, params, Ok(out));
+            while wait_until_ready && handle.try_poll() == PwHashPoll::Pending {
+                thread::yield_now();
+            }
+            handle.cancel();
+            assert_eq!(Some(true), *password_zeroed.lock().unwrap());
+            assert_eq!(Some(true), *salt_zeroed.lock().unwrap());
+            assert_eq!(Some(true), *out_zeroed.lock().unwrap());
+        }
+    }
+
+    #[test]
+    fn it_should_zero_the_inputs_when_the_worker_panics() {
+        let params = PwHashParams::custom(1, 8192).unwrap();
+        let (password, password_zeroed) = panic_on_read(HASHBYTES);
+        let (salt, salt_zeroed) = spy_with_len(false, SALTBYTES);
+        let handle = hash_async(password, salt, params);
+        match handle.wait() {
+            Err(SodiumError::Generic(m)) => assert_eq!("pwhash worker thread panicked", m),
+            _ => panic!("expected the worker to panic"),
+        }
+        assert_eq!(Some(true), *password_zeroed.lock().unwrap());
+        assert_eq!(Some(true), *salt_zeroed.lock().unwrap());
+    }
 }
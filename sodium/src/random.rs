@@ -3,8 +3,15 @@
 use super::check_init;
 
 use super::secbuf::SecBuf;
+use crate::error::SodiumError;
+use std::ops::Range;
+
+/// the required seed length for `random_secbuf_deterministic`
+pub const SEEDBYTES: usize = rust_sodium_sys::randombytes_SEEDBYTES as usize;
 
 /// randomize the provided SecBuf
+/// the write lock is taken here, so the buffer should be passed NoAccess,
+/// and it is left NoAccess
 pub fn random_secbuf(b: &mut SecBuf) {
     check_init();
     unsafe {
@@ -13,13 +20,263 @@ pub fn random_secbuf(b: &mut SecBuf) {
     }
 }
 
+/// randomize only the bytes of `b` within `range`, the rest are left untouched
+pub fn random_secbuf_range(b: &mut SecBuf, range: Range<usize>) -> Result<(), SodiumError> {
+    if range.start > range.end || range.end > b.len() {
+        return Err(SodiumError::OutOfBounds(format!(
+            "range {:?} is outside a Buffer of length {}",
+            range,
+            b.len()
+        )));
+    }
+    check_init();
+    let mut b = b.write_lock();
+    let part = &mut b[range];
+    unsafe {
+        rust_sodium_sys::randombytes_buf(raw_ptr_void!(part), part.len());
+    }
+    Ok(())
+}
+
+/// fill the provided SecBuf with bytes deterministically derived from `seed`
+/// the same seed always produces the same bytes, use this for reproducible tests only
+///
+/// @param {SecBuf} b - the buffer to fill
+///
+/// @param {SecBuf} seed - must be SEEDBYTES long
+pub fn random_secbuf_deterministic(b: &mut SecBuf, seed: &mut SecBuf) -> Result<(), SodiumError> {
+    if seed.len() != SEEDBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "seed must be a Buffer of length: {}, got {}.",
+            SEEDBYTES,
+            seed.len()
+        )));
+    }
+    check_init();
+    let seed = seed.read_lock();
+    let mut b = b.write_lock();
+    unsafe {
+        rust_sodium_sys::randombytes_buf_deterministic(
+            raw_ptr_void!(b),
+            b.len(),
+            raw_ptr_char_immut!(seed),
+        );
+    }
+    Ok(())
+}
+
+/// a random u64, for things like jitter or ids
+/// (not for key material, use a SecBuf for that)
+pub fn random_u64() -> u64 {
+    check_init();
+    let mut b = [0u8; 8];
+    unsafe {
+        rust_sodium_sys::randombytes_buf(raw_ptr_void!(b), b.len());
+    }
+    u64::from_le_bytes(b)
+}
+
+/// an unbiased random number in 0..upper_bound, 0 if upper_bound is 0
+/// (not for key material, use a SecBuf for that)
+pub fn random_uniform(upper_bound: u32) -> u32 {
+    check_init();
+    unsafe { rust_sodium_sys::randombytes_uniform(upper_bound) }
+}
+
+/// shuffle `items` in place (Fisher-Yates over `random_uniform`)
+/// (not for key material, use a SecBuf for that)
+///
+/// panics if `items` has more than u32::max_value() elements
+pub fn shuffle<T>(items: &mut [T]) {
+    assert!(items.len() <= u32::max_value() as usize);
+    for i in (1..items.len()).rev() {
+        let j = random_uniform(i as u32 + 1) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// allocate a new SecBuf backed by secure memory, filled with random bytes
+/// (for things like seeds), `size` need not be a multiple of 8
+pub fn random_secure(size: usize) -> SecBuf {
+    let mut b = SecBuf::with_secure_any(size);
+    random_secbuf(&mut b);
+    b
+}
+
+/// allocate a new SecBuf backed by insecure memory, filled with random bytes
+/// (for things like nonces)
+pub fn random_insecure(size: usize) -> SecBuf {
+    let mut b = SecBuf::with_insecure(size);
+    random_secbuf(&mut b);
+    b
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::secbuf::ProtectState;
 
     #[test]
     fn it_should_randomize_buffer() {
         let mut b = SecBuf::with_insecure(1);
         random_secbuf(&mut b);
     }
+
+    #[test]
+    fn it_should_allocate_random_secure() {
+        assert_eq!(20, random_secure(20).len());
+        let mut a = random_secure(32);
+        let mut b = random_secure(32);
+        assert_eq!(32, a.len());
+        let a = a.read_lock();
+        let b = b.read_lock();
+        assert_ne!(a.debug_unredacted(), b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_allocate_random_insecure() {
+        let mut a = random_insecure(24);
+        let mut b = random_insecure(24);
+        assert_eq!(24, a.len());
+        let a = a.read_lock();
+        let b = b.read_lock();
+        assert_ne!(a.debug_unredacted(), b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_randomize_deterministically() {
+        let mut seed = SecBuf::with_insecure(SEEDBYTES);
+        seed.write(0, &[7; SEEDBYTES]).unwrap();
+        let mut a = SecBuf::with_insecure(64);
+        let mut b = SecBuf::with_insecure(64);
+        random_secbuf_deterministic(&mut a, &mut seed).unwrap();
+        random_secbuf_deterministic(&mut b, &mut seed).unwrap();
+
+        let mut other_seed = SecBuf::with_insecure(SEEDBYTES);
+        other_seed.write(0, &[8; SEEDBYTES]).unwrap();
+        let mut c = SecBuf::with_insecure(64);
+        random_secbuf_deterministic(&mut c, &mut other_seed).unwrap();
+
+        let a = a.read_lock();
+        let b = b.read_lock();
+        let c = c.read_lock();
+        assert_eq!(a.debug_unredacted(), b.debug_unredacted());
+        assert_ne!(a.debug_unredacted(), c.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_reject_bad_deterministic_seed() {
+        let mut seed = SecBuf::with_insecure(16);
+        let mut b = SecBuf::with_insecure(8);
+        random_secbuf_deterministic(&mut b, &mut seed).expect_err("should have failed");
+    }
+
+    #[test]
+    fn it_should_randomize_noaccess_buffer() {
+        let mut b = SecBuf::with_secure(16);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        random_secbuf(&mut b);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        let b = b.read_lock();
+        assert_ne!(format!("{:?}", [0u8; 16]), b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_match_deterministic_vector() {
+        // chacha20 ietf keystream for this key with the libsodium DRG nonce,
+        // fixed by libsodium, so identical on every platform
+        let mut seed = SecBuf::with_insecure(SEEDBYTES);
+        let bytes: Vec<u8> = (0..SEEDBYTES as u8).collect();
+        seed.write(0, &bytes).unwrap();
+        let mut b = SecBuf::with_secure(16);
+        random_secbuf_deterministic(&mut b, &mut seed).unwrap();
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        let b = b.read_lock();
+        assert_eq!(
+            "[13, 142, 108, 198, 135, 21, 100, 137, 38, 115, 46, 126, 167, 50, 80, 207]",
+            b.debug_unredacted()
+        );
+    }
+
+    #[test]
+    fn it_should_random_u64() {
+        assert_ne!(random_u64(), random_u64());
+    }
+
+    #[test]
+    fn it_should_random_uniform_within_bound() {
+        for _ in 0..100 {
+            assert_eq!(0, random_uniform(1));
+            assert!(random_uniform(7) < 7);
+        }
+    }
+
+    #[test]
+    fn it_should_random_uniform_evenly() {
+        const BUCKETS: usize = 10;
+        const SAMPLES: usize = 10_000;
+        let mut counts = [0usize; BUCKETS];
+        for _ in 0..SAMPLES {
+            counts[random_uniform(BUCKETS as u32) as usize] += 1;
+        }
+        let expected = (SAMPLES / BUCKETS) as f64;
+        let chi_square: f64 = counts
+            .iter()
+            .map(|c| (*c as f64 - expected).powi(2) / expected)
+            .sum();
+        // 9 degrees of freedom, 40 is far beyond the 0.9999 quantile
+        assert!(
+            chi_square < 40.0,
+            "chi square {} for {:?}",
+            chi_square,
+            counts
+        );
+    }
+
+    #[test]
+    fn it_should_shuffle_preserving_items() {
+        let original: Vec<u32> = (0..1000).collect();
+        let mut shuffled = original.clone();
+        shuffle(&mut shuffled);
+        assert_ne!(original, shuffled);
+        shuffled.sort();
+        assert_eq!(original, shuffled);
+
+        let mut empty: [u8; 0] = [];
+        shuffle(&mut empty);
+        let mut one = [5];
+        shuffle(&mut one);
+        assert_eq!([5], one);
+    }
+
+    #[test]
+    fn it_should_randomize_range() {
+        let mut b = SecBuf::with_secure(32);
+        let mut changed = false;
+        // all 8 bytes drawing zero is a 2^-64 chance, but retry rather than flake
+        for _ in 0..3 {
+            b.fill(0);
+            random_secbuf_range(&mut b, 8..16).unwrap();
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+            let b = b.read_lock();
+            assert!(b[..8].iter().chain(b[16..].iter()).all(|x| *x == 0));
+            if b[8..16].iter().any(|x| *x != 0) {
+                changed = true;
+                break;
+            }
+        }
+        assert!(changed);
+    }
+
+    #[test]
+    fn it_should_reject_bad_range() {
+        let mut b = SecBuf::with_insecure(16);
+        match random_secbuf_range(&mut b, 8..17) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+        let backwards = Range { start: 9, end: 8 };
+        assert!(random_secbuf_range(&mut b, backwards).is_err());
+        random_secbuf_range(&mut b, 16..16).unwrap();
+    }
 }
@@ -0,0 +1,102 @@
+//! This module provides X25519 scalar multiplication (crypto_scalarmult)
+//! over SecBufs, for DH-style key agreement
+
+use super::{check_init, secbuf::SecBuf};
+use crate::error::SodiumError;
+
+/// Size of a scalarmult secret scalar
+pub const SCALARBYTES: usize = rust_sodium_sys::crypto_scalarmult_SCALARBYTES as usize;
+
+/// Size of a group element, both the public input and the shared output
+pub const BYTES: usize = rust_sodium_sys::crypto_scalarmult_BYTES as usize;
+
+/// multiply the group element `public` by the scalar `secret`
+///
+/// prefer the kx session functions, which hash the result with both public keys
+///
+/// @param {SecBuf} secret - the SCALARBYTES secret scalar
+///
+/// @param {SecBuf} public - the other party's BYTES public key
+///
+/// @UseReturn {SecBuf} - the shared point, in secure memory,
+/// WeakKey if `public` is a low order point
+pub fn scalarmult(secret: &mut SecBuf, public: &mut SecBuf) -> Result<SecBuf, SodiumError> {
+    check_init();
+    if secret.len() != SCALARBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "secret must be a Buffer of length: {}, got {}.",
+            SCALARBYTES,
+            secret.len()
+        )));
+    }
+    if public.len() != BYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "public must be a Buffer of length: {}, got {}.",
+            BYTES,
+            public.len()
+        )));
+    }
+    let mut shared = SecBuf::try_with_secure(BYTES)?;
+    let res = {
+        let mut shared = shared.write_lock();
+        let secret = secret.read_lock();
+        let public = public.read_lock();
+        unsafe {
+            rust_sodium_sys::crypto_scalarmult(
+                raw_ptr_char!(shared),
+                raw_ptr_char_immut!(secret),
+                raw_ptr_char_immut!(public),
+            )
+        }
+    };
+    // libsodium refuses low order points, which would give an all zero secret
+    if res != 0 || shared.is_zero() {
+        return Err(SodiumError::WeakKey);
+    }
+    Ok(shared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kx;
+
+    #[test]
+    fn it_should_agree_on_a_shared_point() {
+        let mut a_pk = SecBuf::with_insecure(BYTES);
+        let mut a = SecBuf::with_secure(SCALARBYTES);
+        kx::keypair(&mut a_pk, &mut a).unwrap();
+        let mut b_pk = SecBuf::with_insecure(BYTES);
+        let mut b = SecBuf::with_secure(SCALARBYTES);
+        kx::keypair(&mut b_pk, &mut b).unwrap();
+
+        let mut ab = scalarmult(&mut a, &mut b_pk).unwrap();
+        let mut ba = scalarmult(&mut b, &mut a_pk).unwrap();
+        assert!(ab.is_secure());
+        assert!(!ab.is_zero());
+        assert_eq!(
+            ab.read_lock().debug_unredacted(),
+            ba.read_lock().debug_unredacted()
+        );
+    }
+
+    #[test]
+    fn it_should_reject_weak_and_bad_scalarmult_inputs() {
+        let mut secret = SecBuf::with_secure(SCALARBYTES);
+        crate::random::random_secbuf(&mut secret);
+        let mut zero = SecBuf::with_insecure(BYTES);
+        zero.fill(0);
+        match scalarmult(&mut secret, &mut zero) {
+            Err(SodiumError::WeakKey) => (),
+            _ => panic!("expected WeakKey"),
+        }
+        match scalarmult(&mut secret, &mut SecBuf::with_insecure(16)) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+        match scalarmult(&mut SecBuf::with_secure(16), &mut zero) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+    }
+}
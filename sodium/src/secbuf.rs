@@ -1,7 +1,19 @@
 //! This module provides an abstraction for memory for use with libsodium
 
 use libc::c_void;
-use std::ops::{Deref, DerefMut};
+use std::{
+    alloc::{self, Layout},
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    panic,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, Once, TryLockError,
+    },
+    thread::{self, ThreadId},
+};
 
 use super::check_init;
 use crate::error::SodiumError;
@@ -15,11 +27,28 @@ pub trait Bufferable: Send {
     where
         Self: Sized + Send;
     fn len(&self) -> usize;
-    fn readable(&mut self);
-    fn writable(&mut self);
-    fn noaccess(&mut self);
+    /// change the memory protection of the backing,
+    /// an Err means the protection is unchanged
+    fn readable(&mut self) -> Result<(), SodiumError>;
+    fn writable(&mut self) -> Result<(), SodiumError>;
+    fn noaccess(&mut self) -> Result<(), SodiumError>;
     fn ref_(&self) -> &[u8];
     fn ref_mut(&mut self) -> &mut [u8];
+    /// true if this is backed by secure memory
+    fn is_secure(&self) -> bool {
+        false
+    }
+    /// Some if this backing may be compared / ordered without a lock
+    fn as_ord_safe(&self) -> Option<&OrdSafe> {
+        None
+    }
+}
+
+/// marker for backing stores whose contents are not secret and are always readable,
+/// so a SecBuf over them can become an `OrdSecBuf`, compared / ordered through `&self`
+/// only RustBuf implements this
+pub trait OrdSafe {
+    fn bytes(&self) -> &[u8];
 }
 
 /// this is an insecure (raw memory) buffer for use with things like public keys
@@ -43,11 +72,17 @@ impl Bufferable for RustBuf {
         self.b.len()
     }
 
-    fn readable(&mut self) {}
+    fn readable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
+    }
 
-    fn writable(&mut self) {}
+    fn writable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
+    }
 
-    fn noaccess(&mut self) {}
+    fn noaccess(&mut self) -> Result<(), SodiumError> {
+        Ok(())
+    }
 
     fn ref_(&self) -> &[u8] {
         &self.b
@@ -56,348 +91,3649 @@ impl Bufferable for RustBuf {
     fn ref_mut(&mut self) -> &mut [u8] {
         &mut self.b
     }
+
+    fn as_ord_safe(&self) -> Option<&OrdSafe> {
+        Some(self)
+    }
 }
 
-/// this is a secure buffer for use with things like private keys
-struct SodiumBuf {
-    z: *mut c_void,
+impl RustBuf {
+    /// the rust allocator aborts rather than failing, so this is always Ok
+    fn try_new(s: usize) -> Result<Box<Bufferable>, SodiumError> {
+        Ok(RustBuf::new(s))
+    }
+}
+
+impl OrdSafe for RustBuf {
+    fn bytes(&self) -> &[u8] {
+        &self.b
+    }
+}
+
+/// an insecure buffer whose start is aligned for SIMD loads (AES-NI, AVX2)
+struct AlignedBuf {
+    z: *mut u8,
     s: usize,
+    /// the layout `z` was allocated with, never zero sized
+    layout: Layout,
 }
 
-unsafe impl Send for SodiumBuf {}
+unsafe impl Send for AlignedBuf {}
 
-impl Bufferable for SodiumBuf {
-    /// warning: funky sizes may result in mis-alignment
-    fn new(s: usize) -> Box<Bufferable> {
-        if s % 8 != 0 {
-            panic!("bad buffer size: {}, disallowing this for safety", s);
+impl AlignedBuf {
+    /// returns InvalidAlignment unless `align` is a power of two
+    fn try_new(s: usize, align: usize) -> Result<Box<Bufferable>, SodiumError> {
+        if !align.is_power_of_two() {
+            return Err(SodiumError::InvalidAlignment(align));
         }
-        let z = unsafe {
-            check_init();
-            let z = rust_sodium_sys::sodium_malloc(s);
-            if z.is_null() {
-                panic!("cannot allocate");
-            }
-            rust_sodium_sys::sodium_mprotect_noaccess(z);
-            z
-        };
-        Box::new(SodiumBuf { z, s })
+        // allocating zero bytes is undefined, so always allocate at least one
+        let layout = Layout::from_size_align(std::cmp::max(s, 1), align)
+            .map_err(|_| SodiumError::OutOfBounds(format!("bad aligned size: {}", s)))?;
+        let z = unsafe { alloc::alloc_zeroed(layout) };
+        if z.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Ok(Box::new(AlignedBuf { z, s, layout }))
+    }
+}
+
+impl Bufferable for AlignedBuf {
+    /// aligned to 8 bytes, see `SecBuf::with_insecure_aligned` for other alignments
+    fn new(s: usize) -> Box<Bufferable> {
+        AlignedBuf::try_new(s, 8).unwrap_or_else(|e| panic!("{}", e))
     }
 
     fn from_string(s: String) -> Box<Bufferable> {
-        let b = s.into_bytes().into_boxed_slice();
-        Box::new(RustBuf { b })
+        let bytes = s.into_bytes();
+        let mut out = AlignedBuf::new(bytes.len());
+        out.ref_mut().copy_from_slice(&bytes);
+        out
     }
 
     fn len(&self) -> usize {
         self.s
     }
 
-    fn readable(&mut self) {
-        unsafe {
-            rust_sodium_sys::sodium_mprotect_readonly(self.z);
-        }
+    fn readable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
     }
 
-    fn writable(&mut self) {
-        unsafe {
-            rust_sodium_sys::sodium_mprotect_readwrite(self.z);
-        }
+    fn writable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
     }
 
-    fn noaccess(&mut self) {
-        unsafe {
-            rust_sodium_sys::sodium_mprotect_noaccess(self.z);
-        }
+    fn noaccess(&mut self) -> Result<(), SodiumError> {
+        Ok(())
     }
 
     fn ref_(&self) -> &[u8] {
-        unsafe { std::slice::from_raw_parts(self.z as *const u8, self.s) }
+        unsafe { std::slice::from_raw_parts(self.z, self.s) }
     }
 
     fn ref_mut(&mut self) -> &mut [u8] {
-        unsafe { std::slice::from_raw_parts_mut(self.z as *mut u8, self.s) }
+        unsafe { std::slice::from_raw_parts_mut(self.z, self.s) }
     }
 }
 
-impl Drop for SodiumBuf {
+impl Drop for AlignedBuf {
     fn drop(&mut self) {
         unsafe {
-            rust_sodium_sys::sodium_free(self.z);
+            alloc::dealloc(self.z, self.layout);
         }
     }
 }
 
-/// Represents the memory protection state of a SecBuf
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ProtectState {
-    NoAccess,
-    ReadOnly,
-    ReadWrite,
-}
+/// bytes of canary on each side of a CanaryBuf
+#[cfg(debug_assertions)]
+const CANARY_LEN: usize = 8;
 
-/// A SecBuf is a memory buffer for use with libsodium functions.
-/// It can be backed by insecure (raw) memory for things like public keys,
-/// or secure (mlocked / mprotected) memory for things like private keys.
-pub struct SecBuf {
-    b: Box<Bufferable>,
-    p: ProtectState,
+/// a debug aid: an insecure buffer with a random canary before and after the
+/// user region, checked on every `noaccess()` and on drop to catch stray writes
+#[cfg(debug_assertions)]
+struct CanaryBuf {
+    b: Box<[u8]>,
+    canary: [u8; CANARY_LEN],
 }
 
-impl std::fmt::Debug for SecBuf {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self.b.ref_())
+#[cfg(debug_assertions)]
+impl CanaryBuf {
+    /// panics with SodiumError::BufferOverflow if either canary was overwritten
+    fn check(&self) {
+        let end = self.b.len() - CANARY_LEN;
+        let before = self.b[..CANARY_LEN] != self.canary;
+        let after = self.b[end..] != self.canary;
+        if before || after {
+            panic!("{}", SodiumError::BufferOverflow { before, after });
+        }
     }
 }
 
-impl SecBuf {
-    /// create a new SecBuf backed by insecure memory (for things like public keys)
-    pub fn with_insecure(s: usize) -> Self {
-        SecBuf {
-            b: RustBuf::new(s),
-            p: ProtectState::NoAccess,
+#[cfg(debug_assertions)]
+impl Bufferable for CanaryBuf {
+    fn new(s: usize) -> Box<Bufferable> {
+        check_init();
+        let mut canary = [0u8; CANARY_LEN];
+        unsafe {
+            rust_sodium_sys::randombytes_buf(raw_ptr_void!(canary), CANARY_LEN);
         }
+        let mut b = vec![0; s + 2 * CANARY_LEN].into_boxed_slice();
+        b[..CANARY_LEN].copy_from_slice(&canary);
+        b[s + CANARY_LEN..].copy_from_slice(&canary);
+        Box::new(CanaryBuf { b, canary })
     }
 
-    /// create a new SecBuf backed by secure memory (for things like private keys)
-    /// warning: funky sizes may result in mis-alignment
-    pub fn with_secure(s: usize) -> Self {
-        SecBuf {
-            b: SodiumBuf::new(s),
-            p: ProtectState::NoAccess,
-        }
+    fn from_string(s: String) -> Box<Bufferable> {
+        let bytes = s.into_bytes();
+        let mut out = CanaryBuf::new(bytes.len());
+        out.ref_mut().copy_from_slice(&bytes);
+        out
     }
 
-    pub fn with_insecure_from_string(s: String) -> Self {
-        SecBuf {
-            b: RustBuf::from_string(s),
-            p: ProtectState::NoAccess,
-        }
+    fn len(&self) -> usize {
+        self.b.len() - 2 * CANARY_LEN
     }
 
-    /// what is the current memory protection state of this SecBuf?
-    pub fn protect_state(&self) -> ProtectState {
-        self.p.clone()
+    fn readable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
     }
 
-    /// should be able to get size without messing with mem protection
-    pub fn len(&self) -> usize {
-        self.b.len()
+    fn writable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
     }
 
-    /// make this SecBuf readable
-    pub fn readable(&mut self) {
-        if self.p == ProtectState::NoAccess {
-            self.p = ProtectState::ReadOnly;
-            self.b.readable();
-        } else {
-            panic!(
-                "SecBuf trying to get Double Locked, Current state : {:?}",
-                self.protect_state()
-            );
-        }
+    fn noaccess(&mut self) -> Result<(), SodiumError> {
+        self.check();
+        Ok(())
     }
 
-    /// make this SecBuf writable
-    pub fn writable(&mut self) {
-        if self.p == ProtectState::NoAccess {
-            self.p = ProtectState::ReadWrite;
-            self.b.writable();
-        } else {
-            panic!(
-                "SecBuf trying to get Double Locked, Current state : {:?}",
-                self.protect_state()
-            );
+    fn ref_(&self) -> &[u8] {
+        &self.b[CANARY_LEN..self.b.len() - CANARY_LEN]
+    }
+
+    fn ref_mut(&mut self) -> &mut [u8] {
+        let end = self.b.len() - CANARY_LEN;
+        &mut self.b[CANARY_LEN..end]
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for CanaryBuf {
+    fn drop(&mut self) {
+        // a second panic while unwinding from the first would abort
+        if !std::thread::panicking() {
+            self.check();
         }
     }
+}
 
-    /// secure this SecBuf against reading or writing
-    pub fn noaccess(&mut self) {
-        self.p = ProtectState::NoAccess;
-        self.b.noaccess();
+/// the backing for a zero length secure SecBuf, so nothing is handed to sodium_malloc
+/// it holds nothing, so it counts as secure and the protection calls are no-ops
+struct EmptyBuf;
+
+impl Bufferable for EmptyBuf {
+    fn new(_s: usize) -> Box<Bufferable> {
+        Box::new(EmptyBuf)
     }
 
-    /// make this SecBuf readable, and return a locker object
-    /// that will secure this SecBuf automatically when it goes out of scope.
-    pub fn read_lock(&mut self) -> Locker {
-        Locker::new(self, false)
+    fn from_string(_s: String) -> Box<Bufferable> {
+        Box::new(EmptyBuf)
     }
 
-    /// make this SecBuf writable, and return a locker object
-    /// that will secure this SecBuf automatically when it goes out of scope.
-    pub fn write_lock(&mut self) -> Locker {
-        Locker::new(self, true)
+    fn len(&self) -> usize {
+        0
     }
 
-    /// helper for writing data to our internal buffer
-    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SodiumError> {
-        if offset + data.len() > self.len() {
-            return Err(SodiumError::new("bad write offset / length"));
-        }
+    fn readable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
+    }
 
-        unsafe {
-            let mut b = self.write_lock();
-            std::ptr::copy(data.as_ptr(), (**b).as_mut_ptr().add(offset), data.len());
-        }
+    fn writable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
+    }
 
+    fn noaccess(&mut self) -> Result<(), SodiumError> {
         Ok(())
     }
-}
 
-impl Deref for SecBuf {
-    type Target = [u8];
+    fn ref_(&self) -> &[u8] {
+        &[]
+    }
 
-    fn deref(&self) -> &[u8] {
-        if self.p == ProtectState::NoAccess {
-            panic!("SecBuf Deref, but state is NoAccess");
-        }
-        self.b.ref_()
+    fn ref_mut(&mut self) -> &mut [u8] {
+        &mut []
     }
-}
 
-impl DerefMut for SecBuf {
-    fn deref_mut(&mut self) -> &mut [u8] {
-        if self.p != ProtectState::ReadWrite {
-            panic!("SecBuf DerefMut, but state is not ReadWrite");
-        }
-        self.b.ref_mut()
+    fn is_secure(&self) -> bool {
+        true
     }
 }
 
-/// a helper object that will automatically secure a SecBuf when dropped
-pub struct Locker<'a>(&'a mut SecBuf);
+/// this is a secure buffer for use with things like private keys
+#[cfg(not(target_arch = "wasm32"))]
+struct SodiumBuf {
+    z: *mut c_void,
+    /// the allocated size, always a multiple of 8
+    s: usize,
+    /// the bytes in use, up to `s`
+    len: usize,
+    /// false if the buffer is left read / write, protected only by its guard pages
+    protect: bool,
+}
 
-impl<'a> Locker<'a> {
-    pub fn new(b: &'a mut SecBuf, writable: bool) -> Self {
-        if writable {
-            b.writable();
+#[cfg(not(target_arch = "wasm32"))]
+unsafe impl Send for SodiumBuf {}
+
+/// number of live SodiumBufs
+static LIVE_SECURE_BUFS: AtomicUsize = AtomicUsize::new(0);
+/// total bytes requested by live SodiumBufs
+static SECURE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// RLIMIT_MEMLOCK limits, `u64::max_value()` means unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemlockLimit {
+    pub soft: u64,
+    pub hard: u64,
+}
+
+/// a snapshot of secure memory usage, for diagnosing allocation failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecureMemoryStats {
+    /// secure buffers currently allocated
+    pub live_buffers: usize,
+    /// bytes requested by those buffers (libsodium adds guard pages on top)
+    pub allocated_bytes: usize,
+    /// the process mlock limits, None where they cannot be read
+    pub memlock_limit: Option<MemlockLimit>,
+}
+
+#[cfg(unix)]
+fn memlock_limit() -> Option<MemlockLimit> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlim) } != 0 {
+        return None;
+    }
+    let limit = |l: libc::rlim_t| {
+        if l == libc::RLIM_INFINITY {
+            u64::max_value()
         } else {
-            b.readable();
+            l as u64
         }
-        Locker(b)
-    }
+    };
+    Some(MemlockLimit {
+        soft: limit(rlim.rlim_cur),
+        hard: limit(rlim.rlim_max),
+    })
 }
 
-impl<'a> Drop for Locker<'a> {
-    fn drop(&mut self) {
-        self.0.noaccess();
-    }
+#[cfg(not(unix))]
+fn memlock_limit() -> Option<MemlockLimit> {
+    None
 }
 
-impl<'a> std::fmt::Debug for Locker<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self.b.ref_())
+/// report the live secure buffers, the bytes they hold,
+/// and on unix the RLIMIT_MEMLOCK soft / hard limits
+pub fn secure_memory_stats() -> SecureMemoryStats {
+    SecureMemoryStats {
+        live_buffers: LIVE_SECURE_BUFS.load(Ordering::SeqCst),
+        allocated_bytes: SECURE_BYTES.load(Ordering::SeqCst),
+        memlock_limit: memlock_limit(),
     }
 }
 
-impl<'a> Deref for Locker<'a> {
-    type Target = SecBuf;
-
-    fn deref(&self) -> &SecBuf {
-        self.0
+/// format a byte count as MiB, dropping a trailing ".0"
+fn mib(bytes: u64) -> String {
+    let s = format!("{:.1}", bytes as f64 / (1024.0 * 1024.0));
+    if s.ends_with(".0") {
+        s[..s.len() - 2].to_string()
+    } else {
+        s
     }
 }
 
-impl<'a> DerefMut for Locker<'a> {
-    fn deref_mut(&mut self) -> &mut SecBuf {
-        self.0
+impl fmt::Display for SecureMemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let used = mib(self.allocated_bytes as u64);
+        match self.memlock_limit {
+            Some(MemlockLimit { soft, .. }) if soft != u64::max_value() => {
+                write!(f, "{} MiB of {} MiB mlock limit in use", used, mib(soft))
+            }
+            Some(_) => write!(f, "{} MiB in use, mlock limit unlimited", used),
+            None => write!(f, "{} MiB in use, mlock limit unknown", used),
+        }?;
+        write!(f, " ({} buffers)", self.live_buffers)
     }
 }
 
 #[cfg(test)]
-mod tests {
-    use super::*;
+thread_local! {
+    /// test hook: when set, secure allocations on this thread fail
+    /// as if sodium_malloc had returned null
+    static FAIL_SECURE_ALLOC: std::cell::Cell<bool> = std::cell::Cell::new(false);
+}
 
-    #[test]
-    fn it_should_create_secbuf_from_string() {
-        let b = SecBuf::with_insecure_from_string("zooooo".to_string());
-        assert_eq!(ProtectState::NoAccess, b.protect_state());
+/// sodium_malloc, unless a test has asked secure allocations to fail
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn secure_malloc(s: usize) -> *mut c_void {
+    #[cfg(test)]
+    {
+        if FAIL_SECURE_ALLOC.with(|f| f.get()) {
+            return std::ptr::null_mut();
+        }
     }
+    rust_sodium_sys::sodium_malloc(s)
+}
 
-    #[test]
-    fn it_should_read_write_insecure() {
-        let mut b = SecBuf::with_insecure(16);
-        assert_eq!(ProtectState::NoAccess, b.protect_state());
-
-        {
-            let mut b = b.write_lock();
-            assert_eq!(ProtectState::ReadWrite, b.protect_state());
-            b[0] = 12;
-        }
+#[cfg(not(target_arch = "wasm32"))]
+impl SodiumBuf {
+    /// sizes that are not a multiple of 8 are refused, to avoid mis-alignment
+    fn try_new(s: usize) -> Result<Box<Bufferable>, SodiumError> {
+        SodiumBuf::try_new_with(s, true)
+    }
 
-        {
-            let b = b.read_lock();
-            assert_eq!(ProtectState::ReadOnly, b.protect_state());
-            assert_eq!(b[0], 12);
+    /// `try_new`, leaving the memory read / write between locks unless `protect`
+    fn try_new_with(s: usize, protect: bool) -> Result<Box<Bufferable>, SodiumError> {
+        if s % 8 != 0 {
+            return Err(SodiumError::UnsupportedKeyLength(s));
         }
+        Ok(Box::new(SodiumBuf::alloc(s, s, protect)?))
     }
 
-    #[test]
-    fn it_should_read_write_secure() {
-        let mut b = SecBuf::with_secure(16);
-        assert_eq!(ProtectState::NoAccess, b.protect_state());
+    /// `try_new` for any length, allocating `len` rounded up to a multiple of 8
+    /// so the buffer stays aligned. The spare bytes sit between the buffer and
+    /// its guard page, so an overflow into them is not caught
+    fn try_new_any(len: usize) -> Result<Box<Bufferable>, SodiumError> {
+        let s = len
+            .checked_add(7)
+            .ok_or(SodiumError::UnsupportedKeyLength(len))?
+            / 8
+            * 8;
+        Ok(Box::new(SodiumBuf::alloc(s, len, true)?))
+    }
 
-        {
-            let mut b = b.write_lock();
-            assert_eq!(ProtectState::ReadWrite, b.protect_state());
-            b[0] = 12;
-        }
+    /// allocate `s` bytes of secure memory, of which the first `len` are used
+    fn alloc(s: usize, len: usize, protect: bool) -> Result<SodiumBuf, SodiumError> {
+        let z = unsafe {
+            check_init();
+            let z = secure_malloc(s);
+            if z.is_null() {
+                return Err(SodiumError::AllocationFailed {
+                    requested: s,
+                    stats: secure_memory_stats(),
+                });
+            }
+            if protect {
+                rust_sodium_sys::sodium_mprotect_noaccess(z);
+            }
+            z
+        };
+        LIVE_SECURE_BUFS.fetch_add(1, Ordering::SeqCst);
+        SECURE_BYTES.fetch_add(s, Ordering::SeqCst);
+        Ok(SodiumBuf { z, s, len, protect })
+    }
+}
 
-        {
-            let b = b.read_lock();
-            assert_eq!(ProtectState::ReadOnly, b.protect_state());
-            assert_eq!(b[0], 12);
-        }
+/// a `sodium_mprotect_*` return code as a Result
+#[cfg(not(target_arch = "wasm32"))]
+fn mprotect_result(function: &'static str, code: i32) -> Result<(), SodiumError> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(SodiumError::Libsodium { function, code })
     }
+}
 
-    #[test]
-    #[should_panic]
-    fn it_should_disallow_bad_align() {
-        SecBuf::with_secure(1);
+#[cfg(not(target_arch = "wasm32"))]
+impl Bufferable for SodiumBuf {
+    /// warning: funky sizes may result in mis-alignment
+    fn new(s: usize) -> Box<Bufferable> {
+        SodiumBuf::try_new(s).unwrap_or_else(|e| panic!("{}", e))
     }
 
-    #[test]
-    fn it_should_debug() {
+    fn from_string(s: String) -> Box<Bufferable> {
+        let b = s.into_bytes().into_boxed_slice();
+        Box::new(RustBuf { b })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn readable(&mut self) -> Result<(), SodiumError> {
+        if !self.protect {
+            return Ok(());
+        }
+        let code = unsafe { rust_sodium_sys::sodium_mprotect_readonly(self.z) };
+        mprotect_result("sodium_mprotect_readonly", code)
+    }
+
+    fn writable(&mut self) -> Result<(), SodiumError> {
+        if !self.protect {
+            return Ok(());
+        }
+        let code = unsafe { rust_sodium_sys::sodium_mprotect_readwrite(self.z) };
+        mprotect_result("sodium_mprotect_readwrite", code)
+    }
+
+    fn noaccess(&mut self) -> Result<(), SodiumError> {
+        if !self.protect {
+            return Ok(());
+        }
+        let code = unsafe { rust_sodium_sys::sodium_mprotect_noaccess(self.z) };
+        mprotect_result("sodium_mprotect_noaccess", code)
+    }
+
+    fn ref_(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.z as *const u8, self.len) }
+    }
+
+    fn ref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.z as *mut u8, self.len) }
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for SodiumBuf {
+    fn drop(&mut self) {
+        unsafe {
+            rust_sodium_sys::sodium_free(self.z);
+        }
+        LIVE_SECURE_BUFS.fetch_sub(1, Ordering::SeqCst);
+        SECURE_BYTES.fetch_sub(self.s, Ordering::SeqCst);
+    }
+}
+
+/// a plain heap buffer that is mlocked (kept out of swap) for its lifetime
+/// lighter than SodiumBuf for medium sized secrets: no guard pages or mprotect,
+/// so readable / writable / noaccess are no-ops
+#[cfg(not(target_arch = "wasm32"))]
+struct MlockBuf {
+    b: Box<[u8]>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MlockBuf {
+    fn try_new(s: usize) -> Result<Box<Bufferable>, SodiumError> {
+        let mut b = vec![0; s].into_boxed_slice();
+        let code = unsafe {
+            check_init();
+            rust_sodium_sys::sodium_mlock(raw_ptr_void!(b), s)
+        };
+        if code != 0 {
+            return Err(SodiumError::Libsodium {
+                function: "sodium_mlock",
+                code,
+            });
+        }
+        Ok(Box::new(MlockBuf { b }))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Bufferable for MlockBuf {
+    fn new(s: usize) -> Box<Bufferable> {
+        MlockBuf::try_new(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn from_string(s: String) -> Box<Bufferable> {
+        let b = s.into_bytes().into_boxed_slice();
+        Box::new(RustBuf { b })
+    }
+
+    fn len(&self) -> usize {
+        self.b.len()
+    }
+
+    fn readable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
+    }
+
+    fn writable(&mut self) -> Result<(), SodiumError> {
+        Ok(())
+    }
+
+    fn noaccess(&mut self) -> Result<(), SodiumError> {
+        Ok(())
+    }
+
+    fn ref_(&self) -> &[u8] {
+        &self.b
+    }
+
+    fn ref_mut(&mut self) -> &mut [u8] {
+        &mut self.b
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for MlockBuf {
+    /// sodium_munlock also zeroes the memory
+    fn drop(&mut self) {
+        let s = self.b.len();
+        let b = &mut self.b;
+        unsafe {
+            rust_sodium_sys::sodium_munlock(raw_ptr_void!(b), s);
+        }
+    }
+}
+
+/// wasm32 has no sodium_malloc / mprotect, so this stands in for SodiumBuf there:
+/// the protect state is emulated in software (violations panic in debug builds)
+/// and the memory is cleared on drop
+#[cfg(target_arch = "wasm32")]
+struct WasmBuf {
+    b: Box<[u8]>,
+    p: ProtectState,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl WasmBuf {
+    /// zero the backing memory in a way the optimizer will not elide
+    fn wipe(&mut self) {
+        for byte in self.b.iter_mut() {
+            unsafe {
+                std::ptr::write_volatile(byte, 0);
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl WasmBuf {
+    /// keeps the same size restrictions as SodiumBuf
+    fn try_new(s: usize) -> Result<Box<Bufferable>, SodiumError> {
+        if s % 8 != 0 {
+            return Err(SodiumError::UnsupportedKeyLength(s));
+        }
+        WasmBuf::try_new_any(s)
+    }
+
+    /// wasm32 memory has no alignment concerns, so this is never refused
+    fn try_new_any(s: usize) -> Result<Box<Bufferable>, SodiumError> {
+        let b = vec![0; s].into_boxed_slice();
+        Ok(Box::new(WasmBuf {
+            b,
+            p: ProtectState::NoAccess,
+        }))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Bufferable for WasmBuf {
+    fn new(s: usize) -> Box<Bufferable> {
+        WasmBuf::try_new(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn from_string(s: String) -> Box<Bufferable> {
+        let b = s.into_bytes().into_boxed_slice();
+        Box::new(RustBuf { b })
+    }
+
+    fn len(&self) -> usize {
+        self.b.len()
+    }
+
+    fn readable(&mut self) -> Result<(), SodiumError> {
+        self.p = ProtectState::ReadOnly;
+        Ok(())
+    }
+
+    fn writable(&mut self) -> Result<(), SodiumError> {
+        self.p = ProtectState::ReadWrite;
+        Ok(())
+    }
+
+    fn noaccess(&mut self) -> Result<(), SodiumError> {
+        self.p = ProtectState::NoAccess;
+        Ok(())
+    }
+
+    fn ref_(&self) -> &[u8] {
+        debug_assert!(
+            self.p != ProtectState::NoAccess,
+            "WasmBuf read, but state is NoAccess"
+        );
+        &self.b
+    }
+
+    fn ref_mut(&mut self) -> &mut [u8] {
+        debug_assert!(
+            self.p == ProtectState::ReadWrite,
+            "WasmBuf write, but state is not ReadWrite"
+        );
+        &mut self.b
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for WasmBuf {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+/// an anonymous mapping, mlocked and excluded from core dumps, for secrets
+/// too large to sit comfortably in sodium_malloc (no guard pages, but the
+/// protect state is enforced with mprotect on the whole mapping)
+#[cfg(all(feature = "mmap", unix))]
+struct MmapBuf {
+    z: *mut c_void,
+    s: usize,
+    map_len: usize,
+}
+
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Send for MmapBuf {}
+
+#[cfg(all(feature = "mmap", unix))]
+impl MmapBuf {
+    fn try_new(s: usize) -> Result<Box<Bufferable>, SodiumError> {
+        check_init();
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        // mmap refuses zero lengths, so always map at least one page
+        let map_len = std::cmp::max(1, (s + page - 1) / page) * page;
+        let failed = || SodiumError::AllocationFailed {
+            requested: s,
+            stats: secure_memory_stats(),
+        };
+        unsafe {
+            let z = libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if z == libc::MAP_FAILED {
+                return Err(failed());
+            }
+            if libc::mlock(z, map_len) != 0 {
+                libc::munmap(z, map_len);
+                return Err(failed());
+            }
+            #[cfg(target_os = "linux")]
+            libc::madvise(z, map_len, libc::MADV_DONTDUMP);
+            libc::mprotect(z, map_len, libc::PROT_NONE);
+            Ok(Box::new(MmapBuf { z, s, map_len }))
+        }
+    }
+
+    /// mprotect the whole mapping
+    fn protect(&mut self, prot: libc::c_int) -> Result<(), SodiumError> {
+        if unsafe { libc::mprotect(self.z, self.map_len, prot) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Bufferable for MmapBuf {
+    fn new(s: usize) -> Box<Bufferable> {
+        MmapBuf::try_new(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn from_string(s: String) -> Box<Bufferable> {
+        let b = s.into_bytes().into_boxed_slice();
+        Box::new(RustBuf { b })
+    }
+
+    fn len(&self) -> usize {
+        self.s
+    }
+
+    fn readable(&mut self) -> Result<(), SodiumError> {
+        self.protect(libc::PROT_READ)
+    }
+
+    fn writable(&mut self) -> Result<(), SodiumError> {
+        self.protect(libc::PROT_READ | libc::PROT_WRITE)
+    }
+
+    fn noaccess(&mut self) -> Result<(), SodiumError> {
+        self.protect(libc::PROT_NONE)
+    }
+
+    fn ref_(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.z as *const u8, self.s) }
+    }
+
+    fn ref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.z as *mut u8, self.s) }
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Drop for MmapBuf {
+    fn drop(&mut self) {
+        unsafe {
+            libc::mprotect(self.z, self.map_len, libc::PROT_READ | libc::PROT_WRITE);
+            rust_sodium_sys::sodium_memzero(self.z, self.map_len);
+            libc::munlock(self.z, self.map_len);
+            libc::munmap(self.z, self.map_len);
+        }
+    }
+}
+
+/// the Bufferable backing `SecBuf::with_secure` on this target
+#[cfg(not(target_arch = "wasm32"))]
+type SecureBuf = SodiumBuf;
+#[cfg(target_arch = "wasm32")]
+type SecureBuf = WasmBuf;
+
+/// the Bufferable backing `SecBuf::with_mlocked` on this target
+/// (wasm32 has no swap to keep out of)
+#[cfg(not(target_arch = "wasm32"))]
+type MlockedBuf = MlockBuf;
+#[cfg(target_arch = "wasm32")]
+type MlockedBuf = RustBuf;
+
+/// the width of the length prefixes written by `SecBuf::concat_with_length_prefix`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixSize {
+    U8,
+    U16,
+    U32,
+}
+
+impl PrefixSize {
+    /// bytes taken by each prefix
+    pub fn width(self) -> usize {
+        match self {
+            PrefixSize::U8 => 1,
+            PrefixSize::U16 => 2,
+            PrefixSize::U32 => 4,
+        }
+    }
+
+    /// the longest field a prefix can describe
+    pub fn max_len(self) -> usize {
+        match self {
+            PrefixSize::U8 => u8::max_value() as usize,
+            PrefixSize::U16 => u16::max_value() as usize,
+            PrefixSize::U32 => u32::max_value() as usize,
+        }
+    }
+}
+
+/// options for `SecBuf::with_secure_opts`, for targets that cannot afford
+/// the full secure memory regime of `with_secure`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecureOpts {
+    /// mprotect the buffer noaccess / readonly outside of write locks,
+    /// when false only the guard pages protect it (ignored on wasm32)
+    pub protect: bool,
+    /// fall back to insecure memory if secure memory cannot be allocated
+    pub fallback_insecure: bool,
+}
+
+impl Default for SecureOpts {
+    /// the same regime as `with_secure`
+    fn default() -> Self {
+        SecureOpts {
+            protect: true,
+            fallback_insecure: false,
+        }
+    }
+}
+
+/// Represents the memory protection state of a SecBuf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtectState {
+    NoAccess,
+    ReadOnly,
+    ReadWrite,
+}
+
+/// A SecBuf is a memory buffer for use with libsodium functions.
+/// It can be backed by insecure (raw) memory for things like public keys,
+/// or secure (mlocked / mprotected) memory for things like private keys.
+pub struct SecBuf {
+    b: Box<Bufferable>,
+    p: ProtectState,
+}
+
+impl std::fmt::Debug for SecBuf {
+    /// secure SecBufs are always redacted, so a stray `{:?}` cannot log a private key,
+    /// insecure ones show their contents unless NoAccess (see `debug_unredacted`)
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_secure() {
+            write!(f, "SecBuf(secure, len={}, [REDACTED])", self.len())
+        } else {
+            write!(f, "{}", self.debug_unredacted())
+        }
+    }
+}
+
+impl SecBuf {
+    /// create a new SecBuf backed by insecure memory (for things like public keys)
+    pub fn with_insecure(s: usize) -> Self {
+        SecBuf::try_with_insecure(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// `with_insecure`, for symmetry with `try_with_secure`
+    pub fn try_with_insecure(s: usize) -> Result<Self, SodiumError> {
+        Ok(SecBuf {
+            b: RustBuf::try_new(s)?,
+            p: ProtectState::NoAccess,
+        })
+    }
+
+    /// create a new SecBuf backed by insecure memory starting on an `align` byte boundary,
+    /// for hardware accelerated primitives that need aligned input
+    /// panics unless `align` is a power of two, see `try_with_insecure_aligned`
+    pub fn with_insecure_aligned(s: usize, align: usize) -> Self {
+        SecBuf::try_with_insecure_aligned(s, align).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// `with_insecure_aligned`, returning InvalidAlignment unless `align` is a power of two
+    pub fn try_with_insecure_aligned(s: usize, align: usize) -> Result<Self, SodiumError> {
+        Ok(SecBuf {
+            b: AlignedBuf::try_new(s, align)?,
+            p: ProtectState::NoAccess,
+        })
+    }
+
+    /// create a new SecBuf backed by secure memory (for things like private keys)
+    /// warning: funky sizes may result in mis-alignment
+    /// panics if the memory cannot be allocated, see `try_with_secure`
+    pub fn with_secure(s: usize) -> Self {
+        SecBuf::try_with_secure(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// create a new SecBuf backed by insecure memory guarded by canaries,
+    /// a debug aid: writes just outside the buffer panic on the next `noaccess()`
+    #[cfg(debug_assertions)]
+    pub fn with_canary(s: usize) -> Self {
+        SecBuf {
+            b: CanaryBuf::new(s),
+            p: ProtectState::NoAccess,
+        }
+    }
+
+    /// create a new SecBuf backed by an mlocked anonymous mapping, for large secrets
+    /// panics if the memory cannot be mapped or locked, see `try_with_mmap`
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn with_mmap(s: usize) -> Self {
+        SecBuf::try_with_mmap(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// create a new SecBuf backed by an mlocked anonymous mapping,
+    /// returning AllocationFailed if it cannot be mapped or locked
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn try_with_mmap(s: usize) -> Result<Self, SodiumError> {
+        Ok(SecBuf {
+            b: MmapBuf::try_new(s)?,
+            p: ProtectState::NoAccess,
+        })
+    }
+
+    /// create a new SecBuf backed by mlocked memory,
+    /// kept out of swap but without guard pages or memory protection
+    pub fn with_mlocked(s: usize) -> Self {
+        SecBuf {
+            b: MlockedBuf::new(s),
+            p: ProtectState::NoAccess,
+        }
+    }
+
+    /// create a new SecBuf backed by secure memory,
+    /// returning an error rather than panicking for sizes secure memory cannot hold
+    pub fn try_with_secure(s: usize) -> Result<Self, SodiumError> {
+        let b = if s == 0 {
+            EmptyBuf::new(0)
+        } else {
+            SecureBuf::try_new(s)?
+        };
+        Ok(SecBuf {
+            b,
+            p: ProtectState::NoAccess,
+        })
+    }
+
+    /// create a new SecBuf backed by secure memory of any length
+    /// panics if the memory cannot be allocated, see `try_with_secure_any`
+    pub fn with_secure_any(s: usize) -> Self {
+        SecBuf::try_with_secure_any(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// `try_with_secure` for lengths that are not a multiple of 8, such as secrets
+    /// sized by their contents (decrypted plain text, decoded strings)
+    ///
+    /// the allocation is rounded up to keep it aligned, and an overflow into
+    /// the spare bytes is not caught by the guard page
+    pub fn try_with_secure_any(s: usize) -> Result<Self, SodiumError> {
+        let b = if s == 0 {
+            EmptyBuf::new(0)
+        } else {
+            SecureBuf::try_new_any(s)?
+        };
+        Ok(SecBuf {
+            b,
+            p: ProtectState::NoAccess,
+        })
+    }
+
+    /// create a new SecBuf backed by secure memory with the given `opts`
+    ///
+    /// @UseReturn {(SecBuf, Option<SodiumError>)} - the buffer, and InsecureFallback
+    /// if secure memory could not be allocated and `opts.fallback_insecure` put it
+    /// in insecure memory instead
+    pub fn with_secure_opts(
+        s: usize,
+        opts: SecureOpts,
+    ) -> Result<(SecBuf, Option<SodiumError>), SodiumError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let res = if s == 0 {
+            Ok(EmptyBuf::new(0))
+        } else {
+            SodiumBuf::try_new_with(s, opts.protect)
+        };
+        #[cfg(target_arch = "wasm32")]
+        let res = if s == 0 {
+            Ok(EmptyBuf::new(0))
+        } else {
+            SecureBuf::try_new(s)
+        };
+        match res {
+            Ok(b) => Ok((
+                SecBuf {
+                    b,
+                    p: ProtectState::NoAccess,
+                },
+                None,
+            )),
+            // only a failed allocation falls back, bad sizes are still refused
+            Err(e) => match e {
+                SodiumError::AllocationFailed { .. } if opts.fallback_insecure => Ok((
+                    SecBuf::try_with_insecure(s)?,
+                    Some(SodiumError::InsecureFallback(Box::new(e))),
+                )),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// create a new SecBuf backed by mlocked memory,
+    /// returning an error rather than panicking if the memory cannot be locked
+    pub fn try_with_mlocked(s: usize) -> Result<Self, SodiumError> {
+        Ok(SecBuf {
+            b: MlockedBuf::try_new(s)?,
+            p: ProtectState::NoAccess,
+        })
+    }
+
+    pub fn with_insecure_from_string(s: String) -> Self {
+        SecBuf {
+            b: RustBuf::from_string(s),
+            p: ProtectState::NoAccess,
+        }
+    }
+
+    /// what is the current memory protection state of this SecBuf?
+    pub fn protect_state(&self) -> ProtectState {
+        self.p.clone()
+    }
+
+    /// should be able to get size without messing with mem protection
+    pub fn len(&self) -> usize {
+        self.b.len()
+    }
+
+    /// true if this SecBuf holds no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// is this SecBuf backed by secure memory
+    pub fn is_secure(&self) -> bool {
+        self.b.is_secure()
+    }
+
+    /// the contents, even for secure SecBufs, for tests comparing buffers,
+    /// never touches the backing memory while the SecBuf is NoAccess
+    pub fn debug_unredacted(&self) -> String {
+        if self.p == ProtectState::NoAccess {
+            format!("SecBuf({:?}, len={})", self.p, self.len())
+        } else {
+            format!("{:?}", self.b.ref_())
+        }
+    }
+
+    /// make this SecBuf readable
+    pub fn readable(&mut self) {
+        self.try_readable().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// make this SecBuf readable, LockState if it is already locked
+    ///
+    /// the protect state only moves on once the backing has been mprotected,
+    /// so a failed call leaves it NoAccess
+    pub fn try_readable(&mut self) -> Result<(), SodiumError> {
+        if self.p != ProtectState::NoAccess {
+            return Err(SodiumError::LockState(self.protect_state()));
+        }
+        self.b.readable()?;
+        self.p = ProtectState::ReadOnly;
+        Ok(())
+    }
+
+    /// make this SecBuf writable
+    pub fn writable(&mut self) {
+        self.try_writable().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// make this SecBuf writable, LockState if it is already locked
+    ///
+    /// as with `try_readable`, a failed call leaves it NoAccess
+    pub fn try_writable(&mut self) -> Result<(), SodiumError> {
+        if self.p != ProtectState::NoAccess {
+            return Err(SodiumError::LockState(self.protect_state()));
+        }
+        self.b.writable()?;
+        self.p = ProtectState::ReadWrite;
+        Ok(())
+    }
+
+    /// secure this SecBuf against reading or writing
+    pub fn noaccess(&mut self) {
+        self.try_noaccess().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// secure this SecBuf against reading or writing
+    ///
+    /// if the backing cannot be mprotected the protect state is left as it was,
+    /// as the memory is still accessible
+    pub fn try_noaccess(&mut self) -> Result<(), SodiumError> {
+        self.b.noaccess()?;
+        self.p = ProtectState::NoAccess;
+        Ok(())
+    }
+
+    /// make this SecBuf readable, and return a locker object
+    /// that will secure this SecBuf automatically when it goes out of scope.
+    pub fn read_lock(&mut self) -> Locker {
+        Locker::new(self, false)
+    }
+
+    /// make this SecBuf writable, and return a locker object
+    /// that will secure this SecBuf automatically when it goes out of scope.
+    pub fn write_lock(&mut self) -> Locker {
+        Locker::new(self, true)
+    }
+
+    /// `read_lock`, returning an error rather than panicking if already locked
+    pub fn try_read_lock(&mut self) -> Result<Locker, SodiumError> {
+        Locker::try_new(self, false)
+    }
+
+    /// `write_lock`, returning an error rather than panicking if already locked
+    pub fn try_write_lock(&mut self) -> Result<Locker, SodiumError> {
+        Locker::try_new(self, true)
+    }
+
+    /// run `f` over a read lock of this SecBuf
+    /// the locker guard restores NoAccess on exit, even if `f` panics
+    pub fn with_read_lock<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let b = self.read_lock();
+        f(&b)
+    }
+
+    /// run `f` over a write lock of this SecBuf
+    /// the locker guard restores NoAccess on exit, even if `f` panics
+    pub fn with_write_lock<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut b = self.write_lock();
+        f(&mut b)
+    }
+
+    /// helper for writing data to our internal buffer
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SodiumError> {
+        if offset + data.len() > self.len() {
+            return Err(SodiumError::new("bad write offset / length"));
+        }
+
+        unsafe {
+            let mut b = self.write_lock();
+            std::ptr::copy(data.as_ptr(), (**b).as_mut_ptr().add(offset), data.len());
+        }
+
+        Ok(())
+    }
+
+    /// constant-time equality of two SecBufs of any backing, false if the lengths differ
+    pub fn ct_eq(&mut self, other: &mut SecBuf) -> bool {
+        self.len() == other.len() && self.equal_at_index(other, 0, self.len()).unwrap_or(false)
+    }
+
+    /// wrap this SecBuf for `==`, `sort` or use as a BTreeMap key
+    ///
+    /// insecure (OrdSafe) backing only; errors for secure SecBufs,
+    /// compare those with `ct_eq` instead
+    ///
+    /// @UseReturn {OrdSecBuf} - Generic for a secure SecBuf
+    pub fn into_ord(self) -> Result<OrdSecBuf, SodiumError> {
+        if self.b.as_ord_safe().is_none() {
+            return Err(SodiumError::new(
+                "only SecBufs backed by insecure memory can be ordered",
+            ));
+        }
+        Ok(OrdSecBuf(self))
+    }
+
+    /// constant-time comparison of `length` bytes starting at `offset` in both SecBufs
+    /// the range must fit within the shorter of the two buffers
+    pub fn equal_at_index(
+        &mut self,
+        other: &mut SecBuf,
+        offset: usize,
+        length: usize,
+    ) -> Result<bool, SodiumError> {
+        let end = offset.checked_add(length);
+        if end.is_none() || end.unwrap() > std::cmp::min(self.len(), other.len()) {
+            return Err(SodiumError::OutOfBounds(format!(
+                "bad compare offset / length: {} / {}",
+                offset, length
+            )));
+        }
+        check_init();
+        let a = self.read_lock();
+        let b = other.read_lock();
+        let res = unsafe {
+            rust_sodium_sys::sodium_memcmp(
+                a[offset..].as_ptr() as *const c_void,
+                b[offset..].as_ptr() as *const c_void,
+                length,
+            )
+        };
+        Ok(res == 0)
+    }
+
+    /// constant-time comparison of this SecBuf against a plain byte slice
+    /// returns false if the lengths differ, the prior protect state is kept
+    pub fn compare_slice(&mut self, other: &[u8]) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        check_init();
+        let res = self.peek(|a| unsafe {
+            rust_sodium_sys::sodium_memcmp(
+                a.as_ptr() as *const c_void,
+                other.as_ptr() as *const c_void,
+                other.len(),
+            )
+        });
+        res == 0
+    }
+
+    /// run `f` over the contents of this SecBuf
+    /// if the SecBuf is NoAccess, a read lock is held for the duration of `f`,
+    /// otherwise the current protect state is left untouched
+    pub(crate) fn peek<R, F: FnOnce(&[u8]) -> R>(&mut self, f: F) -> R {
+        if self.p == ProtectState::NoAccess {
+            let b = self.read_lock();
+            f(&b)
+        } else {
+            f(self.b.ref_())
+        }
+    }
+
+    /// increment this SecBuf as a little-endian integer in constant time,
+    /// wrapping to zero on overflow (for advancing nonces)
+    pub fn increment(&mut self) {
+        check_init();
+        let mut b = self.write_lock();
+        unsafe {
+            rust_sodium_sys::sodium_increment(raw_ptr_char!(b), b.len());
+        }
+    }
+
+    /// add `other` to this SecBuf, both as little-endian integers of the same length,
+    /// in constant time, wrapping on overflow
+    pub fn add(&mut self, other: &mut SecBuf) -> Result<(), SodiumError> {
+        if self.len() != other.len() {
+            return Err(SodiumError::LengthMismatch(format!(
+                "cannot add a Buffer of length {} to one of length {}",
+                other.len(),
+                self.len()
+            )));
+        }
+        check_init();
+        let mut a = self.write_lock();
+        let b = other.read_lock();
+        unsafe {
+            rust_sodium_sys::sodium_add(raw_ptr_char!(a), raw_ptr_char_immut!(b), a.len());
+        }
+        Ok(())
+    }
+
+    /// overwrite this SecBuf with the contents of `src` in place, without reallocating,
+    /// e.g. to rotate a key without leaving a replacement allocation around
+    pub fn copy_from(&mut self, src: &mut SecBuf) -> Result<(), SodiumError> {
+        if self.len() != src.len() {
+            return Err(SodiumError::LengthMismatch(format!(
+                "cannot copy a Buffer of length {} into one of length {}",
+                src.len(),
+                self.len()
+            )));
+        }
+        let mut dst = self.write_lock();
+        let src = src.read_lock();
+        dst.copy_from_slice(&src);
+        Ok(())
+    }
+
+    /// combine this SecBuf with `other` byte by byte into a new insecure SecBuf
+    fn bitwise(&mut self, other: &mut SecBuf, op: fn(u8, u8) -> u8) -> Result<SecBuf, SodiumError> {
+        if self.len() != other.len() {
+            return Err(SodiumError::LengthMismatch(format!(
+                "other Buffer must be {} bytes, got {}",
+                self.len(),
+                other.len()
+            )));
+        }
+        let mut out = SecBuf::try_with_insecure(self.len())?;
+        {
+            let (a, b) = read_lock_pair(self, other);
+            let mut o = out.write_lock();
+            for ((o, a), b) in o.iter_mut().zip(a.iter()).zip(b.iter()) {
+                *o = op(*a, *b);
+            }
+        }
+        Ok(out)
+    }
+
+    /// `self & other` into a new insecure SecBuf of the same length
+    pub fn bitwise_and(&mut self, other: &mut SecBuf) -> Result<SecBuf, SodiumError> {
+        self.bitwise(other, |a, b| a & b)
+    }
+
+    /// `self | other` into a new insecure SecBuf of the same length
+    pub fn bitwise_or(&mut self, other: &mut SecBuf) -> Result<SecBuf, SodiumError> {
+        self.bitwise(other, |a, b| a | b)
+    }
+
+    /// `self ^ other` into a new insecure SecBuf of the same length
+    pub fn bitwise_xor(&mut self, other: &mut SecBuf) -> Result<SecBuf, SodiumError> {
+        self.bitwise(other, |a, b| a ^ b)
+    }
+
+    /// `!self` into a new insecure SecBuf of the same length
+    pub fn bitwise_not(&mut self) -> Result<SecBuf, SodiumError> {
+        let mut out = SecBuf::try_with_insecure(self.len())?;
+        {
+            let a = self.read_lock();
+            let mut o = out.write_lock();
+            for (o, a) in o.iter_mut().zip(a.iter()) {
+                *o = !a;
+            }
+        }
+        Ok(out)
+    }
+
+    /// whether every byte of this SecBuf is zero, checked in constant time
+    pub fn is_zero(&mut self) -> bool {
+        check_init();
+        let b = self.read_lock();
+        unsafe { rust_sodium_sys::sodium_is_zero(raw_ptr_char_immut!(b), b.len()) == 1 }
+    }
+
+    /// one-time pad encrypt this SecBuf, returning `self` XOR `pad` in a new insecure SecBuf
+    /// the pad is zeroed afterwards so it cannot be used again,
+    /// an all-zero pad is rejected with PadUsed
+    ///
+    /// decrypt by applying a copy of the original pad to the ciphertext
+    pub fn apply_otp(&mut self, pad: &mut SecBuf) -> Result<SecBuf, SodiumError> {
+        if self.len() != pad.len() {
+            return Err(SodiumError::LengthMismatch(format!(
+                "one-time pad must be a Buffer of length: {}, got {}.",
+                self.len(),
+                pad.len()
+            )));
+        }
+        check_init();
+        let mut out = SecBuf::try_with_insecure(self.len())?;
+        {
+            let mut p = pad.write_lock();
+            let used = !p.is_empty()
+                && unsafe { rust_sodium_sys::sodium_is_zero(raw_ptr_char_immut!(p), p.len()) == 1 };
+            if used {
+                return Err(SodiumError::PadUsed);
+            }
+            let b = self.read_lock();
+            let mut o = out.write_lock();
+            for ((o, b), p) in o.iter_mut().zip(b.iter()).zip(p.iter()) {
+                *o = b ^ p;
+            }
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(p), p.len());
+            }
+        }
+        Ok(out)
+    }
+
+    /// XOR all of `parts` together into a new secure SecBuf,
+    /// recovering a secret split with `split_xor_shares`
+    ///
+    /// the parts are read locked one at a time, and may be of any length
+    pub fn multi_party_xor(parts: &mut [&mut SecBuf]) -> Result<SecBuf, SodiumError> {
+        let len = match parts.first() {
+            Some(p) => p.len(),
+            None => return Err(SodiumError::EmptyInput),
+        };
+        if let Some(p) = parts.iter().find(|p| p.len() != len) {
+            return Err(SodiumError::LengthMismatch(format!(
+                "all parts must be Buffers of length: {}, got {}.",
+                len,
+                p.len()
+            )));
+        }
+        let mut out = SecBuf::try_with_secure_any(len)?;
+        {
+            let mut o = out.write_lock();
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(o), o.len());
+            }
+            for part in parts.iter_mut() {
+                let p = part.read_lock();
+                for (o, p) in o.iter_mut().zip(p.iter()) {
+                    *o ^= p;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// split this SecBuf into `n` secure shares, all of which are needed
+    /// to recover it with `multi_party_xor`
+    ///
+    /// the first n - 1 shares are random, the last is their XOR with this SecBuf.
+    /// shares match the length of this SecBuf, whatever it is
+    pub fn split_xor_shares(&mut self, n: usize) -> Result<Vec<SecBuf>, SodiumError> {
+        if n == 0 {
+            return Err(SodiumError::EmptyInput);
+        }
+        let mut shares = Vec::with_capacity(n);
+        for _ in 1..n {
+            let mut share = SecBuf::try_with_secure_any(self.len())?;
+            crate::random::random_secbuf(&mut share);
+            shares.push(share);
+        }
+        let last = {
+            let mut parts: Vec<&mut SecBuf> = shares.iter_mut().collect();
+            parts.push(self);
+            SecBuf::multi_party_xor(&mut parts)?
+        };
+        shares.push(last);
+        Ok(shares)
+    }
+
+    /// concatenate `bufs`, each preceded by its length as a little endian
+    /// `prefix_size` integer, for length-prefixed (TLV style) message fields
+    ///
+    /// @UseReturn {SecBuf} - insecure, as it is meant for the wire,
+    /// OutOfBounds if a length does not fit the prefix
+    pub fn concat_with_length_prefix(
+        bufs: &mut [&mut SecBuf],
+        prefix_size: PrefixSize,
+    ) -> Result<SecBuf, SodiumError> {
+        let width = prefix_size.width();
+        let mut total = 0;
+        for b in bufs.iter() {
+            if b.len() > prefix_size.max_len() {
+                return Err(SodiumError::OutOfBounds(format!(
+                    "field of length {} does not fit a {} byte prefix",
+                    b.len(),
+                    width
+                )));
+            }
+            total += width + b.len();
+        }
+        let mut out = SecBuf::try_with_insecure(total)?;
+        {
+            let mut o = out.write_lock();
+            let mut at = 0;
+            for b in bufs.iter_mut() {
+                let len = (b.len() as u64).to_le_bytes();
+                o[at..at + width].copy_from_slice(&len[..width]);
+                at += width;
+                let b = b.read_lock();
+                o[at..at + b.len()].copy_from_slice(&b);
+                at += b.len();
+            }
+        }
+        Ok(out)
+    }
+
+    /// split a SecBuf written by `concat_with_length_prefix` back into its fields
+    ///
+    /// @UseReturn {Vec<SecBuf>} - the fields as insecure SecBufs,
+    /// OutOfBounds if a prefix or field runs past the end of `buf`
+    pub fn split_length_prefixed(
+        buf: &mut SecBuf,
+        prefix_size: PrefixSize,
+    ) -> Result<Vec<SecBuf>, SodiumError> {
+        let width = prefix_size.width();
+        let b = buf.read_lock();
+        let mut fields = Vec::new();
+        let mut at = 0;
+        while at < b.len() {
+            if b.len() - at < width {
+                return Err(SodiumError::OutOfBounds(format!(
+                    "truncated length prefix at offset {}",
+                    at
+                )));
+            }
+            let mut len = [0u8; 8];
+            len[..width].copy_from_slice(&b[at..at + width]);
+            let len = u64::from_le_bytes(len) as usize;
+            at += width;
+            if b.len() - at < len {
+                return Err(SodiumError::OutOfBounds(format!(
+                    "field of length {} at offset {} runs past the end, got {} bytes",
+                    len,
+                    at,
+                    b.len() - at
+                )));
+            }
+            let mut field = SecBuf::try_with_insecure(len)?;
+            field.write(0, &b[at..at + len])?;
+            fields.push(field);
+            at += len;
+        }
+        Ok(fields)
+    }
+
+    /// set every byte of this SecBuf to `value`
+    pub fn fill(&mut self, value: u8) {
+        let mut b = self.write_lock();
+        for x in b.iter_mut() {
+            *x = value;
+        }
+    }
+
+    /// replace every byte of this SecBuf with `f` of it, in place
+    /// the write lock is held while `f` runs, so it panics if this SecBuf is already locked
+    pub fn map_bytes<F: Fn(u8) -> u8>(&mut self, f: F) {
+        let mut b = self.write_lock();
+        for x in b.iter_mut() {
+            *x = f(*x);
+        }
+    }
+
+    /// `map_bytes`, with `f` also given the index of each byte
+    pub fn map_bytes_indexed<F: Fn(usize, u8) -> u8>(&mut self, f: F) {
+        let mut b = self.write_lock();
+        for (i, x) in b.iter_mut().enumerate() {
+            *x = f(i, *x);
+        }
+    }
+
+    /// rotate the bytes of this SecBuf left by `n` (modulo the length) in place
+    pub fn rotate_left(&mut self, n: usize) {
+        if self.len() == 0 {
+            return;
+        }
+        let n = n % self.len();
+        let mut b = self.write_lock();
+        (**b).rotate_left(n);
+    }
+
+    /// rotate the bytes of this SecBuf right by `n` (modulo the length) in place
+    pub fn rotate_right(&mut self, n: usize) {
+        if self.len() == 0 {
+            return;
+        }
+        let n = n % self.len();
+        let mut b = self.write_lock();
+        (**b).rotate_right(n);
+    }
+
+    /// iterate over `chunk_size` chunks of this SecBuf, each copied out into a Vec<u8>
+    /// under a read lock that is released before the chunk is yielded
+    /// the final chunk is shorter if the length is not a multiple of `chunk_size`
+    ///
+    /// panics if `chunk_size` is 0
+    pub fn chunk_iter(&mut self, chunk_size: usize) -> ChunkIter {
+        assert!(chunk_size != 0, "chunk size must be non-zero");
+        ChunkIter {
+            b: self,
+            chunk_size,
+            offset: 0,
+        }
+    }
+
+    /// iterate over copies of the bytes of this SecBuf
+    /// the iterator holds a read lock, which is released when it is dropped
+    pub fn byte_iter(&mut self) -> ByteIter {
+        ByteIter {
+            b: self.read_lock(),
+            offset: 0,
+        }
+    }
+
+    /// like `chunk_iter`, but each chunk is copied into a new SecBuf backed by secure memory
+    /// of its exact length (see `try_with_secure_any`), yielding Err if it cannot be allocated
+    ///
+    /// panics if `chunk_size` is 0
+    pub fn chunk_iter_secure(&mut self, chunk_size: usize) -> ChunkIterSecure {
+        ChunkIterSecure(self.chunk_iter(chunk_size))
+    }
+
+    /// clamp this 32 byte SecBuf as a curve25519 scalar:
+    /// clear the 3 low bits, clear the high bit and set the second-highest bit
+    pub fn clamp_curve25519(&mut self) -> Result<(), SodiumError> {
+        if self.len() != 32 {
+            return Err(SodiumError::SizeMismatch(format!(
+                "curve25519 scalar must be 32 bytes, got {}",
+                self.len()
+            )));
+        }
+        let mut b = self.write_lock();
+        b[0] &= 248;
+        b[31] &= 127;
+        b[31] |= 64;
+        Ok(())
+    }
+
+    /// copy this SecBuf into a new SecBuf backed by secure memory (left NoAccess)
+    /// if this SecBuf is backed by insecure memory, it is zeroed afterward,
+    /// but left allocated so it can be dropped as usual
+    pub fn try_upgrade_to_secure(&mut self) -> Result<SecBuf, SodiumError> {
+        let mut out = SecBuf::try_with_secure_any(self.len())?;
+        let zero_source = !self.is_secure();
+        {
+            let mut src = self.write_lock();
+            let mut dest = out.write_lock();
+            dest.copy_from_slice(&src);
+            if zero_source {
+                unsafe {
+                    rust_sodium_sys::sodium_memzero(raw_ptr_void!(src), src.len());
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// move this SecBuf into a new secure backing of `new_len` (left NoAccess)
+    /// the first min(old, new) bytes are copied, any new bytes are zero
+    /// and the old backing is zeroed before it is dropped
+    pub fn resize_secure(&mut self, new_len: usize) -> Result<(), SodiumError> {
+        let mut out = SecBuf::try_with_secure_any(new_len)?;
+        {
+            let mut src = self.write_lock();
+            let mut dest = out.write_lock();
+            let n = std::cmp::min(src.len(), new_len);
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(dest), dest.len());
+            }
+            dest[..n].copy_from_slice(&src[..n]);
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(src), src.len());
+            }
+        }
+        std::mem::swap(&mut self.b, &mut out.b);
+        Ok(())
+    }
+
+    /// deep copy this SecBuf into a new SecBuf backed by secure memory (left NoAccess)
+    /// each copy of key material is one more place it can leak from,
+    /// so prefer moving a SecBuf to cloning it
+    pub fn secure_clone(&mut self) -> Result<SecBuf, SodiumError> {
+        let mut out = SecBuf::try_with_secure_any(self.len())?;
+        {
+            let src = self.read_lock();
+            let mut dest = out.write_lock();
+            dest.copy_from_slice(&src);
+        }
+        Ok(out)
+    }
+
+    /// deep copy this SecBuf into a new SecBuf backed by insecure memory,
+    /// the same as `downgrade_to_insecure`
+    /// only for data that is not secret, see `secure_clone`
+    pub fn insecure_clone(&mut self) -> SecBuf {
+        self.downgrade_to_insecure()
+    }
+
+    /// copy this SecBuf into a new SecBuf backed by insecure memory
+    /// this SecBuf is left untouched
+    pub fn downgrade_to_insecure(&mut self) -> SecBuf {
+        let mut out = SecBuf::with_insecure(self.len());
+        {
+            let src = self.read_lock();
+            let mut dest = out.write_lock();
+            dest.copy_from_slice(&src);
+        }
+        out
+    }
+
+    /// guard this SecBuf so it is zeroed if this thread panics, see PanicScrubGuard
+    pub fn scrub_on_panic(self) -> PanicScrubGuard {
+        install_scrub_hook();
+        let id = NEXT_SCRUB_ID.fetch_add(1, Ordering::SeqCst);
+        // the SecBuf is boxed, so this pointer stays valid as the guard moves,
+        // and the hook finds whatever backing it holds at the time of the panic
+        let mut buf = Box::new(self);
+        let entry = ScrubEntry {
+            buf: &mut *buf as *mut SecBuf,
+            thread: thread::current().id(),
+        };
+        let mut registry = match SCRUB_REGISTRY.lock() {
+            Ok(registry) => registry,
+            Err(e) => e.into_inner(),
+        };
+        registry.insert(id, entry);
+        PanicScrubGuard {
+            buf: Some(buf),
+            id,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// zero this SecBuf and release its backing memory now, rather than on drop,
+    /// e.g. before a `fork` / `exec`. Secure memory is handed to `sodium_free` straight away
+    pub fn wipe(mut self) {
+        check_init();
+        // whatever the protect state, make the backing writable for the last time,
+        // if even that fails, releasing secure memory still zeroes it
+        if self.b.writable().is_ok() {
+            let b = self.b.ref_mut();
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(b), b.len());
+            }
+        }
+        drop(self);
+    }
+}
+
+/// a SecBuf backed by insecure (OrdSafe) memory, which implements the std comparison
+/// traits, for sorted sets of public keys / addresses. Made by `SecBuf::into_ord`
+///
+/// the locks RustBuf would take are no-ops, so nothing is needed through `&self`
+pub struct OrdSecBuf(SecBuf);
+
+impl OrdSecBuf {
+    /// the contents, which are always readable
+    pub fn bytes(&self) -> &[u8] {
+        match self.0.b.as_ord_safe() {
+            Some(b) => b.bytes(),
+            None => unreachable!("OrdSecBuf over a backing that is not OrdSafe"),
+        }
+    }
+
+    /// the wrapped SecBuf
+    pub fn into_inner(self) -> SecBuf {
+        self.0
+    }
+}
+
+impl Deref for OrdSecBuf {
+    type Target = SecBuf;
+
+    fn deref(&self) -> &SecBuf {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for OrdSecBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "OrdSecBuf({:?})", self.bytes())
+    }
+}
+
+/// `eq` is constant-time like `ct_eq`
+impl PartialEq for OrdSecBuf {
+    fn eq(&self, other: &OrdSecBuf) -> bool {
+        let (a, b) = (self.bytes(), other.bytes());
+        a.len() == b.len()
+            && unsafe {
+                rust_sodium_sys::sodium_memcmp(
+                    a.as_ptr() as *const c_void,
+                    b.as_ptr() as *const c_void,
+                    a.len(),
+                ) == 0
+            }
+    }
+}
+
+impl Eq for OrdSecBuf {}
+
+impl PartialOrd for OrdSecBuf {
+    fn partial_cmp(&self, other: &OrdSecBuf) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// lexicographic byte ordering, this is NOT constant-time,
+/// which is why it is only available for insecure memory
+impl Ord for OrdSecBuf {
+    fn cmp(&self, other: &OrdSecBuf) -> std::cmp::Ordering {
+        self.bytes().cmp(other.bytes())
+    }
+}
+
+impl Deref for SecBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.p == ProtectState::NoAccess {
+            panic!("SecBuf Deref, but state is NoAccess");
+        }
+        self.b.ref_()
+    }
+}
+
+impl DerefMut for SecBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        if self.p != ProtectState::ReadWrite {
+            panic!("SecBuf DerefMut, but state is not ReadWrite");
+        }
+        self.b.ref_mut()
+    }
+}
+
+/// iterator returned by `SecBuf::chunk_iter`
+pub struct ChunkIter<'a> {
+    b: &'a mut SecBuf,
+    chunk_size: usize,
+    offset: usize,
+}
+
+impl<'a> ChunkIter<'a> {
+    /// the byte range of the next chunk, if any
+    fn next_range(&mut self) -> Option<std::ops::Range<usize>> {
+        if self.offset >= self.b.len() {
+            return None;
+        }
+        let start = self.offset;
+        let end = std::cmp::min(start + self.chunk_size, self.b.len());
+        self.offset = end;
+        Some(start..end)
+    }
+}
+
+impl<'a> Iterator for ChunkIter<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let range = self.next_range()?;
+        let b = self.b.read_lock();
+        Some(b[range].to_vec())
+    }
+}
+
+/// iterator returned by `SecBuf::byte_iter`
+pub struct ByteIter<'a> {
+    b: Locker<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for ByteIter<'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.b.get(self.offset).cloned()?;
+        self.offset += 1;
+        Some(byte)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.b.len() - self.offset;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for ByteIter<'a> {}
+
+/// iterator returned by `SecBuf::chunk_iter_secure`
+pub struct ChunkIterSecure<'a>(ChunkIter<'a>);
+
+impl<'a> Iterator for ChunkIterSecure<'a> {
+    type Item = Result<SecBuf, SodiumError>;
+
+    fn next(&mut self) -> Option<Result<SecBuf, SodiumError>> {
+        let range = self.0.next_range()?;
+        let mut out = match SecBuf::try_with_secure_any(range.len()) {
+            Ok(out) => out,
+            Err(e) => return Some(Err(e)),
+        };
+        {
+            let src = self.0.b.read_lock();
+            let mut out = out.write_lock();
+            out.copy_from_slice(&src[range]);
+        }
+        Some(Ok(out))
+    }
+}
+
+/// a helper object that will automatically secure a SecBuf when dropped
+///
+/// unwinding guarantees: `Drop` runs while a panic unwinds through the locked scope,
+/// and it cannot itself panic, so the SecBuf is NoAccess again once the panic is caught.
+/// If the mprotect back to NoAccess fails, the error is ignored and the protect state
+/// stays ReadOnly / ReadWrite, matching the memory.
+/// Nothing runs if the process is built with `panic = "abort"` (the memory is gone anyway),
+/// if the Locker is leaked with `std::mem::forget`,
+/// or if a panic unwinds across an FFI boundary, which is undefined behaviour.
+pub struct Locker<'a>(&'a mut SecBuf);
+
+impl<'a> Locker<'a> {
+    pub fn new(b: &'a mut SecBuf, writable: bool) -> Self {
+        Locker::try_new(b, writable).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// `new`, LockState if the SecBuf is already locked,
+    /// or the backing's error if it could not be mprotected
+    pub fn try_new(b: &'a mut SecBuf, writable: bool) -> Result<Self, SodiumError> {
+        if writable {
+            b.try_writable()?;
+        } else {
+            b.try_readable()?;
+        }
+        Ok(Locker(b))
+    }
+}
+
+impl<'a> Drop for Locker<'a> {
+    /// when unwinding, a secure SecBuf is zeroed before it is locked again,
+    /// as whatever owns it may never get to drop it
+    fn drop(&mut self) {
+        if thread::panicking() && self.0.is_secure() && self.0.b.writable().is_ok() {
+            let b = self.0.b.ref_mut();
+            unsafe {
+                rust_sodium_sys::sodium_memzero(raw_ptr_void!(b), b.len());
+            }
+        }
+        let _ = self.0.try_noaccess();
+    }
+}
+
+impl<'a> std::fmt::Debug for Locker<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl<'a> Deref for Locker<'a> {
+    type Target = SecBuf;
+
+    fn deref(&self) -> &SecBuf {
+        self.0
+    }
+}
+
+impl<'a> DerefMut for Locker<'a> {
+    fn deref_mut(&mut self) -> &mut SecBuf {
+        self.0
+    }
+}
+
+/// a SecBuf guarded by a PanicScrubGuard, for the panic hook to scrub
+struct ScrubEntry {
+    buf: *mut SecBuf,
+    thread: ThreadId,
+}
+
+unsafe impl Send for ScrubEntry {}
+
+lazy_static! {
+    /// the live PanicScrubGuards, by id
+    static ref SCRUB_REGISTRY: Mutex<HashMap<usize, ScrubEntry>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_SCRUB_ID: AtomicUsize = AtomicUsize::new(0);
+static SCRUB_HOOK: Once = Once::new();
+
+/// install the scrubbing panic hook, chained in front of whatever hook was set before
+fn install_scrub_hook() {
+    SCRUB_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            scrub_current_thread();
+            previous(info);
+        }));
+    });
+}
+
+/// zero every guarded SecBuf created on the panicking thread
+fn scrub_current_thread() {
+    // give up rather than deadlock if the panic happened while the registry was locked
+    let registry = match SCRUB_REGISTRY.try_lock() {
+        Ok(registry) => registry,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
+    };
+    let current = thread::current().id();
+    for entry in registry.values().filter(|e| e.thread == current) {
+        let buf = unsafe { &mut *entry.buf };
+        if buf.b.writable().is_err() {
+            continue;
+        }
+        unsafe {
+            let b = buf.b.ref_mut();
+            rust_sodium_sys::sodium_memzero(raw_ptr_void!(b), b.len());
+        }
+        // put the memory back in the protect state the SecBuf records
+        let _ = match buf.p {
+            ProtectState::NoAccess => buf.b.noaccess(),
+            ProtectState::ReadOnly => buf.b.readable(),
+            ProtectState::ReadWrite => Ok(()),
+        };
+    }
+}
+
+/// A SecBuf that is zeroed by a panic hook if its thread panics,
+/// before unwinding can leave it readable. Derefs to the SecBuf, which may be
+/// replaced or resized through the guard: the hook scrubs whatever it holds at the
+/// time of the panic, then puts it back in its recorded protect state.
+///
+/// this is best-effort: the hook is process wide, so a later `std::panic::set_hook`
+/// replaces it, and it does not run when panics abort. The guard stays on the thread
+/// that created it (it is not Send), and only panics on that thread scrub it
+pub struct PanicScrubGuard {
+    buf: Option<Box<SecBuf>>,
+    id: usize,
+    _not_send: PhantomData<*mut u8>,
+}
+
+impl PanicScrubGuard {
+    /// stop guarding, and return the SecBuf
+    pub fn into_inner(mut self) -> SecBuf {
+        self.unregister();
+        *self
+            .buf
+            .take()
+            .expect("PanicScrubGuard always holds a SecBuf")
+    }
+
+    fn unregister(&mut self) {
+        let mut registry = match SCRUB_REGISTRY.lock() {
+            Ok(registry) => registry,
+            Err(e) => e.into_inner(),
+        };
+        registry.remove(&self.id);
+    }
+}
+
+impl Drop for PanicScrubGuard {
+    fn drop(&mut self) {
+        if self.buf.is_some() {
+            self.unregister();
+        }
+    }
+}
+
+impl Deref for PanicScrubGuard {
+    type Target = SecBuf;
+
+    fn deref(&self) -> &SecBuf {
+        self.buf
+            .as_ref()
+            .expect("PanicScrubGuard always holds a SecBuf")
+    }
+}
+
+impl DerefMut for PanicScrubGuard {
+    fn deref_mut(&mut self) -> &mut SecBuf {
+        self.buf
+            .as_mut()
+            .expect("PanicScrubGuard always holds a SecBuf")
+    }
+}
+
+/// read lock two SecBufs at once, e.g. to compare them
+///
+/// each SecBuf is NoAccess again when its Locker is dropped. Dropping the pair as a
+/// tuple releases `a` before `b`; once destructured with `let (a, b) = ...` the
+/// locals drop in reverse declaration order, so `b` is released before `a`
+pub fn read_lock_pair<'a>(a: &'a mut SecBuf, b: &'a mut SecBuf) -> (Locker<'a>, Locker<'a>) {
+    (a.read_lock(), b.read_lock())
+}
+
+/// A group of SecBufs managed together, e.g. an agent's keys and seeds
+#[derive(Default)]
+pub struct SecBufSet(Vec<SecBuf>);
+
+impl SecBufSet {
+    pub fn new(bufs: Vec<SecBuf>) -> Self {
+        SecBufSet(bufs)
+    }
+
+    pub fn push(&mut self, b: SecBuf) {
+        self.0.push(b);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut SecBuf> {
+        self.0.get_mut(index)
+    }
+
+    /// set every SecBuf in the set to NoAccess
+    pub fn lock_all(&mut self) {
+        for b in self.0.iter_mut() {
+            b.noaccess();
+        }
+    }
+
+    /// read lock every SecBuf in the set, in order
+    /// each returns to NoAccess as its Locker is dropped
+    pub fn read_all(&mut self) -> Vec<Locker> {
+        self.0.iter_mut().map(|b| b.read_lock()).collect()
+    }
+
+    /// write lock every SecBuf in the set, in order
+    /// each returns to NoAccess as its Locker is dropped
+    pub fn write_all(&mut self) -> Vec<Locker> {
+        self.0.iter_mut().map(|b| b.write_lock()).collect()
+    }
+}
+
+impl From<Vec<SecBuf>> for SecBufSet {
+    fn from(bufs: Vec<SecBuf>) -> Self {
+        SecBufSet(bufs)
+    }
+}
+
+impl IntoIterator for SecBufSet {
+    type Item = SecBuf;
+    type IntoIter = std::vec::IntoIter<SecBuf>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut SecBufSet {
+    type Item = &'a mut SecBuf;
+    type IntoIter = std::slice::IterMut<'a, SecBuf>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter_mut()
+    }
+}
+
+impl<'a> IntoIterator for &'a SecBufSet {
+    type Item = &'a SecBuf;
+    type IntoIter = std::slice::Iter<'a, SecBuf>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// a Bufferable for tests that need to see how a backing was left when it was dropped
+#[cfg(test)]
+pub(crate) mod spy {
+    use super::*;
+
+    /// a backing that records whether it was all zero when dropped,
+    /// insecure unless `secure`
+    pub(crate) struct SpyBuf {
+        pub(crate) b: Box<[u8]>,
+        pub(crate) zero_on_drop: std::sync::Arc<std::sync::Mutex<Option<bool>>>,
+        pub(crate) secure: bool,
+    }
+
+    impl Bufferable for SpyBuf {
+        fn new(s: usize) -> Box<Bufferable> {
+            Box::new(SpyBuf {
+                b: vec![0; s].into_boxed_slice(),
+                zero_on_drop: Default::default(),
+                secure: false,
+            })
+        }
+
+        fn from_string(s: String) -> Box<Bufferable> {
+            Box::new(SpyBuf {
+                b: s.into_bytes().into_boxed_slice(),
+                zero_on_drop: Default::default(),
+                secure: false,
+            })
+        }
+
+        fn len(&self) -> usize {
+            self.b.len()
+        }
+
+        fn readable(&mut self) -> Result<(), SodiumError> {
+            Ok(())
+        }
+
+        fn writable(&mut self) -> Result<(), SodiumError> {
+            Ok(())
+        }
+
+        fn noaccess(&mut self) -> Result<(), SodiumError> {
+            Ok(())
+        }
+
+        fn ref_(&self) -> &[u8] {
+            &self.b
+        }
+
+        fn ref_mut(&mut self) -> &mut [u8] {
+            &mut self.b
+        }
+
+        fn is_secure(&self) -> bool {
+            self.secure
+        }
+    }
+
+    impl Drop for SpyBuf {
+        fn drop(&mut self) {
+            *self.zero_on_drop.lock().unwrap() = Some(self.b.iter().all(|b| *b == 0));
+        }
+    }
+
+    /// a SecBuf over a SpyBuf of 0xa5 bytes, and the SpyBuf's record of its drop
+    pub(crate) fn spy(secure: bool) -> (SecBuf, std::sync::Arc<std::sync::Mutex<Option<bool>>>) {
+        spy_with_len(secure, 32)
+    }
+
+    /// `spy` with `len` bytes
+    pub(crate) fn spy_with_len(
+        secure: bool,
+        len: usize,
+    ) -> (SecBuf, std::sync::Arc<std::sync::Mutex<Option<bool>>>) {
+        let zero_on_drop = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let b = SecBuf {
+            b: Box::new(SpyBuf {
+                b: vec![0xa5; len].into_boxed_slice(),
+                zero_on_drop: zero_on_drop.clone(),
+                secure,
+            }),
+            p: ProtectState::NoAccess,
+        };
+        (b, zero_on_drop)
+    }
+
+    /// a SpyBuf that panics when it is made readable, to panic a thread mid-lock
+    pub(crate) struct PanicOnReadBuf(SpyBuf);
+
+    impl Bufferable for PanicOnReadBuf {
+        fn new(s: usize) -> Box<Bufferable> {
+            Box::new(PanicOnReadBuf(SpyBuf {
+                b: vec![0; s].into_boxed_slice(),
+                zero_on_drop: Default::default(),
+                secure: false,
+            }))
+        }
+
+        fn from_string(s: String) -> Box<Bufferable> {
+            Box::new(PanicOnReadBuf(SpyBuf {
+                b: s.into_bytes().into_boxed_slice(),
+                zero_on_drop: Default::default(),
+                secure: false,
+            }))
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn readable(&mut self) -> Result<(), SodiumError> {
+            panic!("spy read panic");
+        }
+
+        fn writable(&mut self) -> Result<(), SodiumError> {
+            Ok(())
+        }
+
+        fn noaccess(&mut self) -> Result<(), SodiumError> {
+            Ok(())
+        }
+
+        fn ref_(&self) -> &[u8] {
+            self.0.ref_()
+        }
+
+        fn ref_mut(&mut self) -> &mut [u8] {
+            self.0.ref_mut()
+        }
+    }
+
+    /// a SecBuf over a PanicOnReadBuf of 0xa5 bytes, and its record of its drop
+    pub(crate) fn panic_on_read(
+        len: usize,
+    ) -> (SecBuf, std::sync::Arc<std::sync::Mutex<Option<bool>>>) {
+        let zero_on_drop = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let b = SecBuf {
+            b: Box::new(PanicOnReadBuf(SpyBuf {
+                b: vec![0xa5; len].into_boxed_slice(),
+                zero_on_drop: zero_on_drop.clone(),
+                secure: false,
+            })),
+            p: ProtectState::NoAccess,
+        };
+        (b, zero_on_drop)
+    }
+
+    /// a backing whose mprotect calls fail while `fail` is set
+    pub(crate) struct FailingBuf {
+        pub(crate) b: Box<[u8]>,
+        pub(crate) fail: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl FailingBuf {
+        fn protect(&self) -> Result<(), SodiumError> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(SodiumError::Libsodium {
+                    function: "mprotect",
+                    code: -1,
+                });
+            }
+            Ok(())
+        }
+    }
+
+    impl Bufferable for FailingBuf {
+        fn new(s: usize) -> Box<Bufferable> {
+            Box::new(FailingBuf {
+                b: vec![0; s].into_boxed_slice(),
+                fail: Default::default(),
+            })
+        }
+
+        fn from_string(s: String) -> Box<Bufferable> {
+            Box::new(FailingBuf {
+                b: s.into_bytes().into_boxed_slice(),
+                fail: Default::default(),
+            })
+        }
+
+        fn len(&self) -> usize {
+            self.b.len()
+        }
+
+        fn readable(&mut self) -> Result<(), SodiumError> {
+            self.protect()
+        }
+
+        fn writable(&mut self) -> Result<(), SodiumError> {
+            self.protect()
+        }
+
+        fn noaccess(&mut self) -> Result<(), SodiumError> {
+            self.protect()
+        }
+
+        fn ref_(&self) -> &[u8] {
+            &self.b
+        }
+
+        fn ref_mut(&mut self) -> &mut [u8] {
+            &mut self.b
+        }
+    }
+
+    /// a SecBuf over a FailingBuf, and the switch that makes its mprotect calls fail
+    pub(crate) fn failing() -> (SecBuf, std::sync::Arc<std::sync::atomic::AtomicBool>) {
+        let fail = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let b = SecBuf {
+            b: Box::new(FailingBuf {
+                b: vec![0; 8].into_boxed_slice(),
+                fail: fail.clone(),
+            }),
+            p: ProtectState::NoAccess,
+        };
+        (b, fail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        spy::{failing, spy, SpyBuf},
+        *,
+    };
+
+    #[test]
+    fn it_should_create_secbuf_from_string() {
+        let b = SecBuf::with_insecure_from_string("zooooo".to_string());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_read_write_insecure() {
+        let mut b = SecBuf::with_insecure(16);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        {
+            let mut b = b.write_lock();
+            assert_eq!(ProtectState::ReadWrite, b.protect_state());
+            b[0] = 12;
+        }
+
+        {
+            let b = b.read_lock();
+            assert_eq!(ProtectState::ReadOnly, b.protect_state());
+            assert_eq!(b[0], 12);
+        }
+    }
+
+    #[test]
+    fn it_should_read_write_secure() {
+        let mut b = SecBuf::with_secure(16);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        {
+            let mut b = b.write_lock();
+            assert_eq!(ProtectState::ReadWrite, b.protect_state());
+            b[0] = 12;
+        }
+
+        {
+            let b = b.read_lock();
+            assert_eq!(ProtectState::ReadOnly, b.protect_state());
+            assert_eq!(b[0], 12);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_should_disallow_bad_align() {
+        SecBuf::with_secure(1);
+    }
+
+    #[test]
+    fn it_should_debug() {
         let mut b = SecBuf::with_insecure(2);
         {
-            let mut b = b.write_lock();
-            b[0] = 42;
-            b[1] = 222;
+            let mut b = b.write_lock();
+            b[0] = 42;
+            b[1] = 222;
+        }
+        {
+            let b = b.read_lock();
+            assert_eq!("[42, 222]", b.debug_unredacted());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_should_panic_on_not_readable() {
+        let b = SecBuf::with_insecure(1);
+        assert_eq!(22, b[0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_should_panic_on_not_writeable() {
+        let mut b = SecBuf::with_insecure(1);
+        b[0] = 22;
+    }
+
+    #[test]
+    fn it_should_write() {
+        let mut b = SecBuf::with_insecure(4);
+        b.write(1, &[42, 42]).unwrap();
+        {
+            let b = b.read_lock();
+            assert_eq!("[0, 42, 42, 0]", b.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_compare_equal_at_index() {
+        let mut a = SecBuf::with_secure(64);
+        let mut b = SecBuf::with_secure(64);
+        a.write(0, &[3; 64]).unwrap();
+        b.write(0, &[3; 64]).unwrap();
+        assert!(a.equal_at_index(&mut b, 0, 32).unwrap());
+
+        b.write(63, &[4]).unwrap();
+        assert!(!a.equal_at_index(&mut b, 0, 64).unwrap());
+        assert!(a.equal_at_index(&mut b, 32, 31).unwrap());
+        assert_eq!(ProtectState::NoAccess, a.protect_state());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_fail_equal_at_index_out_of_bounds() {
+        let mut a = SecBuf::with_insecure(16);
+        let mut b = SecBuf::with_insecure(8);
+        match a.equal_at_index(&mut b, 4, 8) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+    }
+
+    #[test]
+    fn it_should_debug_noaccess_without_deref() {
+        let b = SecBuf::with_insecure(8);
+        assert_eq!("SecBuf(NoAccess, len=8)", format!("{:?}", b));
+        let b = SecBuf::with_secure(8);
+        assert_eq!("SecBuf(NoAccess, len=8)", b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_redact_secure_debug() {
+        let mut b = SecBuf::with_secure(4);
+        b.write(0, &[1, 2, 3, 4]).unwrap();
+        assert_eq!("SecBuf(secure, len=4, [REDACTED])", format!("{:?}", b));
+        let b = b.read_lock();
+        assert_eq!("SecBuf(secure, len=4, [REDACTED])", format!("{:?}", b));
+        assert_eq!("SecBuf(secure, len=4, [REDACTED])", format!("{:?}", *b));
+        assert_eq!("[1, 2, 3, 4]", b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_not_redact_insecure_debug() {
+        let mut b = SecBuf::with_insecure(4);
+        b.write(0, &[1, 2, 3, 4]).unwrap();
+        let b = b.read_lock();
+        assert_eq!("[1, 2, 3, 4]", format!("{:?}", b));
+        assert_eq!("[1, 2, 3, 4]", format!("{:?}", *b));
+        assert_eq!(b.debug_unredacted(), format!("{:?}", *b));
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_should_fail_write_on_bad_offset() {
+        let mut b = SecBuf::with_insecure(4);
+        b.write(3, &[42, 42]).unwrap();
+    }
+
+    #[test]
+    fn it_should_rotate_and_restore() {
+        let mut b = SecBuf::with_insecure(4);
+        b.write(0, &[1, 2, 3, 4]).unwrap();
+        b.rotate_left(3);
+        b.rotate_right(3);
+        let b = b.read_lock();
+        assert_eq!("[1, 2, 3, 4]", b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_rotate_wrapping_at_boundary() {
+        let mut b = SecBuf::with_insecure(4);
+        b.write(0, &[1, 2, 3, 4]).unwrap();
+        b.rotate_left(5);
+        {
+            let b = b.read_lock();
+            assert_eq!("[2, 3, 4, 1]", b.debug_unredacted());
+        }
+        b.rotate_right(6);
+        let b = b.read_lock();
+        assert_eq!("[3, 4, 1, 2]", b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_rotate_secure_leaving_noaccess() {
+        let mut b = SecBuf::with_secure(8);
+        b.write(0, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        b.rotate_left(2);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        let b = b.read_lock();
+        assert_eq!("[3, 4, 5, 6, 7, 8, 1, 2]", b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_iterate_chunks() {
+        let mut b = SecBuf::with_insecure(10);
+        b.write(0, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+        let chunks: Vec<Vec<u8>> = b.chunk_iter(4).collect();
+        assert_eq!(vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]], chunks);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_iterate_secure_chunks() {
+        let mut b = SecBuf::with_secure(32);
+        b.write(0, &[1; 16]).unwrap();
+        b.write(16, &[2; 16]).unwrap();
+        let mut chunks: Vec<SecBuf> = b.chunk_iter_secure(16).collect::<Result<_, _>>().unwrap();
+        assert_eq!(2, chunks.len());
+        for (i, chunk) in chunks.iter_mut().enumerate() {
+            assert_eq!(ProtectState::NoAccess, chunk.protect_state());
+            let chunk = chunk.read_lock();
+            assert_eq!(
+                format!("{:?}", vec![i as u8 + 1; 16]),
+                chunk.debug_unredacted()
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_iterate_a_trailing_partial_secure_chunk() {
+        let mut b = SecBuf::with_secure_any(20);
+        let bytes: Vec<u8> = (0..20).collect();
+        b.write(0, &bytes).unwrap();
+        let mut chunks: Vec<SecBuf> = b.chunk_iter_secure(8).collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            vec![8, 8, 4],
+            chunks.iter().map(|c| c.len()).collect::<Vec<_>>()
+        );
+        let mut last = chunks.pop().unwrap();
+        assert!(last.is_secure());
+        assert_eq!("[16, 17, 18, 19]", last.read_lock().debug_unredacted());
+    }
+
+    #[test]
+    #[should_panic(expected = "chunk size must be non-zero")]
+    fn it_should_panic_on_a_zero_chunk_size() {
+        let _ = SecBuf::with_insecure(8).chunk_iter(0);
+    }
+
+    #[test]
+    fn it_should_compare_slice() {
+        let mut b = SecBuf::with_secure(8);
+        b.write(0, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+        assert!(b.compare_slice(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert!(!b.compare_slice(&[1, 2, 3, 4, 5, 6, 7, 9]));
+        assert!(!b.compare_slice(&[1, 2, 3, 4, 5, 6, 7]));
+        assert!(!b.compare_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9]));
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        let mut b = b.read_lock();
+        assert!(b.compare_slice(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(ProtectState::ReadOnly, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_clamp_curve25519() {
+        let mut b = SecBuf::with_secure(32);
+        b.write(0, &[0xff; 32]).unwrap();
+        b.clamp_curve25519().unwrap();
+        let b = b.read_lock();
+        assert_eq!(0xf8, b[0]);
+        assert_eq!(0x7f, b[31]);
+        assert_eq!([0xff; 30][..], b[1..31]);
+
+        let mut c = SecBuf::with_insecure(32);
+        c.clamp_curve25519().unwrap();
+        let c = c.read_lock();
+        assert_eq!(0x40, c[31]);
+
+        let mut d = SecBuf::with_insecure(16);
+        assert!(d.clamp_curve25519().is_err());
+    }
+
+    #[test]
+    fn it_should_scope_closure_locks() {
+        let mut b = SecBuf::with_secure(8);
+        b.with_write_lock(|b| b.copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        let sum: u32 = b.with_read_lock(|b| b.iter().map(|x| *x as u32).sum());
+        assert_eq!(36, sum);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_restore_noaccess_when_closure_panics() {
+        let mut b = SecBuf::with_secure(8);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            b.with_write_lock(|_| panic!("boom"));
+        }));
+        assert!(res.is_err());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            b.with_read_lock(|_| panic!("boom"));
+        }));
+        assert!(res.is_err());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_upgrade_any_length_to_secure() {
+        let mut b = SecBuf::with_insecure(20);
+        b.fill(6);
+        let mut c = b.try_upgrade_to_secure().unwrap();
+        assert!(c.is_secure());
+        assert_eq!(20, c.len());
+        assert_eq!(
+            format!("{:?}", [6u8; 20].to_vec()),
+            c.read_lock().debug_unredacted()
+        );
+    }
+
+    #[test]
+    fn it_should_upgrade_to_secure() {
+        use crate::random::random_secbuf;
+        let mut b = SecBuf::with_insecure(32);
+        random_secbuf(&mut b);
+        let expected = b.read_lock().debug_unredacted();
+
+        let mut c = b.try_upgrade_to_secure().unwrap();
+        assert!(c.is_secure());
+        assert_eq!(ProtectState::NoAccess, c.protect_state());
+        assert_eq!(expected, c.read_lock().debug_unredacted());
+        assert_eq!(
+            format!("{:?}", [0u8; 32].to_vec()),
+            b.read_lock().debug_unredacted()
+        );
+
+        let mut d = c.downgrade_to_insecure();
+        assert!(!d.is_secure());
+        assert_eq!(expected, d.read_lock().debug_unredacted());
+        assert_eq!(expected, c.read_lock().debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_restore_noaccess_when_locked_scope_panics() {
+        for writable in [false, true].iter() {
+            let mut b = SecBuf::with_secure(8);
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let _locker = if *writable {
+                    b.write_lock()
+                } else {
+                    b.read_lock()
+                };
+                panic!("boom");
+            }));
+            assert!(res.is_err());
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+            // and it can be locked again
+            b.write(0, &[1; 8]).unwrap();
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+        }
+    }
+
+    #[test]
+    fn it_should_read_write_mlocked() {
+        let mut b = SecBuf::with_mlocked(100);
+        assert_eq!(100, b.len());
+        assert!(!b.is_secure());
+        b.write(0, &[3; 100]).unwrap();
+        {
+            let mut b = b.write_lock();
+            b[99] = 4;
+        }
+        let b = b.read_lock();
+        assert_eq!(3, b[0]);
+        assert_eq!(4, b[99]);
+    }
+
+    #[test]
+    fn it_should_sort_public_keys() {
+        let keys = [[3u8; 32], [1u8; 32], [2u8; 32], [1u8; 32]];
+        let mut v: Vec<OrdSecBuf> = keys
+            .iter()
+            .map(|k| {
+                let mut b = SecBuf::with_insecure(32);
+                b.write(0, k).unwrap();
+                b.into_ord().unwrap()
+            })
+            .collect();
+        v.sort();
+
+        let firsts: Vec<u8> = v.iter().map(|b| b.bytes()[0]).collect();
+        assert_eq!(vec![1, 1, 2, 3], firsts);
+        assert_eq!(v[0], v[1]);
+        assert_ne!(v[1], v[2]);
+        assert!(v[2] < v[3]);
+        assert_eq!(ProtectState::NoAccess, v[0].protect_state());
+    }
+
+    #[test]
+    fn it_should_ct_eq_any_backing() {
+        let mut a = SecBuf::with_secure(8);
+        let mut b = SecBuf::with_insecure(8);
+        a.write(0, &[5; 8]).unwrap();
+        b.write(0, &[5; 8]).unwrap();
+        assert!(a.ct_eq(&mut b));
+        b.write(7, &[6]).unwrap();
+        assert!(!a.ct_eq(&mut b));
+        assert!(!a.ct_eq(&mut SecBuf::with_insecure(16)));
+    }
+
+    #[test]
+    fn it_should_refuse_to_order_secure() {
+        assert!(SecBuf::with_secure(8).into_ord().is_err());
+        let b = SecBuf::with_insecure(8).into_ord().unwrap().into_inner();
+        assert_eq!(8, b.len());
+    }
+
+    #[test]
+    fn it_should_try_lock() {
+        let mut b = SecBuf::with_secure(8);
+        {
+            let mut b = b.try_write_lock().unwrap();
+            match b.try_read_lock() {
+                Err(SodiumError::LockState(ProtectState::ReadWrite)) => (),
+                _ => panic!("expected LockState(ReadWrite)"),
+            }
+        }
+        assert!(b.try_read_lock().is_ok());
+    }
+
+    #[test]
+    fn it_should_try_allocate() {
+        match SecBuf::try_with_secure(12) {
+            Err(SodiumError::UnsupportedKeyLength(12)) => (),
+            _ => panic!("expected UnsupportedKeyLength(12)"),
+        }
+        assert_eq!(16, SecBuf::try_with_secure(16).unwrap().len());
+        assert_eq!(12, SecBuf::try_with_mlocked(12).unwrap().len());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn it_should_return_allocation_failure() {
+        FAIL_SECURE_ALLOC.with(|f| f.set(true));
+        let res = SecBuf::try_with_secure(32);
+        FAIL_SECURE_ALLOC.with(|f| f.set(false));
+        match res {
+            Err(SodiumError::AllocationFailed { requested: 32, .. }) => (),
+            _ => panic!("expected AllocationFailed"),
+        }
+        assert_eq!(32, SecBuf::try_with_secure(32).unwrap().len());
+        assert_eq!(5, SecBuf::try_with_insecure(5).unwrap().len());
+    }
+
+    #[test]
+    fn it_should_fill() {
+        for mut b in vec![SecBuf::with_insecure(8), SecBuf::with_secure(8)] {
+            b.fill(0xab);
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+            let b = b.read_lock();
+            assert_eq!(format!("{:?}", [0xabu8; 8]), b.debug_unredacted());
+        }
+    }
+
+    #[test]
+    fn it_should_display_memory_stats() {
+        let stats = SecureMemoryStats {
+            live_buffers: 3,
+            allocated_bytes: 1_992_294,
+            memlock_limit: Some(MemlockLimit {
+                soft: 2 * 1024 * 1024,
+                hard: u64::max_value(),
+            }),
+        };
+        assert_eq!(
+            "1.9 MiB of 2 MiB mlock limit in use (3 buffers)",
+            stats.to_string()
+        );
+        let stats = SecureMemoryStats {
+            memlock_limit: None,
+            ..stats
+        };
+        assert_eq!(
+            "1.9 MiB in use, mlock limit unknown (3 buffers)",
+            stats.to_string()
+        );
+    }
+
+    #[test]
+    fn it_should_resize_secure() {
+        let mut b = SecBuf::with_insecure(8);
+        b.write(0, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        b.resize_secure(16).unwrap();
+        assert!(b.is_secure());
+        assert_eq!(16, b.len());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        {
+            let b = b.read_lock();
+            assert_eq!(
+                "[1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, 0, 0, 0, 0]",
+                b.debug_unredacted()
+            );
+        }
+
+        b.resize_secure(8).unwrap();
+        b.resize_secure(16).unwrap();
+        b.resize_secure(8).unwrap();
+        assert_eq!(8, b.len());
+        let b = b.read_lock();
+        assert_eq!("[1, 2, 3, 4, 5, 6, 7, 8]", b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_resize_secure_to_any_length() {
+        let mut b = SecBuf::with_insecure(8);
+        b.fill(2);
+        b.resize_secure(20).unwrap();
+        assert!(b.is_secure());
+        assert_eq!(20, b.len());
+        b.resize_secure(13).unwrap();
+        assert_eq!(13, b.len());
+        let mut expected = [0u8; 13];
+        expected[..8].copy_from_slice(&[2; 8]);
+        assert_eq!(format!("{:?}", expected), b.read_lock().debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_truncate_on_shrink() {
+        let mut b = SecBuf::with_secure(16);
+        b.write(0, &[9u8; 16]).unwrap();
+        b.write(8, &[7u8; 8]).unwrap();
+        b.resize_secure(8).unwrap();
+        let b = b.read_lock();
+        assert_eq!(format!("{:?}", [9u8; 8]), b.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_increment() {
+        let mut b = SecBuf::with_insecure(24);
+        b.increment();
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        {
+            let b = b.read_lock();
+            assert_eq!(1, b[0]);
+            assert!(b[1..].iter().all(|x| *x == 0));
         }
+
+        b.write(0, &[0xff, 0x00]).unwrap();
+        b.increment();
         {
             let b = b.read_lock();
-            assert_eq!("[42, 222]", format!("{:?}", *b));
+            assert_eq!(&[0x00u8, 0x01][..], &b[..2]);
         }
+
+        b.fill(0xff);
+        b.increment();
+        let b = b.read_lock();
+        assert_eq!(format!("{:?}", [0u8; 24]), b.debug_unredacted());
     }
 
     #[test]
-    #[should_panic]
-    fn it_should_panic_on_not_readable() {
-        let b = SecBuf::with_insecure(1);
-        assert_eq!(22, b[0]);
+    fn it_should_add() {
+        let mut a = SecBuf::with_insecure(4);
+        let mut b = SecBuf::with_secure(8);
+        assert!(a.add(&mut b).is_err());
+
+        let mut b = SecBuf::with_insecure(4);
+        a.write(0, &[3, 0, 0, 0]).unwrap();
+        b.write(0, &[4, 1, 0, 0]).unwrap();
+        a.add(&mut b).unwrap();
+        assert_eq!(ProtectState::NoAccess, a.protect_state());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        {
+            let a = a.read_lock();
+            assert_eq!("[7, 1, 0, 0]", a.debug_unredacted());
+        }
+
+        // carry across bytes, and wrap past the top
+        a.write(0, &[0xff, 0xff, 0x00, 0xff]).unwrap();
+        b.write(0, &[0x01, 0x00, 0x00, 0x01]).unwrap();
+        a.add(&mut b).unwrap();
+        let a = a.read_lock();
+        assert_eq!("[0, 0, 1, 0]", a.debug_unredacted());
     }
 
     #[test]
-    #[should_panic]
-    fn it_should_panic_on_not_writeable() {
-        let mut b = SecBuf::with_insecure(1);
-        b[0] = 22;
+    fn it_should_handle_empty_buffers() {
+        for mut b in vec![SecBuf::with_insecure(0), SecBuf::with_secure(0)] {
+            assert!(b.is_empty());
+            assert_eq!(0, b.len());
+            {
+                let b = b.read_lock();
+                assert!(b.is_empty());
+            }
+            {
+                let mut b = b.write_lock();
+                assert_eq!(0, b.iter_mut().count());
+            }
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+            b.write(0, &[]).unwrap();
+        }
+        assert!(SecBuf::with_secure(0).is_secure());
+        assert!(!SecBuf::with_insecure(8).is_empty());
     }
 
     #[test]
-    fn it_should_write() {
-        let mut b = SecBuf::with_insecure(4);
-        b.write(1, &[42, 42]).unwrap();
+    #[cfg(debug_assertions)]
+    fn it_should_pass_untouched_canaries() {
+        let mut b = SecBuf::with_canary(16);
+        assert_eq!(16, b.len());
+        b.write(0, &[0xff; 16]).unwrap();
+        let b = b.read_lock();
+        assert_eq!(format!("{:?}", [0xffu8; 16]), b.debug_unredacted());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "after the end")]
+    fn it_should_detect_canary_overflow() {
+        let mut b = SecBuf::with_canary(16);
+        let mut b = b.write_lock();
+        unsafe {
+            *(**b).as_mut_ptr().add(16) ^= 0xff;
+        }
+        // dropping the lock calls noaccess()
+    }
+
+    #[test]
+    #[cfg(all(feature = "mmap", unix))]
+    fn it_should_round_trip_mmap() {
+        let len = 1024 * 1024;
+        let mut b = match SecBuf::try_with_mmap(len) {
+            Ok(b) => b,
+            // mlock limits too low on this machine
+            Err(SodiumError::AllocationFailed { .. }) => return,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(b.is_secure());
+        assert_eq!(len, b.len());
+
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        b.write(0, &data).unwrap();
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        let b = b.read_lock();
+        assert!(data[..] == b[..]);
+    }
+
+    #[test]
+    fn it_should_secure_clone_any_length() {
+        let mut a = SecBuf::with_secure_any(13);
+        a.fill(5);
+        let mut b = a.secure_clone().unwrap();
+        assert!(b.is_secure());
+        assert_eq!(13, b.len());
+        assert_eq!(
+            a.read_lock().debug_unredacted(),
+            b.read_lock().debug_unredacted()
+        );
+    }
+
+    #[test]
+    fn it_should_deep_clone() {
+        let mut a = SecBuf::with_secure(16);
+        a.fill(3);
+        let mut secure = a.secure_clone().unwrap();
+        let mut insecure = a.insecure_clone();
+        assert!(secure.is_secure());
+        assert!(!insecure.is_secure());
+        assert_eq!(ProtectState::NoAccess, a.protect_state());
+        assert_eq!(ProtectState::NoAccess, secure.protect_state());
+        assert_eq!(ProtectState::NoAccess, insecure.protect_state());
+
+        a.fill(4);
+        let secure = secure.read_lock();
+        let insecure = insecure.read_lock();
+        assert_eq!(format!("{:?}", [3u8; 16]), secure.debug_unredacted());
+        assert_eq!(format!("{:?}", [3u8; 16]), insecure.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_iterate_bytes_under_one_lock() {
+        let mut b = SecBuf::with_secure(8);
+        b.write(0, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        {
+            let iter = b.byte_iter();
+            assert_eq!(8, iter.len());
+            let bytes: Vec<u8> = iter.collect();
+            assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], bytes);
+        }
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        let sum: u32 = b.byte_iter().skip(4).map(u32::from).sum();
+        assert_eq!(26, sum);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_apply_otp_once() {
+        use crate::random::random_secbuf;
+        let mut message = SecBuf::with_secure(32);
+        random_secbuf(&mut message);
+        let mut pad = SecBuf::with_secure(32);
+        random_secbuf(&mut pad);
+        let mut pad_copy = pad.secure_clone().unwrap();
+
+        let mut cipher = message.apply_otp(&mut pad).unwrap();
+        assert!(!cipher.is_secure());
+        {
+            let pad = pad.read_lock();
+            assert!(pad.iter().all(|b| *b == 0));
+        }
+        match message.apply_otp(&mut pad) {
+            Err(SodiumError::PadUsed) => (),
+            _ => panic!("expected PadUsed"),
+        }
+
+        let mut plain = cipher.apply_otp(&mut pad_copy).unwrap();
+        let message = message.read_lock();
+        let plain = plain.read_lock();
+        assert_eq!(message.debug_unredacted(), plain.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_reject_otp_size_mismatch() {
+        use crate::random::random_secbuf;
+        let mut message = SecBuf::with_insecure(8);
+        let mut pad = SecBuf::with_insecure(9);
+        random_secbuf(&mut pad);
+        match message.apply_otp(&mut pad) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+        let pad = pad.read_lock();
+        assert!(!pad.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn it_should_split_and_recover_xor_shares() {
+        use crate::random::random_secbuf;
+        let mut key = SecBuf::with_secure(32);
+        random_secbuf(&mut key);
+
+        let mut shares = key.split_xor_shares(3).unwrap();
+        assert_eq!(3, shares.len());
+        assert!(shares.iter().all(|s| s.is_secure() && s.len() == 32));
+
+        let mut parts: Vec<&mut SecBuf> = shares.iter_mut().collect();
+        let mut recovered = SecBuf::multi_party_xor(&mut parts).unwrap();
+        assert!(recovered.is_secure());
+
+        let mut parts: Vec<&mut SecBuf> = shares.iter_mut().take(2).collect();
+        let mut partial = SecBuf::multi_party_xor(&mut parts).unwrap();
+
+        let key = key.read_lock();
+        let recovered = recovered.read_lock();
+        let partial = partial.read_lock();
+        assert_eq!(key.debug_unredacted(), recovered.debug_unredacted());
+        assert_ne!(key.debug_unredacted(), partial.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_split_an_odd_length_secret() {
+        let mut key = SecBuf::with_secure_any(13);
+        key.write(0, &[7; 13]).unwrap();
+
+        let mut shares = key.split_xor_shares(2).unwrap();
+        assert!(shares.iter().all(|s| s.is_secure() && s.len() == 13));
+
+        let mut parts: Vec<&mut SecBuf> = shares.iter_mut().collect();
+        let mut recovered = SecBuf::multi_party_xor(&mut parts).unwrap();
+        assert_eq!(13, recovered.len());
+        assert_eq!(
+            key.read_lock().debug_unredacted(),
+            recovered.read_lock().debug_unredacted()
+        );
+    }
+
+    #[test]
+    fn it_should_reject_bad_xor_parts() {
+        match SecBuf::multi_party_xor(&mut []) {
+            Err(SodiumError::EmptyInput) => (),
+            _ => panic!("expected EmptyInput"),
+        }
+        let mut a = SecBuf::with_insecure(8);
+        let mut b = SecBuf::with_insecure(9);
+        match SecBuf::multi_party_xor(&mut [&mut a, &mut b]) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+        assert!(a.split_xor_shares(0).is_err());
+    }
+
+    #[test]
+    fn it_should_lock_a_whole_set() {
+        let mut set = SecBufSet::new(Vec::new());
+        assert!(set.is_empty());
+        for i in 0..5 {
+            let mut b = SecBuf::with_secure(8 * (i + 1));
+            b.fill(i as u8);
+            set.push(b);
+        }
+        assert_eq!(5, set.len());
+
+        set.get_mut(0).unwrap().readable();
+        set.lock_all();
+        assert!((&set)
+            .into_iter()
+            .all(|b| b.protect_state() == ProtectState::NoAccess));
+
+        {
+            let all = set.read_all();
+            assert_eq!(5, all.len());
+            for (i, b) in all.iter().enumerate() {
+                assert_eq!(ProtectState::ReadOnly, b.protect_state());
+                assert_eq!(8 * (i + 1), b.len());
+                assert!(b.iter().all(|x| *x == i as u8));
+            }
+        }
+        assert!((&set)
+            .into_iter()
+            .all(|b| b.protect_state() == ProtectState::NoAccess));
+
+        {
+            let mut all = set.write_all();
+            for b in all.iter_mut() {
+                assert_eq!(ProtectState::ReadWrite, b.protect_state());
+                b[0] = 0xee;
+            }
+        }
+        for b in &mut set {
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+            let b = b.read_lock();
+            assert_eq!(0xee, b[0]);
+        }
+
+        let bufs: Vec<SecBuf> = set.into_iter().collect();
+        assert_eq!(5, bufs.len());
+    }
+
+    #[test]
+    fn it_should_read_lock_a_pair() {
+        let mut a = SecBuf::with_secure(8);
+        let mut b = SecBuf::with_insecure(8);
+        a.fill(1);
+        b.fill(2);
         {
+            let (la, lb) = read_lock_pair(&mut a, &mut b);
+            assert_eq!(ProtectState::ReadOnly, la.protect_state());
+            assert_eq!(ProtectState::ReadOnly, lb.protect_state());
+            assert_eq!(3, la[0] + lb[7]);
+        }
+        assert_eq!(ProtectState::NoAccess, a.protect_state());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        drop(read_lock_pair(&mut a, &mut b));
+        assert_eq!(ProtectState::NoAccess, a.protect_state());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_satisfy_de_morgan() {
+        fn from(bytes: &[u8]) -> SecBuf {
+            let mut b = SecBuf::with_insecure(bytes.len());
+            b.write(0, bytes).unwrap();
+            b
+        }
+        fn dump(b: &mut SecBuf) -> String {
             let b = b.read_lock();
-            assert_eq!("[0, 42, 42, 0]", format!("{:?}", *b));
+            b.debug_unredacted()
+        }
+        let mut a = from(&[0x00, 0xff, 0x0f, 0xa5]);
+        let mut b = from(&[0x00, 0x00, 0x3c, 0x5a]);
+
+        assert_eq!("[0, 0, 12, 0]", dump(&mut a.bitwise_and(&mut b).unwrap()));
+        assert_eq!(
+            "[0, 255, 63, 255]",
+            dump(&mut a.bitwise_or(&mut b).unwrap())
+        );
+        assert_eq!(
+            "[0, 255, 51, 255]",
+            dump(&mut a.bitwise_xor(&mut b).unwrap())
+        );
+        assert_eq!("[255, 0, 240, 90]", dump(&mut a.bitwise_not().unwrap()));
+
+        // !(a & b) == !a | !b
+        let mut lhs = a.bitwise_and(&mut b).unwrap().bitwise_not().unwrap();
+        let mut rhs = a
+            .bitwise_not()
+            .unwrap()
+            .bitwise_or(&mut b.bitwise_not().unwrap())
+            .unwrap();
+        assert!(lhs.ct_eq(&mut rhs));
+        // !(a | b) == !a & !b
+        let mut lhs = a.bitwise_or(&mut b).unwrap().bitwise_not().unwrap();
+        let mut rhs = a
+            .bitwise_not()
+            .unwrap()
+            .bitwise_and(&mut b.bitwise_not().unwrap())
+            .unwrap();
+        assert!(lhs.ct_eq(&mut rhs));
+
+        let mut c = SecBuf::with_insecure(3);
+        match a.bitwise_and(&mut c) {
+            Err(SodiumError::LengthMismatch(m)) => {
+                assert_eq!("other Buffer must be 4 bytes, got 3", m)
+            }
+            _ => panic!("expected LengthMismatch"),
+        }
+        assert_eq!(ProtectState::NoAccess, a.protect_state());
+    }
+
+    #[test]
+    fn it_should_xor_itself_to_zero() {
+        use crate::random::random_secbuf;
+        let mut a = SecBuf::with_secure(32);
+        random_secbuf(&mut a);
+        let mut copy = a.secure_clone().unwrap();
+        let mut zero = a.bitwise_xor(&mut copy).unwrap();
+        assert!(!zero.is_secure());
+        assert!(zero.is_zero());
+        assert!(!a.is_zero());
+    }
+
+    #[test]
+    fn it_should_rotate_a_key_in_place() {
+        use crate::random::random_secbuf;
+        let mut key = SecBuf::with_secure(32);
+        random_secbuf(&mut key);
+        let old = key.read_lock().debug_unredacted();
+        let mut new = SecBuf::with_secure(32);
+        random_secbuf(&mut new);
+        let expected = new.read_lock().debug_unredacted();
+        let before = key.b.ref_().as_ptr();
+
+        key.copy_from(&mut new).unwrap();
+        assert_eq!(before, key.b.ref_().as_ptr());
+        assert_eq!(ProtectState::NoAccess, key.protect_state());
+        assert_eq!(ProtectState::NoAccess, new.protect_state());
+        assert_eq!(expected, key.read_lock().debug_unredacted());
+        assert_ne!(old, key.read_lock().debug_unredacted());
+        assert_eq!(expected, new.read_lock().debug_unredacted());
+
+        let mut short = SecBuf::with_secure(16);
+        match key.copy_from(&mut short) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+        assert_eq!(expected, key.read_lock().debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_zero_before_releasing_on_wipe() {
+        let zero_on_drop = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let mut b = SecBuf {
+            b: Box::new(SpyBuf {
+                b: vec![0xa5; 32].into_boxed_slice(),
+                zero_on_drop: zero_on_drop.clone(),
+                secure: false,
+            }),
+            p: ProtectState::NoAccess,
+        };
+        // wiping works from any protect state
+        b.readable();
+        b.wipe();
+        assert_eq!(Some(true), *zero_on_drop.lock().unwrap());
+
+        // plain drop leaves zeroing to the backing
+        let zero_on_drop = std::sync::Arc::new(std::sync::Mutex::new(None));
+        drop(SecBuf {
+            b: Box::new(SpyBuf {
+                b: vec![0xa5; 32].into_boxed_slice(),
+                zero_on_drop: zero_on_drop.clone(),
+                secure: false,
+            }),
+            p: ProtectState::NoAccess,
+        });
+        assert_eq!(Some(false), *zero_on_drop.lock().unwrap());
+    }
+
+    #[test]
+    fn it_should_wipe_secure_and_insecure_buffers() {
+        use crate::random::random_secbuf;
+        let mut b = SecBuf::with_secure(64);
+        random_secbuf(&mut b);
+        b.wipe();
+        SecBuf::with_insecure(8).wipe();
+    }
+
+    #[test]
+    fn it_should_align_insecure_aligned_buffers() {
+        for align in &[1, 8, 16, 32] {
+            for s in &[0, 1, 31, 64] {
+                let mut b = SecBuf::with_insecure_aligned(*s, *align);
+                assert_eq!(*s, b.len());
+                assert!(!b.is_secure());
+                b.write(0, &vec![0xa5; *s]).unwrap();
+                let b = b.read_lock();
+                assert_eq!(0, b.as_ptr() as usize % align);
+                assert!(b.iter().all(|x| *x == 0xa5));
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_reject_alignments_that_are_not_powers_of_two() {
+        for align in &[0, 3, 24] {
+            match SecBuf::try_with_insecure_aligned(32, *align) {
+                Err(SodiumError::InvalidAlignment(a)) => assert_eq!(*align, a),
+                _ => panic!("expected InvalidAlignment"),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn it_should_fall_back_to_insecure_memory_when_asked() {
+        let opts = SecureOpts {
+            fallback_insecure: true,
+            ..Default::default()
+        };
+        FAIL_SECURE_ALLOC.with(|f| f.set(true));
+        let fallback = SecBuf::with_secure_opts(32, opts);
+        let refused = SecBuf::with_secure_opts(32, SecureOpts::default());
+        FAIL_SECURE_ALLOC.with(|f| f.set(false));
+
+        let (mut b, err) = fallback.unwrap();
+        assert!(!b.is_secure());
+        b.write(0, &[7; 32]).unwrap();
+        assert!(b.read_lock().iter().all(|x| *x == 7));
+        match err {
+            Some(SodiumError::InsecureFallback(cause)) => match *cause {
+                SodiumError::AllocationFailed { requested: 32, .. } => (),
+                _ => panic!("expected AllocationFailed"),
+            },
+            _ => panic!("expected InsecureFallback"),
+        }
+        match refused {
+            Err(SodiumError::AllocationFailed { requested: 32, .. }) => (),
+            _ => panic!("expected AllocationFailed"),
+        }
+
+        let (b, err) = SecBuf::with_secure_opts(32, opts).unwrap();
+        assert!(b.is_secure());
+        assert!(err.is_none());
+    }
+
+    #[test]
+    fn it_should_use_unprotected_secure_memory() {
+        let opts = SecureOpts {
+            protect: false,
+            ..Default::default()
+        };
+        let (mut b, err) = SecBuf::with_secure_opts(16, opts).unwrap();
+        assert!(err.is_none());
+        assert!(b.is_secure());
+        b.write(0, &[9; 16]).unwrap();
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        assert!(b.read_lock().iter().all(|x| *x == 9));
+    }
+
+    #[test]
+    fn it_should_round_trip_length_prefixed_fields() {
+        let mut a = SecBuf::with_insecure(5);
+        let mut b = SecBuf::with_secure(16);
+        let mut c = SecBuf::with_insecure(20);
+        a.fill(1);
+        b.fill(2);
+        c.fill(3);
+        let mut b10 = SecBuf::with_insecure(10);
+        b10.write(0, &b.read_lock()[..10]).unwrap();
+
+        let mut joined =
+            SecBuf::concat_with_length_prefix(&mut [&mut a, &mut b10, &mut c], PrefixSize::U16)
+                .unwrap();
+        assert_eq!(3 * 2 + 5 + 10 + 20, joined.len());
+        assert!(!joined.is_secure());
+        assert_eq!(&[5, 0], &joined.read_lock()[..2]);
+
+        let mut fields = SecBuf::split_length_prefixed(&mut joined, PrefixSize::U16).unwrap();
+        assert_eq!(3, fields.len());
+        for (field, original) in fields.iter_mut().zip(vec![a, b10, c].iter_mut()) {
+            assert!(!field.is_secure());
+            assert_eq!(
+                original.read_lock().debug_unredacted(),
+                field.read_lock().debug_unredacted()
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_reject_bad_length_prefixes() {
+        let mut long = SecBuf::with_insecure(256);
+        match SecBuf::concat_with_length_prefix(&mut [&mut long], PrefixSize::U8) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+        let mut joined =
+            SecBuf::concat_with_length_prefix(&mut [&mut long], PrefixSize::U32).unwrap();
+        assert_eq!(&[0, 1, 0, 0], &joined.read_lock()[..4]);
+        assert_eq!(
+            1,
+            SecBuf::split_length_prefixed(&mut joined, PrefixSize::U32)
+                .unwrap()
+                .len()
+        );
+
+        let mut truncated = SecBuf::with_insecure(4);
+        truncated.write(0, &[3, 0, 9, 9]).unwrap();
+        match SecBuf::split_length_prefixed(&mut truncated, PrefixSize::U16) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+        match SecBuf::split_length_prefixed(&mut truncated, PrefixSize::U32) {
+            Err(SodiumError::OutOfBounds(_)) => (),
+            _ => panic!("expected OutOfBounds"),
+        }
+        let mut empty = SecBuf::with_insecure(0);
+        assert!(SecBuf::split_length_prefixed(&mut empty, PrefixSize::U8)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn it_should_scrub_guarded_buffers_on_panic() {
+        let (b, guarded) = spy(false);
+        let (unguarded_b, unguarded) = spy(false);
+        let res = std::thread::spawn(move || {
+            let _unguarded_b = unguarded_b;
+            let mut guard = b.scrub_on_panic();
+            assert_eq!(0xa5, guard.read_lock()[0]);
+            panic!("scrub test panic");
+        })
+        .join();
+        assert!(res.is_err());
+        assert_eq!(Some(true), *guarded.lock().unwrap());
+        assert_eq!(Some(false), *unguarded.lock().unwrap());
+    }
+
+    #[test]
+    fn it_should_scrub_a_buffer_swapped_into_a_guard() {
+        let (b, replaced) = spy(false);
+        let (swapped_in, guarded) = spy(false);
+        let res = std::thread::spawn(move || {
+            let mut guard = b.scrub_on_panic();
+            *guard = swapped_in;
+            assert_eq!(0xa5, guard.read_lock()[0]);
+            panic!("scrub test panic");
+        })
+        .join();
+        assert!(res.is_err());
+        assert_eq!(Some(false), *replaced.lock().unwrap());
+        assert_eq!(Some(true), *guarded.lock().unwrap());
+    }
+
+    #[test]
+    fn it_should_restore_the_protect_state_after_scrubbing() {
+        let mut b = SecBuf::with_secure(8);
+        b.write(0, &[0xa5; 8]).unwrap();
+        let mut guard = b.scrub_on_panic();
+        let res = std::panic::catch_unwind(|| panic!("scrub test panic"));
+        assert!(res.is_err());
+        assert_eq!(ProtectState::NoAccess, guard.protect_state());
+        assert!(guard.read_lock().iter().all(|b| *b == 0));
+        assert_eq!(ProtectState::NoAccess, guard.protect_state());
+    }
+
+    #[test]
+    fn it_should_release_a_scrub_guard() {
+        let (b, zero_on_drop) = spy(false);
+        let guard = b.scrub_on_panic();
+        let id = guard.id;
+        assert!(SCRUB_REGISTRY.lock().unwrap().contains_key(&id));
+        let mut b = guard.into_inner();
+        assert!(!SCRUB_REGISTRY.lock().unwrap().contains_key(&id));
+        assert_eq!(0xa5, b.read_lock()[0]);
+        drop(b);
+        assert_eq!(Some(false), *zero_on_drop.lock().unwrap());
+
+        let guard = SecBuf::with_secure(8).scrub_on_panic();
+        let id = guard.id;
+        drop(guard);
+        assert!(!SCRUB_REGISTRY.lock().unwrap().contains_key(&id));
+    }
+
+    #[test]
+    fn it_should_zero_secure_buffers_when_unwinding_past_a_lock() {
+        let (secure_b, secure) = spy(true);
+        let (insecure_b, insecure) = spy(false);
+        let res = std::thread::spawn(move || {
+            let mut secure_b = secure_b;
+            let mut insecure_b = insecure_b;
+            let _s = secure_b.read_lock();
+            let _i = insecure_b.read_lock();
+            panic!("locker test panic");
+        })
+        .join();
+        assert!(res.is_err());
+        assert_eq!(Some(true), *secure.lock().unwrap());
+        assert_eq!(Some(false), *insecure.lock().unwrap());
+    }
+
+    #[test]
+    fn it_should_keep_the_protect_state_when_mprotect_fails() {
+        let (mut b, fail) = failing();
+        fail.store(true, Ordering::SeqCst);
+        match b.try_readable() {
+            Err(SodiumError::Libsodium { .. }) => (),
+            _ => panic!("expected Libsodium"),
+        }
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+        assert!(b.try_write_lock().is_err());
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        fail.store(false, Ordering::SeqCst);
+        b.writable();
+        fail.store(true, Ordering::SeqCst);
+        assert!(b.try_noaccess().is_err());
+        assert_eq!(ProtectState::ReadWrite, b.protect_state());
+        // a double lock is an error rather than a panic
+        match b.try_readable() {
+            Err(SodiumError::LockState(ProtectState::ReadWrite)) => (),
+            _ => panic!("expected LockState"),
+        }
+
+        fail.store(false, Ordering::SeqCst);
+        b.noaccess();
+        {
+            let _locker = b.read_lock();
+            // dropping the Locker must not panic when mprotect fails
+            fail.store(true, Ordering::SeqCst);
         }
+        assert_eq!(ProtectState::ReadOnly, b.protect_state());
+    }
+
+    #[test]
+    fn it_should_map_bytes_in_place() {
+        let mut b = SecBuf::with_secure(8);
+        b.write(0, &[0, 1, 2, 3, 252, 253, 254, 255]).unwrap();
+        b.map_bytes(|x| x.wrapping_add(1));
+        assert_eq!(
+            "[1, 2, 3, 4, 253, 254, 255, 0]",
+            b.read_lock().debug_unredacted()
+        );
+        b.map_bytes(|x| x.wrapping_sub(1));
+        assert_eq!(
+            "[0, 1, 2, 3, 252, 253, 254, 255]",
+            b.read_lock().debug_unredacted()
+        );
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        b.map_bytes_indexed(|i, x| x.wrapping_add(i as u8));
+        assert_eq!("[0, 2, 4, 6, 0, 2, 4, 6]", b.read_lock().debug_unredacted());
+        b.map_bytes_indexed(|i, x| x.wrapping_sub(i as u8));
+        assert_eq!(
+            "[0, 1, 2, 3, 252, 253, 254, 255]",
+            b.read_lock().debug_unredacted()
+        );
     }
 
     #[test]
     #[should_panic]
-    fn it_should_fail_write_on_bad_offset() {
+    fn it_should_panic_mapping_a_locked_buffer() {
         let mut b = SecBuf::with_insecure(4);
-        b.write(3, &[42, 42]).unwrap();
+        b.readable();
+        b.map_bytes(|x| x ^ 0xff);
+    }
+
+    #[test]
+    fn it_should_allocate_secure_memory_of_any_length() {
+        assert!(SecBuf::try_with_secure(7).is_err());
+        for len in 0..=17 {
+            let mut b = SecBuf::with_secure_any(len);
+            assert_eq!(len, b.len());
+            assert!(len == 0 || b.is_secure());
+            let bytes: Vec<u8> = (0..len as u8).collect();
+            b.write(0, &bytes).unwrap();
+            assert_eq!(format!("{:?}", bytes), b.read_lock().debug_unredacted());
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+        }
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    fn it_should_read_write_secure_on_wasm() {
+        let mut b = SecBuf::with_secure(16);
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        {
+            let mut b = b.write_lock();
+            assert_eq!(ProtectState::ReadWrite, b.protect_state());
+            b[0] = 12;
+        }
+        assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+        {
+            let b = b.read_lock();
+            assert_eq!(ProtectState::ReadOnly, b.protect_state());
+            assert_eq!(b[0], 12);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn it_should_round_trip_through_the_locks_on_wasm() {
+        for len in &[13, 16] {
+            let mut b = SecBuf::with_secure_any(*len);
+            assert_eq!(*len, b.len());
+            let bytes: Vec<u8> = (0..*len as u8).collect();
+            b.write(0, &bytes).unwrap();
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+
+            {
+                let mut b = b.write_lock();
+                b[1] = 0xff;
+            }
+            let mut expected = bytes.clone();
+            expected[1] = 0xff;
+            assert_eq!(format!("{:?}", expected), b.read_lock().debug_unredacted());
+            assert_eq!(ProtectState::NoAccess, b.protect_state());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn it_should_wipe_wasm_buf() {
+        let mut b = WasmBuf {
+            b: vec![42; 8].into_boxed_slice(),
+            p: ProtectState::NoAccess,
+        };
+        b.wipe();
+        b.readable();
+        assert_eq!(&[0; 8], b.ref_());
     }
 }
@@ -0,0 +1,337 @@
+//! This module provides access to libsodium
+
+use super::{check_init, secbuf::SecBuf};
+use crate::error::SodiumError;
+
+pub const KEYBYTES: usize = rust_sodium_sys::crypto_secretbox_KEYBYTES as usize;
+pub const NONCEBYTES: usize = rust_sodium_sys::crypto_secretbox_NONCEBYTES as usize;
+/// The cipher is always this many bytes longer than the message
+pub const MACBYTES: usize = rust_sodium_sys::crypto_secretbox_MACBYTES as usize;
+/// The counter taken by `SecBuf::timestamp_nonce`, which fills the rest of the nonce randomly
+pub const NONCE_COUNTERBYTES: usize = 8;
+
+/// validate the key and nonce lengths shared by encrypt and decrypt
+fn check_key_nonce(nonce: &SecBuf, key: &SecBuf) -> Result<(), SodiumError> {
+    if key.len() != KEYBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "key must be a Buffer of length: {}, got {}.",
+            KEYBYTES,
+            key.len()
+        )));
+    }
+    if nonce.len() != NONCEBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "nonce must be a Buffer of length: {}, got {}.",
+            NONCEBYTES,
+            nonce.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Authenticated symmetric encryption of a message
+///
+/// @param {SecBuf} message - data to encrypt
+///
+/// @param {SecBuf} nonce - must never be reused with the same key
+///
+/// @param {SecBuf} key - symmetric secret key
+///
+/// @UseReturn {SecBuf} - the cipher text (message length + MACBYTES)
+pub fn encrypt(
+    message: &mut SecBuf,
+    nonce: &mut SecBuf,
+    key: &mut SecBuf,
+) -> Result<SecBuf, SodiumError> {
+    check_init();
+    check_key_nonce(nonce, key)?;
+    let mut cipher = SecBuf::with_insecure(message.len() + MACBYTES);
+    {
+        let mut cipher = cipher.write_lock();
+        let message = message.read_lock();
+        let nonce = nonce.read_lock();
+        let key = key.read_lock();
+        unsafe {
+            rust_sodium_sys::crypto_secretbox_easy(
+                raw_ptr_char!(cipher),
+                raw_ptr_char_immut!(message),
+                message.len() as libc::c_ulonglong,
+                raw_ptr_char_immut!(nonce),
+                raw_ptr_char_immut!(key),
+            );
+        }
+    }
+    Ok(cipher)
+}
+
+/// Decrypt and authenticate a cipher text produced by `encrypt`
+///
+/// the plaintext is placed in secure memory of any length, see `SecBuf::try_with_secure_any`
+///
+/// @param {SecBuf} cipher - the cipher text
+///
+/// @param {SecBuf} nonce - the nonce used to encrypt
+///
+/// @param {SecBuf} key - symmetric secret key
+///
+/// @UseReturn {SecBuf} - the decrypted message
+pub fn decrypt(
+    cipher: &mut SecBuf,
+    nonce: &mut SecBuf,
+    key: &mut SecBuf,
+) -> Result<SecBuf, SodiumError> {
+    check_init();
+    check_key_nonce(nonce, key)?;
+    if cipher.len() < MACBYTES {
+        return Err(SodiumError::LengthMismatch(format!(
+            "cipher must be at least {} bytes long, got {}.",
+            MACBYTES,
+            cipher.len()
+        )));
+    }
+    let mut message = SecBuf::try_with_secure_any(cipher.len() - MACBYTES)?;
+    let res = {
+        let mut message = message.write_lock();
+        let cipher = cipher.read_lock();
+        let nonce = nonce.read_lock();
+        let key = key.read_lock();
+        unsafe {
+            rust_sodium_sys::crypto_secretbox_open_easy(
+                raw_ptr_char!(message),
+                raw_ptr_char_immut!(cipher),
+                cipher.len() as libc::c_ulonglong,
+                raw_ptr_char_immut!(nonce),
+                raw_ptr_char_immut!(key),
+            )
+        }
+    };
+    if res != 0 {
+        return Err(SodiumError::DecryptionFailed);
+    }
+    Ok(message)
+}
+
+impl SecBuf {
+    /// build a NONCEBYTES nonce of random bytes followed by `counter`,
+    /// which is incremented (as a little-endian integer) first
+    ///
+    /// @param {SecBuf} counter - NONCE_COUNTERBYTES, returns CounterOverflow
+    /// once it reaches all 0xff rather than wrapping
+    pub fn timestamp_nonce(counter: &mut SecBuf) -> Result<SecBuf, SodiumError> {
+        if counter.len() != NONCE_COUNTERBYTES {
+            return Err(SodiumError::SizeMismatch(format!(
+                "counter must be a Buffer of length: {}, got {}.",
+                NONCE_COUNTERBYTES,
+                counter.len()
+            )));
+        }
+        check_init();
+        let mut nonce = SecBuf::try_with_insecure(NONCEBYTES)?;
+        let random_len = NONCEBYTES - NONCE_COUNTERBYTES;
+        crate::random::random_secbuf_range(&mut nonce, 0..random_len)?;
+
+        let mut counter = counter.write_lock();
+        if counter.iter().all(|b| *b == 0xff) {
+            return Err(SodiumError::CounterOverflow);
+        }
+        unsafe {
+            rust_sodium_sys::sodium_increment(raw_ptr_char!(counter), counter.len());
+        }
+        nonce.write(random_len, &counter)?;
+        Ok(nonce)
+    }
+
+    /// encrypt the contents of this SecBuf with `encrypt`,
+    /// returning the cipher text as plain bytes
+    ///
+    /// @param {SecBuf} nonce - must never be reused with the same key
+    ///
+    /// @param {SecBuf} key - symmetric secret key
+    pub fn encrypt(
+        &mut self,
+        nonce: &mut SecBuf,
+        key: &mut SecBuf,
+    ) -> Result<Vec<u8>, SodiumError> {
+        let mut cipher = encrypt(self, nonce, key)?;
+        let cipher = cipher.read_lock();
+        Ok(cipher.to_vec())
+    }
+
+    /// decrypt and authenticate cipher text bytes produced by `SecBuf::encrypt`
+    ///
+    /// unlike `decrypt`, the plaintext is placed in insecure memory,
+    /// so any length is accepted
+    ///
+    /// @param {SecBuf} nonce - the nonce used to encrypt
+    ///
+    /// @param {SecBuf} key - symmetric secret key
+    pub fn decrypt(
+        ciphertext: &[u8],
+        nonce: &mut SecBuf,
+        key: &mut SecBuf,
+    ) -> Result<SecBuf, SodiumError> {
+        check_init();
+        check_key_nonce(nonce, key)?;
+        if ciphertext.len() < MACBYTES {
+            return Err(SodiumError::LengthMismatch(format!(
+                "cipher must be at least {} bytes long, got {}.",
+                MACBYTES,
+                ciphertext.len()
+            )));
+        }
+        let mut message = SecBuf::with_insecure(ciphertext.len() - MACBYTES);
+        let res = {
+            let mut message = message.write_lock();
+            let nonce = nonce.read_lock();
+            let key = key.read_lock();
+            unsafe {
+                rust_sodium_sys::crypto_secretbox_open_easy(
+                    raw_ptr_char!(message),
+                    raw_ptr_char_immut!(ciphertext),
+                    ciphertext.len() as libc::c_ulonglong,
+                    raw_ptr_char_immut!(nonce),
+                    raw_ptr_char_immut!(key),
+                )
+            }
+        };
+        if res != 0 {
+            return Err(SodiumError::DecryptionFailed);
+        }
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::random_secbuf;
+
+    #[test]
+    fn it_should_encrypt_and_decrypt() {
+        let mut message = SecBuf::with_secure(32);
+        random_secbuf(&mut message);
+        let mut nonce = SecBuf::with_insecure(NONCEBYTES);
+        random_secbuf(&mut nonce);
+        let mut key = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut key);
+
+        let mut cipher = encrypt(&mut message, &mut nonce, &mut key).unwrap();
+        assert_eq!(32 + MACBYTES, cipher.len());
+
+        let mut decrypted = decrypt(&mut cipher, &mut nonce, &mut key).unwrap();
+        let message = message.read_lock();
+        let decrypted = decrypted.read_lock();
+        assert_eq!(message.debug_unredacted(), decrypted.debug_unredacted());
+    }
+
+    #[test]
+    fn it_should_fail_to_decrypt_tampered_cipher() {
+        let mut message = SecBuf::with_secure(32);
+        random_secbuf(&mut message);
+        let mut nonce = SecBuf::with_insecure(NONCEBYTES);
+        random_secbuf(&mut nonce);
+        let mut key = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut key);
+
+        let mut cipher = encrypt(&mut message, &mut nonce, &mut key).unwrap();
+        {
+            let mut cipher = cipher.write_lock();
+            cipher[0] ^= 1;
+        }
+
+        match decrypt(&mut cipher, &mut nonce, &mut key) {
+            Err(SodiumError::DecryptionFailed) => (),
+            _ => panic!("expected DecryptionFailed"),
+        }
+    }
+
+    #[test]
+    fn it_should_reject_bad_key_length() {
+        let mut message = SecBuf::with_secure(32);
+        let mut nonce = SecBuf::with_insecure(NONCEBYTES);
+        let mut key = SecBuf::with_secure(16);
+        encrypt(&mut message, &mut nonce, &mut key).expect_err("should have failed");
+    }
+
+    #[test]
+    fn it_should_round_trip_secbuf_methods() {
+        let mut message = SecBuf::with_secure(32);
+        random_secbuf(&mut message);
+        let mut nonce = SecBuf::with_insecure(NONCEBYTES);
+        random_secbuf(&mut nonce);
+        let mut key = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut key);
+
+        let mut cipher = message.encrypt(&mut nonce, &mut key).unwrap();
+        assert_eq!(32 + MACBYTES, cipher.len());
+
+        let mut decrypted = SecBuf::decrypt(&cipher, &mut nonce, &mut key).unwrap();
+        assert!(!decrypted.is_secure());
+        {
+            let message = message.read_lock();
+            let decrypted = decrypted.read_lock();
+            assert_eq!(message.debug_unredacted(), decrypted.debug_unredacted());
+        }
+
+        cipher[5] ^= 0x80;
+        match SecBuf::decrypt(&cipher, &mut nonce, &mut key) {
+            Err(SodiumError::DecryptionFailed) => (),
+            _ => panic!("expected DecryptionFailed"),
+        }
+    }
+
+    #[test]
+    fn it_should_make_distinct_timestamp_nonces() {
+        let mut counter = SecBuf::with_insecure(NONCE_COUNTERBYTES);
+        counter.fill(0);
+        let mut nonces: Vec<String> = Vec::new();
+        let mut last = 0u64;
+        for _ in 0..300 {
+            let mut nonce = SecBuf::timestamp_nonce(&mut counter).unwrap();
+            assert_eq!(NONCEBYTES, nonce.len());
+            let nonce = nonce.read_lock();
+            let count = nonce[NONCEBYTES - NONCE_COUNTERBYTES..]
+                .iter()
+                .rev()
+                .fold(0u64, |c, b| (c << 8) | u64::from(*b));
+            assert!(count > last);
+            last = count;
+            nonces.push(nonce.debug_unredacted());
+        }
+        assert_eq!(300, last);
+        nonces.sort();
+        nonces.dedup();
+        assert_eq!(300, nonces.len());
+    }
+
+    #[test]
+    fn it_should_detect_timestamp_nonce_counter_overflow() {
+        let mut counter = SecBuf::with_insecure(NONCE_COUNTERBYTES);
+        counter.fill(0xff);
+        match SecBuf::timestamp_nonce(&mut counter) {
+            Err(SodiumError::CounterOverflow) => (),
+            _ => panic!("expected CounterOverflow"),
+        }
+        let mut counter = SecBuf::with_insecure(4);
+        assert!(SecBuf::timestamp_nonce(&mut counter).is_err());
+    }
+
+    #[test]
+    fn it_should_decrypt_an_odd_length_message() {
+        let mut message = SecBuf::with_insecure(13);
+        random_secbuf(&mut message);
+        let mut nonce = SecBuf::with_insecure(NONCEBYTES);
+        random_secbuf(&mut nonce);
+        let mut key = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut key);
+
+        let mut cipher = encrypt(&mut message, &mut nonce, &mut key).unwrap();
+        let mut decrypted = decrypt(&mut cipher, &mut nonce, &mut key).unwrap();
+        assert!(decrypted.is_secure());
+        assert_eq!(13, decrypted.len());
+        assert_eq!(
+            message.read_lock().debug_unredacted(),
+            decrypted.read_lock().debug_unredacted()
+        );
+    }
+}
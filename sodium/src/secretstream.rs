@@ -0,0 +1,420 @@
+//! This module provides access to libsodium's XChaCha20-Poly1305 secretstream,
+//! authenticated encryption of a sequence of messages, for payloads too large
+//! to encrypt in one piece
+
+use super::{check_init, secbuf::SecBuf};
+use crate::error::SodiumError;
+
+use rust_sodium_sys::crypto_secretstream_xchacha20poly1305_state as State;
+
+pub const KEYBYTES: usize =
+    rust_sodium_sys::crypto_secretstream_xchacha20poly1305_KEYBYTES as usize;
+pub const HEADERBYTES: usize =
+    rust_sodium_sys::crypto_secretstream_xchacha20poly1305_HEADERBYTES as usize;
+/// Each cipher text chunk is this many bytes longer than its plaintext
+pub const ABYTES: usize = rust_sodium_sys::crypto_secretstream_xchacha20poly1305_ABYTES as usize;
+
+/// an ordinary chunk
+pub const TAG_MESSAGE: u8 =
+    rust_sodium_sys::crypto_secretstream_xchacha20poly1305_TAG_MESSAGE as u8;
+/// the end of a group of chunks, the stream continues
+pub const TAG_PUSH: u8 = rust_sodium_sys::crypto_secretstream_xchacha20poly1305_TAG_PUSH as u8;
+/// the key is ratcheted after this chunk
+pub const TAG_REKEY: u8 = rust_sodium_sys::crypto_secretstream_xchacha20poly1305_TAG_REKEY as u8;
+/// the last chunk of the stream
+pub const TAG_FINAL: u8 = rust_sodium_sys::crypto_secretstream_xchacha20poly1305_TAG_FINAL as u8;
+
+/// The tag authenticated along with each chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamTag {
+    /// an ordinary chunk
+    Message,
+    /// the end of a group of chunks, the stream continues
+    Push,
+    /// the key is ratcheted after this chunk
+    Rekey,
+    /// the last chunk of the stream
+    Final,
+}
+
+impl StreamTag {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            StreamTag::Message => TAG_MESSAGE,
+            StreamTag::Push => TAG_PUSH,
+            StreamTag::Rekey => TAG_REKEY,
+            StreamTag::Final => TAG_FINAL,
+        }
+    }
+
+    pub fn from_u8(tag: u8) -> Option<StreamTag> {
+        match tag {
+            TAG_MESSAGE => Some(StreamTag::Message),
+            TAG_PUSH => Some(StreamTag::Push),
+            TAG_REKEY => Some(StreamTag::Rekey),
+            TAG_FINAL => Some(StreamTag::Final),
+            _ => None,
+        }
+    }
+}
+
+fn check_key(key: &SecBuf) -> Result<(), SodiumError> {
+    if key.len() != KEYBYTES {
+        return Err(SodiumError::SizeMismatch(format!(
+            "key must be a Buffer of length: {}, got {}.",
+            KEYBYTES,
+            key.len()
+        )));
+    }
+    Ok(())
+}
+
+/// the stream state holds a key derived from the stream key
+fn zero_state(state: &mut State) {
+    unsafe {
+        rust_sodium_sys::sodium_memzero(
+            state as *mut State as *mut libc::c_void,
+            std::mem::size_of::<State>(),
+        );
+    }
+}
+
+/// Encrypts a stream of chunks, to be read back in the same order by a StreamDecryptor
+pub struct StreamEncryptor {
+    state: State,
+    finished: bool,
+}
+
+impl StreamEncryptor {
+    /// start a new stream
+    ///
+    /// @param {SecBuf} key - KEYBYTES symmetric secret key
+    ///
+    /// @UseReturn {(SecBuf, StreamEncryptor)} - the HEADERBYTES header must be
+    /// sent ahead of the chunks, it is not secret
+    pub fn new(key: &mut SecBuf) -> Result<(SecBuf, StreamEncryptor), SodiumError> {
+        check_init();
+        check_key(key)?;
+        let mut enc = StreamEncryptor {
+            state: State::default(),
+            finished: false,
+        };
+        let mut header = SecBuf::try_with_insecure(HEADERBYTES)?;
+        {
+            let mut header = header.write_lock();
+            let key = key.read_lock();
+            unsafe {
+                rust_sodium_sys::crypto_secretstream_xchacha20poly1305_init_push(
+                    &mut enc.state,
+                    raw_ptr_char!(header),
+                    raw_ptr_char_immut!(key),
+                );
+            }
+        }
+        Ok((header, enc))
+    }
+
+    /// encrypt the next chunk of the stream
+    ///
+    /// @param {[u8]} plaintext - the chunk
+    ///
+    /// @param {StreamTag} tag - Message, or Final for the last chunk
+    ///
+    /// @UseReturn {Vec<u8>} - the cipher text (plaintext length + ABYTES)
+    pub fn push(&mut self, plaintext: &[u8], tag: StreamTag) -> Result<Vec<u8>, SodiumError> {
+        if self.finished {
+            return Err(SodiumError::new("stream already has a final chunk"));
+        }
+        let mut cipher = vec![0u8; plaintext.len() + ABYTES];
+        let res = unsafe {
+            rust_sodium_sys::crypto_secretstream_xchacha20poly1305_push(
+                &mut self.state,
+                raw_ptr_char!(cipher),
+                std::ptr::null_mut(),
+                raw_ptr_char_immut!(plaintext),
+                plaintext.len() as libc::c_ulonglong,
+                std::ptr::null(),
+                0,
+                tag.as_u8(),
+            )
+        };
+        if res != 0 {
+            return Err(SodiumError::Libsodium {
+                function: "crypto_secretstream_xchacha20poly1305_push",
+                code: res,
+            });
+        }
+        self.finished = tag == StreamTag::Final;
+        Ok(cipher)
+    }
+}
+
+impl Drop for StreamEncryptor {
+    fn drop(&mut self) {
+        zero_state(&mut self.state);
+    }
+}
+
+/// Decrypts and authenticates the chunks of a stream made by a StreamEncryptor
+///
+/// chunks that were reordered, dropped from the middle or tampered with fail to pull,
+/// a stream cut short after a whole chunk is caught by `finish`
+pub struct StreamDecryptor {
+    state: State,
+    finished: bool,
+}
+
+impl StreamDecryptor {
+    /// start reading a stream
+    ///
+    /// @param {SecBuf} key - the KEYBYTES key the stream was encrypted with
+    ///
+    /// @param {SecBuf} header - the header returned by `StreamEncryptor::new`
+    pub fn new(key: &mut SecBuf, header: &mut SecBuf) -> Result<StreamDecryptor, SodiumError> {
+        check_init();
+        check_key(key)?;
+        if header.len() != HEADERBYTES {
+            return Err(SodiumError::SizeMismatch(format!(
+                "header must be a Buffer of length: {}, got {}.",
+                HEADERBYTES,
+                header.len()
+            )));
+        }
+        let mut dec = StreamDecryptor {
+            state: State::default(),
+            finished: false,
+        };
+        let header = header.read_lock();
+        let key = key.read_lock();
+        let res = unsafe {
+            rust_sodium_sys::crypto_secretstream_xchacha20poly1305_init_pull(
+                &mut dec.state,
+                raw_ptr_char_immut!(header),
+                raw_ptr_char_immut!(key),
+            )
+        };
+        if res != 0 {
+            return Err(SodiumError::DecryptionFailed);
+        }
+        Ok(dec)
+    }
+
+    /// decrypt and authenticate the next chunk of the stream
+    ///
+    /// nothing may follow the Final chunk
+    ///
+    /// @UseReturn {(Vec<u8>, StreamTag)} - the plaintext and its tag
+    pub fn pull(&mut self, ciphertext: &[u8]) -> Result<(Vec<u8>, StreamTag), SodiumError> {
+        if self.finished {
+            return Err(SodiumError::new("stream continues past its final chunk"));
+        }
+        if ciphertext.len() < ABYTES {
+            return Err(SodiumError::DecryptionFailed);
+        }
+        let mut message = vec![0u8; ciphertext.len() - ABYTES];
+        let mut tag = 0u8;
+        let res = unsafe {
+            rust_sodium_sys::crypto_secretstream_xchacha20poly1305_pull(
+                &mut self.state,
+                raw_ptr_char!(message),
+                std::ptr::null_mut(),
+                &mut tag,
+                raw_ptr_char_immut!(ciphertext),
+                ciphertext.len() as libc::c_ulonglong,
+                std::ptr::null(),
+                0,
+            )
+        };
+        if res != 0 {
+            return Err(SodiumError::DecryptionFailed);
+        }
+        let tag = StreamTag::from_u8(tag).ok_or(SodiumError::DecryptionFailed)?;
+        self.finished = tag == StreamTag::Final;
+        Ok((message, tag))
+    }
+
+    /// whether the Final chunk has been pulled
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// call once the input is exhausted,
+    /// returns StreamTruncated if the Final chunk was never pulled
+    pub fn finish(self) -> Result<(), SodiumError> {
+        if !self.finished {
+            return Err(SodiumError::StreamTruncated);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StreamDecryptor {
+    fn drop(&mut self) {
+        zero_state(&mut self.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::random_secbuf;
+
+    const CHUNK: usize = 64 * 1024;
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut b = SecBuf::with_insecure(len);
+        random_secbuf(&mut b);
+        let b = b.read_lock();
+        b.to_vec()
+    }
+
+    fn random_key() -> SecBuf {
+        let mut key = SecBuf::with_secure(KEYBYTES);
+        random_secbuf(&mut key);
+        key
+    }
+
+    fn encrypt_chunks(key: &mut SecBuf, message: &[u8]) -> (SecBuf, Vec<Vec<u8>>) {
+        let (header, mut enc) = StreamEncryptor::new(key).unwrap();
+        let count = message.chunks(CHUNK).count();
+        let chunks = message
+            .chunks(CHUNK)
+            .enumerate()
+            .map(|(i, c)| {
+                let tag = if i + 1 == count {
+                    StreamTag::Final
+                } else {
+                    StreamTag::Message
+                };
+                enc.push(c, tag).unwrap()
+            })
+            .collect();
+        (header, chunks)
+    }
+
+    #[test]
+    fn it_should_round_trip_a_large_stream() {
+        let mut key = random_key();
+        let message = random_bytes(1024 * 1024);
+
+        let (mut header, chunks) = encrypt_chunks(&mut key, &message);
+        assert_eq!(HEADERBYTES, header.len());
+        assert_eq!(16, chunks.len());
+        assert!(chunks.iter().all(|c| c.len() == CHUNK + ABYTES));
+
+        let mut dec = StreamDecryptor::new(&mut key, &mut header).unwrap();
+        let mut out = Vec::with_capacity(message.len());
+        let mut last_tag = StreamTag::Message;
+        for c in chunks.iter() {
+            let (m, tag) = dec.pull(c).unwrap();
+            out.extend_from_slice(&m);
+            last_tag = tag;
+        }
+        assert_eq!(StreamTag::Final, last_tag);
+        assert_eq!(message, out);
+        dec.finish().unwrap();
+    }
+
+    #[test]
+    fn it_should_round_trip_five_chunks() {
+        let mut key = random_key();
+        let message = random_bytes(5 * CHUNK);
+        let (mut header, chunks) = encrypt_chunks(&mut key, &message);
+        assert_eq!(5, chunks.len());
+
+        let mut dec = StreamDecryptor::new(&mut key, &mut header).unwrap();
+        let mut out = Vec::new();
+        for c in chunks.iter() {
+            assert!(!dec.is_finished());
+            out.extend_from_slice(&dec.pull(c).unwrap().0);
+        }
+        assert!(dec.is_finished());
+        assert!(dec.pull(&chunks[4]).is_err());
+        dec.finish().unwrap();
+        assert_eq!(message, out);
+    }
+
+    #[test]
+    fn it_should_reject_truncated_cipher() {
+        let mut key = random_key();
+        let message = random_bytes(3 * CHUNK);
+        let (mut header, chunks) = encrypt_chunks(&mut key, &message);
+
+        let mut dec = StreamDecryptor::new(&mut key, &mut header).unwrap();
+        dec.pull(&chunks[0]).unwrap();
+        let truncated = &chunks[1][..chunks[1].len() - 1];
+        match dec.pull(truncated) {
+            Err(SodiumError::DecryptionFailed) => (),
+            _ => panic!("expected DecryptionFailed"),
+        }
+        match dec.pull(&chunks[1][..ABYTES - 1]) {
+            Err(SodiumError::DecryptionFailed) => (),
+            _ => panic!("expected DecryptionFailed"),
+        }
+    }
+
+    #[test]
+    fn it_should_reject_reordered_chunks() {
+        let mut key = random_key();
+        let message = random_bytes(5 * CHUNK);
+        let (mut header, mut chunks) = encrypt_chunks(&mut key, &message);
+        chunks.swap(1, 2);
+
+        let mut dec = StreamDecryptor::new(&mut key, &mut header).unwrap();
+        dec.pull(&chunks[0]).unwrap();
+        match dec.pull(&chunks[1]) {
+            Err(SodiumError::DecryptionFailed) => (),
+            _ => panic!("expected DecryptionFailed"),
+        }
+    }
+
+    #[test]
+    fn it_should_detect_a_missing_final_chunk() {
+        let mut key = random_key();
+        let message = random_bytes(5 * CHUNK);
+        let (mut header, mut chunks) = encrypt_chunks(&mut key, &message);
+        chunks.pop();
+
+        let mut dec = StreamDecryptor::new(&mut key, &mut header).unwrap();
+        for c in chunks.iter() {
+            assert_eq!(StreamTag::Message, dec.pull(c).unwrap().1);
+        }
+        assert!(!dec.is_finished());
+        match dec.finish() {
+            Err(SodiumError::StreamTruncated) => (),
+            _ => panic!("expected StreamTruncated"),
+        }
+    }
+
+    #[test]
+    fn it_should_refuse_to_push_after_final() {
+        let mut key = random_key();
+        let (_, mut enc) = StreamEncryptor::new(&mut key).unwrap();
+        enc.push(b"last", StreamTag::Final).unwrap();
+        assert!(enc.push(b"more", StreamTag::Message).is_err());
+    }
+
+    #[test]
+    fn it_should_map_tags() {
+        for tag in [
+            StreamTag::Message,
+            StreamTag::Push,
+            StreamTag::Rekey,
+            StreamTag::Final,
+        ]
+        .iter()
+        {
+            assert_eq!(Some(*tag), StreamTag::from_u8(tag.as_u8()));
+        }
+        assert_eq!(None, StreamTag::from_u8(0x80));
+    }
+
+    #[test]
+    fn it_should_reject_bad_key_and_header() {
+        let mut key = SecBuf::with_secure(16);
+        assert!(StreamEncryptor::new(&mut key).is_err());
+        let mut key = SecBuf::with_secure(KEYBYTES);
+        let mut header = SecBuf::with_insecure(8);
+        assert!(StreamDecryptor::new(&mut key, &mut header).is_err());
+    }
+}
@@ -1,25 +1,74 @@
 //! This module provides access to libsodium
-use super::{check_init, secbuf::SecBuf};
+use super::{
+    check_init,
+    secbuf::{Locker, SecBuf},
+};
 use crate::error::SodiumError;
 
 pub const PUBLICKEYBYTES: usize = rust_sodium_sys::crypto_sign_PUBLICKEYBYTES as usize;
 pub const SECRETKEYBYTES: usize = rust_sodium_sys::crypto_sign_SECRETKEYBYTES as usize;
+pub const SEEDBYTES: usize = rust_sodium_sys::crypto_sign_SEEDBYTES as usize;
+pub const BYTES: usize = rust_sodium_sys::crypto_sign_BYTES as usize;
+/// Size of a converted curve25519 (X25519) key, public or secret
+pub const CURVE25519BYTES: usize = rust_sodium_sys::crypto_scalarmult_BYTES as usize;
 
-/// Generate a signing keypair from a seed buffer
+/// Generate a fresh, random signing keypair
 ///
-/// @param {SecBuf} publicKey - Empty Buffer to be used as publicKey return
+/// the public key is backed by insecure memory, the secret key by secure memory
 ///
-/// @param {SecBuf} privateKey - Empty Buffer to be used as secretKey return
+/// @UseReturn {(SecBuf, SecBuf)} - (publicKey, secretKey) both in NoAccess state
+pub fn keypair() -> Result<(SecBuf, SecBuf), SodiumError> {
+    check_init();
+    let mut public_key = SecBuf::with_insecure(PUBLICKEYBYTES);
+    let mut secret_key = SecBuf::with_secure(SECRETKEYBYTES);
+    {
+        let mut public_key = public_key.write_lock();
+        let mut secret_key = secret_key.write_lock();
+        unsafe {
+            rust_sodium_sys::crypto_sign_keypair(
+                raw_ptr_char!(public_key),
+                raw_ptr_char!(secret_key),
+            );
+        }
+    }
+    Ok((public_key, secret_key))
+}
+
+/// Generate a signing keypair deterministically from a 32 byte seed
 ///
 /// @param {SecBuf} seed - the seed to derive a keypair from
 ///
-/// @UseReturn {SecBuf} - { publicKey, privateKey }
+/// @UseReturn {(SecBuf, SecBuf)} - (publicKey, secretKey) both in NoAccess state
+pub fn keypair_from_seed(seed: &mut SecBuf) -> Result<(SecBuf, SecBuf), SodiumError> {
+    check_len("seed", seed.len(), SEEDBYTES)?;
+    let mut public_key = SecBuf::with_insecure(PUBLICKEYBYTES);
+    let mut secret_key = SecBuf::with_secure(SECRETKEYBYTES);
+    seed_keypair(seed, &mut public_key, &mut secret_key)?;
+    Ok((public_key, secret_key))
+}
+
+/// Generate a signing keypair from a seed buffer
+///
+/// @param {SecBuf} seed - the SEEDBYTES seed to derive a keypair from
+///
+/// @param {SecBuf} publicKey - Empty PUBLICKEYBYTES Buffer to be used as publicKey return
+///
+/// @param {SecBuf} privateKey - Empty SECRETKEYBYTES Buffer, backed by secure memory,
+/// to be used as secretKey return
 pub fn seed_keypair(
+    seed: &mut SecBuf,
     public_key: &mut SecBuf,
     secret_key: &mut SecBuf,
-    seed: &mut SecBuf,
 ) -> Result<(), SodiumError> {
     check_init();
+    check_len("seed", seed.len(), SEEDBYTES)?;
+    check_len("public key", public_key.len(), PUBLICKEYBYTES)?;
+    check_len("secret key", secret_key.len(), SECRETKEYBYTES)?;
+    if !secret_key.is_secure() {
+        return Err(SodiumError::new(
+            "secret key must be backed by secure memory",
+        ));
+    }
     let seed = seed.read_lock();
     let mut secret_key = secret_key.write_lock();
     let mut public_key = public_key.write_lock();
@@ -33,13 +82,24 @@ pub fn seed_keypair(
     Ok(())
 }
 
+/// SizeMismatch unless `len` is `expected`
+fn check_len(what: &str, len: usize, expected: usize) -> Result<(), SodiumError> {
+    if len != expected {
+        return Err(SodiumError::SizeMismatch(format!(
+            "{} must be a Buffer of length: {}, got {}.",
+            what, expected, len
+        )));
+    }
+    Ok(())
+}
+
 /// generate a signature
 ///
 /// @param {Buffer} message - the message to sign
 ///
-/// @param {SecBuf} secretKey - the secret key to sign with
+/// @param {SecBuf} secretKey - the SECRETKEYBYTES secret key to sign with
 ///
-/// @param {SecBuf} signature - Empty Buffer to be used as signature return
+/// @param {SecBuf} signature - Empty BYTES Buffer to be used as signature return
 ///
 /// @UseReturn {SecBuf} {signature}
 pub fn sign(
@@ -48,6 +108,8 @@ pub fn sign(
     signature: &mut SecBuf,
 ) -> Result<(), SodiumError> {
     check_init();
+    check_len("secret key", secret_key.len(), SECRETKEYBYTES)?;
+    check_len("signature", signature.len(), BYTES)?;
     let message = message.read_lock();
     let secret_key = secret_key.read_lock();
     let mut signature = signature.write_lock();
@@ -66,31 +128,419 @@ pub fn sign(
 
 /// verify a signature given the message and a publicKey
 ///
-/// @param {Buffer} signature
+/// @param {Buffer} signature - BYTES long
 ///
 /// @param {Buffer} message
 ///
-/// @param {Buffer} publicKey
-pub fn verify(signature: &mut SecBuf, message: &mut SecBuf, public_key: &mut SecBuf) -> i32 {
+/// @param {Buffer} publicKey - PUBLICKEYBYTES long
+///
+/// @UseReturn {bool} - false for a bad signature, Err only for bad buffer sizes
+pub fn verify(
+    signature: &mut SecBuf,
+    message: &mut SecBuf,
+    public_key: &mut SecBuf,
+) -> Result<bool, SodiumError> {
     check_init();
+    check_len("signature", signature.len(), BYTES)?;
+    check_len("public key", public_key.len(), PUBLICKEYBYTES)?;
     let signature = signature.read_lock();
     let message = message.read_lock();
     let public_key = public_key.read_lock();
     let mess_len = message.len() as libc::c_ulonglong;
-    unsafe {
-        return rust_sodium_sys::crypto_sign_verify_detached(
+    let res = unsafe {
+        rust_sodium_sys::crypto_sign_verify_detached(
             raw_ptr_char_immut!(signature),
             raw_ptr_char_immut!(message),
             mess_len,
             raw_ptr_char_immut!(public_key),
-        );
+        )
+    };
+    Ok(res == 0)
+}
+
+/// one signature to check with `verify_batch`
+pub struct VerifyItem<'a> {
+    pub signature: &'a mut SecBuf,
+    pub message: &'a mut SecBuf,
+    pub public_key: &'a mut SecBuf,
+}
+
+/// verify many signatures at once, taking every read lock up front
+/// (with the `parallel` feature the checks are spread over the rayon thread pool)
+///
+/// @UseReturn {Vec<bool>} - one result per item, in order;
+/// Err only if some signature or public key has the wrong size
+pub fn verify_batch(items: &mut [VerifyItem]) -> Result<Vec<bool>, SodiumError> {
+    check_init();
+    for item in items.iter() {
+        check_len("signature", item.signature.len(), BYTES)?;
+        check_len("public key", item.public_key.len(), PUBLICKEYBYTES)?;
+    }
+    let locked: Vec<(Locker, Locker, Locker)> = items
+        .iter_mut()
+        .map(|item| {
+            (
+                item.signature.read_lock(),
+                item.message.read_lock(),
+                item.public_key.read_lock(),
+            )
+        })
+        .collect();
+    let slices: Vec<(&[u8], &[u8], &[u8])> = locked
+        .iter()
+        .map(|(s, m, p)| (&s[..], &m[..], &p[..]))
+        .collect();
+    Ok(verify_slices(&slices))
+}
+
+/// `verify_batch` for owned (message, signature, public key) tuples,
+/// e.g. entries collected from the network
+///
+/// @UseReturn {Vec<bool>} - one result per item, in order;
+/// Err only if some signature or public key has the wrong size
+pub fn verify_batch_owned(
+    items: &mut [(Vec<u8>, SecBuf, SecBuf)],
+) -> Result<Vec<bool>, SodiumError> {
+    check_init();
+    for (_, signature, public_key) in items.iter() {
+        check_len("signature", signature.len(), BYTES)?;
+        check_len("public key", public_key.len(), PUBLICKEYBYTES)?;
     }
+    let locked: Vec<(&[u8], Locker, Locker)> = items
+        .iter_mut()
+        .map(|(message, signature, public_key)| {
+            (&message[..], signature.read_lock(), public_key.read_lock())
+        })
+        .collect();
+    let slices: Vec<(&[u8], &[u8], &[u8])> = locked
+        .iter()
+        .map(|(m, s, p)| (&s[..], *m, &p[..]))
+        .collect();
+    Ok(verify_slices(&slices))
 }
 
+/// the sizes have already been checked
+fn verify_one(signature: &[u8], message: &[u8], public_key: &[u8]) -> bool {
+    unsafe {
+        rust_sodium_sys::crypto_sign_verify_detached(
+            raw_ptr_char_immut!(signature),
+            raw_ptr_char_immut!(message),
+            message.len() as libc::c_ulonglong,
+            raw_ptr_char_immut!(public_key),
+        ) == 0
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+fn verify_slices(slices: &[(&[u8], &[u8], &[u8])]) -> Vec<bool> {
+    slices.iter().map(|(s, m, p)| verify_one(s, m, p)).collect()
+}
+
+#[cfg(feature = "parallel")]
+fn verify_slices(slices: &[(&[u8], &[u8], &[u8])]) -> Vec<bool> {
+    use rayon::prelude::*;
+    slices
+        .par_iter()
+        .map(|(s, m, p)| verify_one(s, m, p))
+        .collect()
+}
+
+/// convert an ed25519 public key into its curve25519 (X25519) form
+///
+/// @param {SecBuf} edPk - PUBLICKEYBYTES ed25519 public key
+///
+/// @param {SecBuf} curvePk - Empty CURVE25519BYTES Buffer for the result
+pub fn pk_to_curve25519(ed_pk: &mut SecBuf, curve_pk: &mut SecBuf) -> Result<(), SodiumError> {
+    check_init();
+    check_len("ed25519 public key", ed_pk.len(), PUBLICKEYBYTES)?;
+    check_len("curve25519 public key", curve_pk.len(), CURVE25519BYTES)?;
+    let ed_pk = ed_pk.read_lock();
+    let mut curve_pk = curve_pk.write_lock();
+    let res = unsafe {
+        rust_sodium_sys::crypto_sign_ed25519_pk_to_curve25519(
+            raw_ptr_char!(curve_pk),
+            raw_ptr_char_immut!(ed_pk),
+        )
+    };
+    // fails for keys that are not valid points
+    if res != 0 {
+        return Err(SodiumError::Libsodium {
+            function: "crypto_sign_ed25519_pk_to_curve25519",
+            code: res,
+        });
+    }
+    Ok(())
+}
+
+/// convert an ed25519 secret key into its curve25519 (X25519) form
+///
+/// @param {SecBuf} edSk - SECRETKEYBYTES ed25519 secret key
+///
+/// @param {SecBuf} curveSk - Empty CURVE25519BYTES Buffer, backed by secure memory,
+/// for the result
+pub fn sk_to_curve25519(ed_sk: &mut SecBuf, curve_sk: &mut SecBuf) -> Result<(), SodiumError> {
+    check_init();
+    check_len("ed25519 secret key", ed_sk.len(), SECRETKEYBYTES)?;
+    check_len("curve25519 secret key", curve_sk.len(), CURVE25519BYTES)?;
+    if !curve_sk.is_secure() {
+        return Err(SodiumError::new(
+            "curve25519 secret key must be backed by secure memory",
+        ));
+    }
+    let ed_sk = ed_sk.read_lock();
+    let mut curve_sk = curve_sk.write_lock();
+    let res = unsafe {
+        rust_sodium_sys::crypto_sign_ed25519_sk_to_curve25519(
+            raw_ptr_char!(curve_sk),
+            raw_ptr_char_immut!(ed_sk),
+        )
+    };
+    if res != 0 {
+        return Err(SodiumError::Libsodium {
+            function: "crypto_sign_ed25519_sk_to_curve25519",
+            code: res,
+        });
+    }
+    Ok(())
+}
+
+/// Streaming signatures, for messages too large to hold in one buffer
+///
+/// this is Ed25519ph (the message is prehashed with sha512), so its signatures
+/// only verify with a Verifier, not with `verify`, and vice versa
+pub struct Signer {
+    state: rust_sodium_sys::crypto_sign_state,
+}
+
+impl Signer {
+    pub fn new() -> Self {
+        check_init();
+        let mut signer = Signer {
+            state: Default::default(),
+        };
+        unsafe {
+            rust_sodium_sys::crypto_sign_init(&mut signer.state);
+        }
+        signer
+    }
+
+    /// add the next chunk of the message
+    /// a read locked SecBuf derefs to `&[u8]`, so it can be passed directly
+    pub fn update(&mut self, chunk: &[u8]) {
+        unsafe {
+            rust_sodium_sys::crypto_sign_update(
+                &mut self.state,
+                raw_ptr_char_immut!(chunk),
+                chunk.len() as libc::c_ulonglong,
+            );
+        }
+    }
+
+    /// sign everything passed to `update`
+    ///
+    /// @param {SecBuf} secretKey - the SECRETKEYBYTES secret key to sign with
+    pub fn finalize(mut self, secret_key: &mut SecBuf) -> Result<[u8; BYTES], SodiumError> {
+        check_len("secret key", secret_key.len(), SECRETKEYBYTES)?;
+        let mut signature = [0u8; BYTES];
+        let secret_key = secret_key.read_lock();
+        unsafe {
+            rust_sodium_sys::crypto_sign_final_create(
+                &mut self.state,
+                raw_ptr_char!(signature),
+                std::ptr::null_mut(),
+                raw_ptr_char_immut!(secret_key),
+            );
+        }
+        Ok(signature)
+    }
+}
+
+impl Default for Signer {
+    fn default() -> Self {
+        Signer::new()
+    }
+}
+
+/// Verifies signatures made by a Signer, see the note there on Ed25519ph
+pub struct Verifier {
+    state: rust_sodium_sys::crypto_sign_state,
+}
+
+impl Verifier {
+    pub fn new() -> Self {
+        check_init();
+        let mut verifier = Verifier {
+            state: Default::default(),
+        };
+        unsafe {
+            rust_sodium_sys::crypto_sign_init(&mut verifier.state);
+        }
+        verifier
+    }
+
+    /// add the next chunk of the message
+    /// a read locked SecBuf derefs to `&[u8]`, so it can be passed directly
+    pub fn update(&mut self, chunk: &[u8]) {
+        unsafe {
+            rust_sodium_sys::crypto_sign_update(
+                &mut self.state,
+                raw_ptr_char_immut!(chunk),
+                chunk.len() as libc::c_ulonglong,
+            );
+        }
+    }
+
+    /// check `signature` over everything passed to `update`
+    ///
+    /// @param {Buffer} publicKey - PUBLICKEYBYTES long
+    ///
+    /// @UseReturn {bool} - false for a bad signature, Err only for a bad key size
+    pub fn finalize(
+        mut self,
+        signature: &[u8; BYTES],
+        public_key: &mut SecBuf,
+    ) -> Result<bool, SodiumError> {
+        check_len("public key", public_key.len(), PUBLICKEYBYTES)?;
+        // the libsodium signature takes a mutable pointer, though it only reads
+        let mut signature = *signature;
+        let public_key = public_key.read_lock();
+        let res = unsafe {
+            rust_sodium_sys::crypto_sign_final_verify(
+                &mut self.state,
+                raw_ptr_char!(signature),
+                raw_ptr_char_immut!(public_key),
+            )
+        };
+        Ok(res == 0)
+    }
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Verifier::new()
+    }
+}
+
+impl SecBuf {
+    /// generate a fresh Ed25519 keypair, see `keypair`
+    ///
+    /// @UseReturn {(SecBuf, SecBuf)} - (publicKey insecure, secretKey secure) both NoAccess
+    pub fn generate_ed25519_keypair() -> Result<(SecBuf, SecBuf), SodiumError> {
+        keypair()
+    }
+
+    /// generate a detached Ed25519 signature over the contents of this SecBuf
+    ///
+    /// @param {SecBuf} sk - the secret key, SECRETKEYBYTES of secure memory
+    ///
+    /// @UseReturn {[u8; BYTES]} - the signature, Libsodium if signing fails
+    pub fn sign(&mut self, sk: &mut SecBuf) -> Result<[u8; BYTES], SodiumError> {
+        check_init();
+        if sk.len() != SECRETKEYBYTES {
+            return Err(SodiumError::SizeMismatch(format!(
+                "secret key must be {} bytes, got {}",
+                SECRETKEYBYTES,
+                sk.len()
+            )));
+        }
+        if !sk.is_secure() {
+            return Err(SodiumError::new(
+                "secret key must be backed by secure memory",
+            ));
+        }
+        let mut signature = [0u8; BYTES];
+        let message = self.read_lock();
+        let sk = sk.read_lock();
+        let res = unsafe {
+            rust_sodium_sys::crypto_sign_detached(
+                raw_ptr_char!(signature),
+                std::ptr::null_mut(),
+                raw_ptr_char_immut!(message),
+                message.len() as libc::c_ulonglong,
+                raw_ptr_char_immut!(sk),
+            )
+        };
+        if res != 0 {
+            return Err(SodiumError::Libsodium {
+                function: "crypto_sign_detached",
+                code: res,
+            });
+        }
+        Ok(signature)
+    }
+
+    /// check a detached Ed25519 signature produced by `SecBuf::sign`
+    ///
+    /// @param {SecBuf} pk - the public key, PUBLICKEYBYTES long
+    ///
+    /// @UseReturn {bool} - true if the signature is valid
+    pub fn verify(msg: &[u8], sig: &[u8; BYTES], pk: &mut SecBuf) -> Result<bool, SodiumError> {
+        check_init();
+        if pk.len() != PUBLICKEYBYTES {
+            return Err(SodiumError::SizeMismatch(format!(
+                "public key must be {} bytes, got {}",
+                PUBLICKEYBYTES,
+                pk.len()
+            )));
+        }
+        let pk = pk.read_lock();
+        let res = unsafe {
+            rust_sodium_sys::crypto_sign_verify_detached(
+                raw_ptr_char_immut!(sig),
+                raw_ptr_char_immut!(msg),
+                msg.len() as libc::c_ulonglong,
+                raw_ptr_char_immut!(pk),
+            )
+        };
+        Ok(res == 0)
+    }
+
+    /// check that `pk` and `sk` belong to the same Ed25519 keypair,
+    /// by signing a fixed message with `sk` and verifying it with `pk`
+    ///
+    /// @param {SecBuf} pk - the public key, PUBLICKEYBYTES long
+    ///
+    /// @param {SecBuf} sk - the secret key, SECRETKEYBYTES of secure memory
+    ///
+    /// @UseReturn {bool} - false for a mismatched pair, Err only for bad buffers
+    pub fn verify_ed25519_keypair(pk: &mut SecBuf, sk: &mut SecBuf) -> Result<bool, SodiumError> {
+        let mut message = SecBuf::with_insecure(KEYPAIR_CHECK_MESSAGE.len());
+        message.write(0, KEYPAIR_CHECK_MESSAGE)?;
+        let signature = message.sign(sk)?;
+        SecBuf::verify(KEYPAIR_CHECK_MESSAGE, &signature, pk)
+    }
+}
+
+/// the (public) message `SecBuf::verify_ed25519_keypair` signs
+const KEYPAIR_CHECK_MESSAGE: &[u8] = b"keypair_verification_test";
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::random::random_secbuf;
+    use crate::{random::random_secbuf, secbuf::ProtectState};
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn secbuf_from(bytes: &[u8], secure: bool) -> SecBuf {
+        let mut b = if secure {
+            SecBuf::with_secure(bytes.len())
+        } else {
+            SecBuf::with_insecure(bytes.len())
+        };
+        b.write(0, bytes).unwrap();
+        b
+    }
+
+    /// the (public, secret) keypair for an RFC 8032 seed
+    fn keys_from_seed(seed: &str) -> (SecBuf, SecBuf) {
+        let mut seed = secbuf_from(&hex(seed), true);
+        keypair_from_seed(&mut seed).unwrap()
+    }
     #[test]
     fn it_should_get_true_on_good_verify() {
         let mut seed = SecBuf::with_secure(32);
@@ -100,16 +550,39 @@ mod tests {
 
         random_secbuf(&mut seed);
 
-        seed_keypair(&mut public_key, &mut secret_key, &mut seed).unwrap();
+        seed_keypair(&mut seed, &mut public_key, &mut secret_key).unwrap();
 
         let mut message = SecBuf::with_insecure(32);
         random_secbuf(&mut message);
 
         sign(&mut message, &mut secret_key, &mut signature).unwrap();
-        {
-            let ver = verify(&mut signature, &mut message, &mut public_key);
-            assert_eq!(0, ver);
-        }
+        assert!(verify(&mut signature, &mut message, &mut public_key).unwrap());
+    }
+
+    #[test]
+    fn it_should_sign_with_generated_keypair() {
+        let (mut public_key, mut secret_key) = keypair().unwrap();
+        assert_eq!(PUBLICKEYBYTES, public_key.len());
+        assert_eq!(SECRETKEYBYTES, secret_key.len());
+
+        let mut message = SecBuf::with_insecure(32);
+        random_secbuf(&mut message);
+        let mut signature = SecBuf::with_insecure(64);
+
+        sign(&mut message, &mut secret_key, &mut signature).unwrap();
+        assert!(verify(&mut signature, &mut message, &mut public_key).unwrap());
+    }
+
+    #[test]
+    fn it_should_generate_same_keypair_from_seed() {
+        let mut seed = SecBuf::with_secure(SEEDBYTES);
+        random_secbuf(&mut seed);
+
+        let (mut pk1, _) = keypair_from_seed(&mut seed).unwrap();
+        let (mut pk2, _) = keypair_from_seed(&mut seed).unwrap();
+        let pk1 = pk1.read_lock();
+        let pk2 = pk2.read_lock();
+        assert_eq!(pk1.debug_unredacted(), pk2.debug_unredacted());
     }
 
     #[test]
@@ -121,7 +594,7 @@ mod tests {
 
         random_secbuf(&mut seed);
 
-        seed_keypair(&mut public_key, &mut secret_key, &mut seed).unwrap();
+        seed_keypair(&mut seed, &mut public_key, &mut secret_key).unwrap();
 
         let mut message = SecBuf::with_insecure(32);
         random_secbuf(&mut message);
@@ -131,9 +604,399 @@ mod tests {
 
         sign(&mut message, &mut secret_key, &mut signature).unwrap();
 
+        assert!(!verify(&mut signature, &mut fake_message, &mut public_key).unwrap());
+    }
+
+    #[test]
+    fn it_should_sign_and_verify_with_secbuf_methods() {
+        let (mut pk, mut sk) = keypair().unwrap();
+        let mut message = SecBuf::with_insecure(48);
+        random_secbuf(&mut message);
+
+        let mut sig = message.sign(&mut sk).unwrap();
+        let msg = message.read_lock().to_vec();
+        assert!(SecBuf::verify(&msg, &sig, &mut pk).unwrap());
+
+        sig[10] ^= 0x04;
+        assert!(!SecBuf::verify(&msg, &sig, &mut pk).unwrap());
+    }
+
+    #[test]
+    fn it_should_reject_bad_sign_key_sizes() {
+        let (mut pk, _) = keypair().unwrap();
+        let mut message = SecBuf::with_insecure(8);
+
+        let mut short_sk = SecBuf::with_secure(32);
+        match message.sign(&mut short_sk) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+        let mut insecure_sk = SecBuf::with_insecure(SECRETKEYBYTES);
+        assert!(message.sign(&mut insecure_sk).is_err());
+
+        let mut long_pk = SecBuf::with_insecure(40);
+        match SecBuf::verify(b"message", &[0u8; BYTES], &mut long_pk) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+        assert!(!SecBuf::verify(b"message", &[0u8; BYTES], &mut pk).unwrap());
+    }
+
+    #[test]
+    fn it_should_match_rfc8032_seed_vector() {
+        let seed_bytes = hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let pk_bytes = hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+
+        let mut seed = SecBuf::with_secure(SEEDBYTES);
+        seed.write(0, &seed_bytes).unwrap();
+        let mut public_key = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut secret_key = SecBuf::with_secure(SECRETKEYBYTES);
+        seed_keypair(&mut seed, &mut public_key, &mut secret_key).unwrap();
+
+        assert_eq!(ProtectState::NoAccess, secret_key.protect_state());
+        assert_eq!(ProtectState::NoAccess, public_key.protect_state());
+        let public_key = public_key.read_lock();
+        let secret_key = secret_key.read_lock();
+        assert_eq!(format!("{:?}", pk_bytes), public_key.debug_unredacted());
+        // the secret key is the seed followed by the public key
+        assert_eq!(
+            format!("{:?}", seed_bytes),
+            format!("{:?}", &secret_key[..32])
+        );
+        assert_eq!(
+            format!("{:?}", pk_bytes),
+            format!("{:?}", &secret_key[32..])
+        );
+    }
+
+    #[test]
+    fn it_should_reject_bad_seed_keypair_buffers() {
+        let mut seed = SecBuf::with_secure(SEEDBYTES);
+        let mut public_key = SecBuf::with_insecure(PUBLICKEYBYTES);
+        let mut insecure_sk = SecBuf::with_insecure(SECRETKEYBYTES);
+        assert!(seed_keypair(&mut seed, &mut public_key, &mut insecure_sk).is_err());
+
+        let mut secret_key = SecBuf::with_secure(SECRETKEYBYTES);
+        let mut short_seed = SecBuf::with_secure(16);
+        match seed_keypair(&mut short_seed, &mut public_key, &mut secret_key) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+        let mut long_pk = SecBuf::with_insecure(40);
+        assert!(seed_keypair(&mut seed, &mut long_pk, &mut secret_key).is_err());
+        match keypair_from_seed(&mut short_seed) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+    }
+
+    #[test]
+    fn it_should_match_rfc8032_signatures() {
+        let vectors = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+            (
+                "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+                "af82",
+                "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+            ),
+        ];
+        for (seed, message, expected) in vectors.iter() {
+            let (mut public_key, mut secret_key) = keys_from_seed(seed);
+            let mut message = secbuf_from(&hex(message), false);
+            let mut signature = SecBuf::with_insecure(BYTES);
+            sign(&mut message, &mut secret_key, &mut signature).unwrap();
+            {
+                let signature = signature.read_lock();
+                assert_eq!(format!("{:?}", hex(expected)), signature.debug_unredacted());
+            }
+            assert!(verify(&mut signature, &mut message, &mut public_key).unwrap());
+
+            {
+                let mut signature = signature.write_lock();
+                signature[63] ^= 0x01;
+            }
+            assert!(!verify(&mut signature, &mut message, &mut public_key).unwrap());
+        }
+    }
+
+    #[test]
+    fn it_should_reject_bad_signature_sizes() {
+        let (mut public_key, mut secret_key) = keypair().unwrap();
+        let mut message = SecBuf::with_insecure(8);
+        let mut short = SecBuf::with_insecure(32);
+        match sign(&mut message, &mut secret_key, &mut short) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+        match verify(&mut short, &mut message, &mut public_key) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+    }
+
+    #[test]
+    fn it_should_match_rfc8032_ed25519ph_vector() {
+        let (mut public_key, mut secret_key) =
+            keys_from_seed("833fe62409237b9d62ec77587520911e9a759cec1d19755b7da901b96dca3d42");
+        let mut signer = Signer::new();
+        signer.update(b"abc");
+        let signature = signer.finalize(&mut secret_key).unwrap();
+        assert_eq!(
+            format!("{:?}", hex("98a70222f0b8121aa9d30f813d683f809e462b469c7ff87639499bb94e6dae4131f85042463c2a355a2003d062adf5aaa10b8c61e636062aaad11c2a26083406")),
+            format!("{:?}", &signature[..])
+        );
+
+        let mut verifier = Verifier::new();
+        verifier.update(b"abc");
+        assert!(verifier.finalize(&signature, &mut public_key).unwrap());
+    }
+
+    #[test]
+    fn it_should_stream_like_one_shot() {
+        let (mut public_key, mut secret_key) = keypair().unwrap();
+        let mut message = SecBuf::with_insecure(10_000);
+        random_secbuf(&mut message);
+
+        let mut whole = Signer::new();
+        let mut chunked = Signer::new();
+        {
+            let message = message.read_lock();
+            whole.update(&message);
+            for chunk in message.chunks(999) {
+                chunked.update(chunk);
+            }
+        }
+        let whole = whole.finalize(&mut secret_key).unwrap();
+        let chunked = chunked.finalize(&mut secret_key).unwrap();
+        assert_eq!(&whole[..], &chunked[..]);
+
+        let mut verifier = Verifier::new();
         {
-            let ver = verify(&mut signature, &mut fake_message, &mut public_key);
-            assert_eq!(-1, ver);
+            let message = message.read_lock();
+            for chunk in message.chunks(4096) {
+                verifier.update(chunk);
+            }
+        }
+        assert!(verifier.finalize(&chunked, &mut public_key).unwrap());
+
+        let mut verifier = Verifier::new();
+        verifier.update(b"some other message");
+        assert!(!verifier.finalize(&chunked, &mut public_key).unwrap());
+    }
+
+    #[test]
+    fn it_should_generate_ed25519_keypair() {
+        let (mut pk, mut sk) = SecBuf::generate_ed25519_keypair().unwrap();
+        assert_eq!(PUBLICKEYBYTES, pk.len());
+        assert_eq!(SECRETKEYBYTES, sk.len());
+        assert!(!pk.is_secure());
+        assert!(sk.is_secure());
+        assert_eq!(ProtectState::NoAccess, pk.protect_state());
+        assert_eq!(ProtectState::NoAccess, sk.protect_state());
+
+        let mut message = SecBuf::with_insecure(32);
+        random_secbuf(&mut message);
+        let sig = message.sign(&mut sk).unwrap();
+        let message = message.read_lock();
+        assert!(SecBuf::verify(&message, &sig, &mut pk).unwrap());
+    }
+
+    /// the curve25519 (public, secret) keys of a fresh seeded ed25519 keypair
+    fn curve_keys() -> (SecBuf, SecBuf) {
+        let mut seed = SecBuf::with_secure(SEEDBYTES);
+        random_secbuf(&mut seed);
+        let (mut ed_pk, mut ed_sk) = keypair_from_seed(&mut seed).unwrap();
+        let mut curve_pk = SecBuf::with_insecure(CURVE25519BYTES);
+        let mut curve_sk = SecBuf::with_secure(CURVE25519BYTES);
+        pk_to_curve25519(&mut ed_pk, &mut curve_pk).unwrap();
+        sk_to_curve25519(&mut ed_sk, &mut curve_sk).unwrap();
+        (curve_pk, curve_sk)
+    }
+
+    #[test]
+    fn it_should_box_with_converted_keys() {
+        let (mut alice_pk, mut alice_sk) = curve_keys();
+        let (mut bob_pk, mut bob_sk) = curve_keys();
+        let message = b"converted keys can box";
+        let nonce = [9u8; rust_sodium_sys::crypto_box_NONCEBYTES as usize];
+        let mac_len = rust_sodium_sys::crypto_box_MACBYTES as usize;
+
+        let mut cipher = vec![0u8; message.len() + mac_len];
+        {
+            let bob_pk = bob_pk.read_lock();
+            let alice_sk = alice_sk.read_lock();
+            let res = unsafe {
+                rust_sodium_sys::crypto_box_easy(
+                    raw_ptr_char!(cipher),
+                    raw_ptr_char_immut!(message),
+                    message.len() as libc::c_ulonglong,
+                    raw_ptr_char_immut!(nonce),
+                    raw_ptr_char_immut!(bob_pk),
+                    raw_ptr_char_immut!(alice_sk),
+                )
+            };
+            assert_eq!(0, res);
+        }
+
+        let mut opened = vec![0u8; message.len()];
+        let alice_pk = alice_pk.read_lock();
+        let bob_sk = bob_sk.read_lock();
+        let res = unsafe {
+            rust_sodium_sys::crypto_box_open_easy(
+                raw_ptr_char!(opened),
+                raw_ptr_char_immut!(cipher),
+                cipher.len() as libc::c_ulonglong,
+                raw_ptr_char_immut!(nonce),
+                raw_ptr_char_immut!(alice_pk),
+                raw_ptr_char_immut!(bob_sk),
+            )
+        };
+        assert_eq!(0, res);
+        assert_eq!(&message[..], &opened[..]);
+    }
+
+    #[test]
+    fn it_should_reject_bad_curve25519_conversions() {
+        let (mut ed_pk, mut ed_sk) = keypair().unwrap();
+        let mut insecure_sk = SecBuf::with_insecure(CURVE25519BYTES);
+        assert!(sk_to_curve25519(&mut ed_sk, &mut insecure_sk).is_err());
+
+        let mut short = SecBuf::with_insecure(16);
+        match pk_to_curve25519(&mut ed_pk, &mut short) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+
+        // not a point on the curve
+        let mut bad_pk = SecBuf::with_insecure(PUBLICKEYBYTES);
+        bad_pk.write(0, &[2u8; PUBLICKEYBYTES]).unwrap();
+        let mut curve_pk = SecBuf::with_insecure(CURVE25519BYTES);
+        match pk_to_curve25519(&mut bad_pk, &mut curve_pk) {
+            Err(SodiumError::Libsodium { function, .. }) => {
+                assert_eq!("crypto_sign_ed25519_pk_to_curve25519", function)
+            }
+            _ => panic!("expected a libsodium error"),
+        }
+    }
+
+    #[test]
+    fn it_should_verify_batch_with_one_bad_item() {
+        let count = 20;
+        let mut public_keys = Vec::new();
+        let mut messages = Vec::new();
+        let mut signatures = Vec::new();
+        for _ in 0..count {
+            let (pk, mut sk) = keypair().unwrap();
+            let mut message = SecBuf::with_insecure(64);
+            random_secbuf(&mut message);
+            let mut signature = SecBuf::with_insecure(BYTES);
+            sign(&mut message, &mut sk, &mut signature).unwrap();
+            public_keys.push(pk);
+            messages.push(message);
+            signatures.push(signature);
+        }
+        {
+            let mut bad = messages[7].write_lock();
+            bad[0] ^= 0x01;
+        }
+
+        let mut items: Vec<VerifyItem> = signatures
+            .iter_mut()
+            .zip(messages.iter_mut())
+            .zip(public_keys.iter_mut())
+            .map(|((signature, message), public_key)| VerifyItem {
+                signature,
+                message,
+                public_key,
+            })
+            .collect();
+        let results = verify_batch(&mut items).unwrap();
+        drop(items);
+
+        assert_eq!(count, results.len());
+        for (i, ok) in results.iter().enumerate() {
+            assert_eq!(i != 7, *ok);
+        }
+        assert_eq!(ProtectState::NoAccess, messages[0].protect_state());
+        assert!(verify_batch(&mut []).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_should_reject_batch_with_bad_sizes() {
+        let (mut public_key, _) = keypair().unwrap();
+        let mut message = SecBuf::with_insecure(8);
+        let mut signature = SecBuf::with_insecure(32);
+        let mut items = [VerifyItem {
+            signature: &mut signature,
+            message: &mut message,
+            public_key: &mut public_key,
+        }];
+        match verify_batch(&mut items) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
+        }
+    }
+
+    #[test]
+    fn it_should_verify_a_matching_keypair() {
+        let (mut pk, mut sk) = SecBuf::generate_ed25519_keypair().unwrap();
+        assert!(SecBuf::verify_ed25519_keypair(&mut pk, &mut sk).unwrap());
+        assert_eq!(ProtectState::NoAccess, pk.protect_state());
+        assert_eq!(ProtectState::NoAccess, sk.protect_state());
+    }
+
+    #[test]
+    fn it_should_reject_mismatched_keypairs() {
+        let (mut pk1, mut sk1) = SecBuf::generate_ed25519_keypair().unwrap();
+        let (mut pk2, mut sk2) = SecBuf::generate_ed25519_keypair().unwrap();
+        assert!(!SecBuf::verify_ed25519_keypair(&mut pk1, &mut sk2).unwrap());
+        assert!(!SecBuf::verify_ed25519_keypair(&mut pk2, &mut sk1).unwrap());
+
+        let mut zeroed = SecBuf::with_secure(SECRETKEYBYTES);
+        zeroed.fill(0);
+        assert!(!SecBuf::verify_ed25519_keypair(&mut pk1, &mut zeroed).unwrap());
+
+        let mut short = SecBuf::with_insecure(PUBLICKEYBYTES - 1);
+        assert!(SecBuf::verify_ed25519_keypair(&mut short, &mut sk1).is_err());
+    }
+
+    #[test]
+    fn it_should_verify_an_owned_batch() {
+        let mut items = Vec::new();
+        for i in 0..6u8 {
+            let (public_key, mut sk) = keypair().unwrap();
+            let message = vec![i; 32];
+            let mut m = SecBuf::with_insecure(message.len());
+            m.write(0, &message).unwrap();
+            let mut signature = SecBuf::with_insecure(BYTES);
+            sign(&mut m, &mut sk, &mut signature).unwrap();
+            items.push((message, signature, public_key));
+        }
+        items[1].0[0] ^= 0x01;
+        {
+            let mut signature = items[4].1.write_lock();
+            signature[10] ^= 0x01;
+        }
+        let (left, right) = items.split_at_mut(3);
+        std::mem::swap(&mut left[2].2, &mut right[0].2);
+        let results = verify_batch_owned(&mut items).unwrap();
+        assert_eq!(vec![true, false, false, false, false, true], results);
+        assert_eq!(ProtectState::NoAccess, items[0].1.protect_state());
+
+        let (_, _, public_key) = items.pop().unwrap();
+        items.push((vec![], SecBuf::with_insecure(32), public_key));
+        match verify_batch_owned(&mut items) {
+            Err(SodiumError::SizeMismatch(_)) => (),
+            _ => panic!("expected SizeMismatch"),
         }
     }
 }
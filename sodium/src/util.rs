@@ -1,6 +1,7 @@
 //! This module provides access to libsodium utility and memory functions
 
 use super::{check_init, secbuf::SecBuf};
+use crate::error::SodiumError;
 
 /// Zero all memory within the provided SecBuf
 pub fn zero(b: &mut SecBuf) {
@@ -35,6 +36,88 @@ pub fn compare(a: &mut SecBuf, b: &mut SecBuf) -> i32 {
     let mut b = b.write_lock();
     unsafe { rust_sodium_sys::sodium_compare(raw_ptr_char!(a), raw_ptr_char!(b), a.len()) }
 }
+
+/// the length of `unpadded_len` bytes padded (ISO/IEC 7816-4) to a multiple of `blocksize`,
+/// at least one byte of padding is always added
+pub(crate) fn padded_len(unpadded_len: usize, blocksize: usize) -> Result<usize, SodiumError> {
+    if blocksize == 0 {
+        return Err(SodiumError::new("bad padding block size"));
+    }
+    (unpadded_len / blocksize)
+        .checked_add(1)
+        .and_then(|blocks| blocks.checked_mul(blocksize))
+        .ok_or_else(|| SodiumError::OutOfBounds(format!("cannot pad {} bytes", unpadded_len)))
+}
+
+/// Pad the first `unpadded_len` bytes of `buf` to a multiple of `blocksize` (sodium_pad),
+/// so an encrypted message only reveals its padded length
+///
+/// @param {SecBuf} buf - must already be large enough for the padded length
+///
+/// @UseReturn {usize} - the padded length
+pub fn pad(buf: &mut SecBuf, unpadded_len: usize, blocksize: usize) -> Result<usize, SodiumError> {
+    check_init();
+    let max_len = buf.len();
+    let needed = padded_len(unpadded_len, blocksize)?;
+    if needed > max_len {
+        return Err(SodiumError::LengthMismatch(format!(
+            "padding needs a Buffer of at least length: {}, got {}.",
+            needed, max_len
+        )));
+    }
+    let mut b = buf.write_lock();
+    let mut written = 0usize;
+    let res = unsafe {
+        rust_sodium_sys::sodium_pad(
+            &mut written,
+            raw_ptr_char!(b),
+            unpadded_len,
+            blocksize,
+            max_len,
+        )
+    };
+    if res != 0 {
+        return Err(SodiumError::Libsodium {
+            function: "sodium_pad",
+            code: res,
+        });
+    }
+    Ok(written)
+}
+
+/// Find the unpadded length of the first `padded_len` bytes of `buf` (sodium_unpad)
+///
+/// @UseReturn {usize} - the unpadded length, InvalidPadding if the padding is malformed
+pub fn unpad(buf: &mut SecBuf, padded_len: usize, blocksize: usize) -> Result<usize, SodiumError> {
+    check_init();
+    if blocksize == 0 {
+        return Err(SodiumError::new("bad padding block size"));
+    }
+    if padded_len > buf.len() {
+        return Err(SodiumError::OutOfBounds(format!(
+            "padded length {} is past the end of a Buffer of length {}",
+            padded_len,
+            buf.len()
+        )));
+    }
+    let b = buf.read_lock();
+    let mut unpadded_len = 0usize;
+    let res = unsafe {
+        rust_sodium_sys::sodium_unpad(
+            &mut unpadded_len,
+            raw_ptr_char_immut!(b),
+            padded_len,
+            blocksize,
+        )
+    };
+    // sodium_unpad only checks the last block, so a length that is not
+    // a whole number of blocks would otherwise be accepted
+    if res != 0 || padded_len % blocksize != 0 {
+        return Err(SodiumError::InvalidPadding);
+    }
+    Ok(unpadded_len)
+}
+
 /// Check if lenght of buffer is of approprate size
 ///
 /// it should be either or size 8,16,32 or 64
@@ -109,4 +192,56 @@ mod tests {
             assert_eq!(0, val_3);
         }
     }
+
+    #[test]
+    fn it_should_pad_and_unpad() {
+        for blocksize in &[1, 8, 16, 64] {
+            for unpadded_len in &[0, 1, 15, 16, 17, 64] {
+                let mut b = SecBuf::with_insecure(128);
+                b.fill(0xaa);
+                let padded_len = pad(&mut b, *unpadded_len, *blocksize).unwrap();
+                assert_eq!(0, padded_len % blocksize);
+                // an exact multiple still gets a whole block of padding
+                assert!(padded_len > *unpadded_len);
+                assert!(padded_len <= unpadded_len + blocksize);
+                assert_eq!(0x80, b.read_lock()[*unpadded_len]);
+                assert_eq!(
+                    *unpadded_len,
+                    unpad(&mut b, padded_len, *blocksize).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_reject_bad_padding() {
+        let mut b = SecBuf::with_insecure(16);
+        let padded_len = pad(&mut b, 5, 16).unwrap();
+        assert_eq!(16, padded_len);
+        {
+            let mut b = b.write_lock();
+            b[5] = 0x81;
+        }
+        match unpad(&mut b, padded_len, 16) {
+            Err(SodiumError::InvalidPadding) => (),
+            _ => panic!("expected InvalidPadding"),
+        }
+        {
+            let mut b = b.write_lock();
+            b[5] = 0x80;
+            b[10] = 1;
+        }
+        match unpad(&mut b, padded_len, 16) {
+            Err(SodiumError::InvalidPadding) => (),
+            _ => panic!("expected InvalidPadding"),
+        }
+        assert!(unpad(&mut b, 12, 8).is_err());
+
+        match pad(&mut SecBuf::with_insecure(16), 16, 16) {
+            Err(SodiumError::LengthMismatch(_)) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+        assert!(pad(&mut b, 4, 0).is_err());
+        assert!(unpad(&mut b, 32, 16).is_err());
+    }
 }
@@ -0,0 +1,70 @@
+//! the secure memory counters are process wide, so these checks live in
+//! their own test binary where no other tests allocate concurrently
+
+extern crate holochain_sodium;
+#[macro_use]
+extern crate lazy_static;
+
+use holochain_sodium::secbuf::{secure_memory_stats, SecBuf};
+use std::{sync::Mutex, thread};
+
+lazy_static! {
+    /// the tests below must not overlap each other either
+    static ref SERIAL: Mutex<()> = Mutex::new(());
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn it_should_count_secure_buffers() {
+    let _guard = SERIAL.lock().unwrap();
+    let before = secure_memory_stats();
+
+    let mut bufs: Vec<SecBuf> = (1..=4).map(|i| SecBuf::with_secure(i * 16)).collect();
+    let stats = secure_memory_stats();
+    assert_eq!(before.live_buffers + 4, stats.live_buffers);
+    assert_eq!(before.allocated_bytes + 160, stats.allocated_bytes);
+
+    // insecure buffers are not counted
+    let _insecure = SecBuf::with_insecure(64);
+    assert_eq!(stats.live_buffers, secure_memory_stats().live_buffers);
+
+    bufs.truncate(1);
+    let stats = secure_memory_stats();
+    assert_eq!(before.live_buffers + 1, stats.live_buffers);
+    assert_eq!(before.allocated_bytes + 16, stats.allocated_bytes);
+
+    drop(bufs);
+    assert_eq!(before, secure_memory_stats());
+
+    #[cfg(unix)]
+    assert!(before.memlock_limit.is_some());
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn it_should_count_secure_buffers_across_threads() {
+    let _guard = SERIAL.lock().unwrap();
+    let before = secure_memory_stats();
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            thread::spawn(|| {
+                for _ in 0..50 {
+                    let a = SecBuf::with_secure(32);
+                    let b = SecBuf::with_secure(8);
+                    assert!(secure_memory_stats().live_buffers >= 2);
+                    drop((a, b));
+                }
+                SecBuf::with_secure(24)
+            })
+        })
+        .collect();
+    let kept: Vec<SecBuf> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    let stats = secure_memory_stats();
+    assert_eq!(before.live_buffers + 8, stats.live_buffers);
+    assert_eq!(before.allocated_bytes + 8 * 24, stats.allocated_bytes);
+
+    drop(kept);
+    assert_eq!(before, secure_memory_stats());
+}